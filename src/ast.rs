@@ -1,5 +1,10 @@
-use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use chrono::{DateTime, Duration as ChronoDuration, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
+use std::fmt;
 use std::ops::Deref;
+use std::str::FromStr;
+
+use crate::decimal::Decimal;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Timestamp(pub DateTime<Utc>);
@@ -7,6 +12,106 @@ pub struct Timestamp(pub DateTime<Utc>);
 #[derive(Debug, Clone, PartialEq)]
 pub struct Duration(pub ChronoDuration);
 
+const NANOS_PER_SECOND: i128 = 1_000_000_000;
+
+/// Largest first, so printing can take the first unit that divides exactly.
+const DURATION_UNITS: [(&str, i128); 8] = [
+    ("w", 604_800 * NANOS_PER_SECOND),
+    ("d", 86_400 * NANOS_PER_SECOND),
+    ("h", 3600 * NANOS_PER_SECOND),
+    ("m", 60 * NANOS_PER_SECOND),
+    ("s", NANOS_PER_SECOND),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+impl Duration {
+    /// The whole duration in nanoseconds, which never overflows an `i128`.
+    pub fn as_nanos(&self) -> i128 {
+        self.0.num_seconds() as i128 * NANOS_PER_SECOND + self.0.subsec_nanos() as i128
+    }
+
+    /// A duration of `nanos` nanoseconds, or `None` if chrono cannot hold it.
+    pub fn from_nanos(nanos: i128) -> Option<Self> {
+        let seconds = ChronoDuration::try_seconds(i64::try_from(nanos / NANOS_PER_SECOND).ok()?)?;
+        seconds.checked_add(&ChronoDuration::nanoseconds((nanos % NANOS_PER_SECOND) as i64)).map(Duration)
+    }
+}
+
+/// Parses `<n><unit>` with units `ns`, `us`, `ms`, `s`, `m`, `h`, `d` and `w`, e.g. `5m` or `30d`.
+impl FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (unit, nanos) = DURATION_UNITS
+            .iter()
+            .filter(|(unit, _)| s.ends_with(unit))
+            .max_by_key(|(unit, _)| unit.len())
+            .ok_or_else(|| format!("unknown duration unit in `{}`", s))?;
+        let count: i64 = s[..s.len() - unit.len()]
+            .parse()
+            .map_err(|_| format!("bad duration `{}`", s))?;
+        Duration::from_nanos(count as i128 * nanos).ok_or_else(|| format!("duration `{}` is out of range", s))
+    }
+}
+
+/// Uses the largest unit that divides the duration exactly, down to nanoseconds.
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.as_nanos();
+        let (unit, nanos) = DURATION_UNITS
+            .iter()
+            .find(|(_, nanos)| total != 0 && total % nanos == 0)
+            .unwrap_or(&("s", NANOS_PER_SECOND));
+        write!(f, "{}{}", total / nanos, unit)
+    }
+}
+
+/// RFC 3339 in UTC, with as many fractional digits as needed.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+    }
+}
+
+/// Serialized as an RFC 3339 string.
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Serialized as whole seconds.
+impl Serialize for Duration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.0.num_seconds())
+    }
+}
+
+/// What a number measures, when it is more than a plain number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Price,
+    Quantity,
+    Money,
+}
+
+impl Unit {
+    /// The lowercase name used by literals like `#price:101.25`
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::Price => "price",
+            Unit::Quantity => "quantity",
+            Unit::Money => "money",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Unit> {
+        [Unit::Price, Unit::Quantity, Unit::Money].into_iter().find(|unit| unit.name() == name)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Symbol(pub String);
 
@@ -29,4 +134,105 @@ pub enum Expr {
     Duration(Duration),
     Timestamp(Timestamp),
     Integer(i64),
+    Decimal(Decimal),
+    Array(Vec<Expr>),
+    /// A number in a unit, like a bar's close or the account's equity
+    Amount(Unit, Box<Expr>),
+}
+
+impl Expr {
+    /// Name of the variant, as shown by the REPL's `,type`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Expr::Nil => "Nil",
+            Expr::Comment(_) => "Comment",
+            Expr::Combination(..) => "Combination",
+            Expr::Symbol(_) => "Symbol",
+            Expr::Boolean(_) => "Boolean",
+            Expr::Float(_) => "Float",
+            Expr::String(_) => "String",
+            Expr::Duration(_) => "Duration",
+            Expr::Timestamp(_) => "Timestamp",
+            Expr::Integer(_) => "Integer",
+            Expr::Decimal(_) => "Decimal",
+            Expr::Array(_) => "Array",
+            Expr::Amount(Unit::Price, _) => "Price",
+            Expr::Amount(Unit::Quantity, _) => "Quantity",
+            Expr::Amount(Unit::Money, _) => "Money",
+        }
+    }
+}
+
+/// Scheme syntax that reads back as the same value: `parse(x.to_string()) == x`.
+///
+/// Nil is `()`, booleans `#t`/`#f`, timestamps `#2024-01-02T15:30:00Z`, decimals `101.25m`,
+/// amounts `#price:101.25` and non-finite floats `+inf.0`, `-inf.0` and `+nan.0`. Comments print
+/// as `;` lines, which the parser skips rather than reading back.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, open: &str, items: &[&Expr], close: &str) -> fmt::Result {
+            f.write_str(open)?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            f.write_str(close)
+        }
+
+        match self {
+            Expr::Nil => f.write_str("()"),
+            Expr::Comment(text) => writeln!(f, ";{}", text),
+            Expr::Combination(target, args) => {
+                let items: Vec<&Expr> = std::iter::once(target.as_ref()).chain(args).collect();
+                list(f, "(", &items, ")")
+            }
+            Expr::Symbol(symbol) => f.write_str(symbol),
+            Expr::Boolean(b) => f.write_str(if *b { "#t" } else { "#f" }),
+            Expr::Float(x) if x.is_nan() => f.write_str("+nan.0"),
+            Expr::Float(x) if x.is_infinite() => f.write_str(if *x > 0.0 { "+inf.0" } else { "-inf.0" }),
+            // Debug always keeps a `.0` or an exponent, so floats never read back as integers.
+            Expr::Float(x) => write!(f, "{:?}", x),
+            Expr::String(s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Expr::Duration(d) => write!(f, "{}", d),
+            Expr::Timestamp(t) => write!(f, "#{}", t),
+            Expr::Integer(i) => write!(f, "{}", i),
+            Expr::Decimal(d) => write!(f, "{}m", d),
+            Expr::Array(items) => list(f, "[", &items.iter().collect::<Vec<_>>(), "]"),
+            Expr::Amount(unit, value) => write!(f, "#{}:{}", unit.name(), value),
+        }
+    }
+}
+
+/// Atoms become the matching JSON scalar and amounts their number; a combination becomes an array, operator first.
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Expr::Nil | Expr::Comment(_) => serializer.serialize_unit(),
+            Expr::Combination(target, args) => serializer.collect_seq(std::iter::once(target.as_ref()).chain(args)),
+            Expr::Symbol(symbol) => serializer.serialize_str(symbol),
+            Expr::Boolean(b) => serializer.serialize_bool(*b),
+            Expr::Float(f) => serializer.serialize_f64(*f),
+            Expr::String(s) => serializer.serialize_str(s),
+            Expr::Duration(d) => d.serialize(serializer),
+            Expr::Timestamp(t) => t.serialize(serializer),
+            Expr::Integer(i) => serializer.serialize_i64(*i),
+            Expr::Decimal(d) => d.serialize(serializer),
+            Expr::Array(items) => serializer.collect_seq(items),
+            Expr::Amount(_, value) => value.serialize(serializer),
+        }
+    }
 }
@@ -0,0 +1,39 @@
+//! One-call backtests: run a program over bars and summarize the result.
+
+use crate::ast::Expr;
+use crate::bars::Bar;
+use crate::broker::{BrokerConfig, EquityPoint, Trade};
+use crate::engine::BarEngine;
+use crate::interpreter::EvalError;
+use crate::plot::Plots;
+use crate::report::PerformanceReport;
+use indexmap::IndexMap;
+
+/// Everything a finished backtest produced.
+#[derive(Debug, Clone)]
+pub struct BacktestResult {
+    pub report: PerformanceReport,
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+    pub plots: Plots,
+}
+
+/// Run `program` over `bars` with the given input overrides.
+pub fn run(
+    program: &[Expr],
+    bars: &[Bar],
+    config: BrokerConfig,
+    inputs: IndexMap<String, Expr>,
+) -> Result<BacktestResult, EvalError> {
+    let initial_cash = config.initial_cash;
+    let mut engine = BarEngine::new(config).with_inputs(inputs);
+    engine.run(program, bars)?;
+    let trades = engine.broker.trades().to_vec();
+    let equity_curve = engine.broker.equity_curve().to_vec();
+    Ok(BacktestResult {
+        report: PerformanceReport::new(initial_cash, &trades, &equity_curve),
+        trades,
+        equity_curve,
+        plots: engine.interpreter.plots,
+    })
+}
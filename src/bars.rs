@@ -0,0 +1,239 @@
+//! OHLCV bars and a tiny CSV loader for them.
+
+use crate::ast::{Duration, Timestamp};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// One price bar. Prices are plain `f64`s for now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar {
+    pub timestamp: Timestamp,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    /// Volume-weighted average price. A bar read from a file uses its typical
+    /// price, `(high + low + close) / 3`; resampled bars weight those by volume.
+    pub vwap: f64,
+}
+
+impl Bar {
+    pub fn new(timestamp: Timestamp, open: f64, high: f64, low: f64, close: f64, volume: f64) -> Self {
+        let vwap = (high + low + close) / 3.0;
+        Self { timestamp, open, high, low, close, volume, vwap }
+    }
+}
+
+/// A data stream after the first, read by scripts as `(close 0 :data 2)` onwards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataStream {
+    /// The first stream's bars aggregated into bars of this period
+    Resampled(Period),
+    /// Bars loaded on their own, like a daily file alongside minute bars
+    Bars(Arc<[Bar]>),
+}
+
+/// Parses a positive duration like `1h`, to resample the first stream.
+impl std::str::FromStr for DataStream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(DataStream::Resampled(Period::new(s.parse::<Duration>()?.0)?))
+    }
+}
+
+/// A resampling period, positive since [`Period::new`] checks it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period(TimeDelta);
+
+impl Period {
+    pub fn new(period: TimeDelta) -> Result<Self, String> {
+        if period <= TimeDelta::zero() {
+            return Err(format!("cannot resample to a period of {}", Duration(period)));
+        }
+        Ok(Self(period))
+    }
+}
+
+/// Aggregates bars into bars of a fixed period as they arrive.
+///
+/// Periods are counted from Monday 1970-01-05 UTC, so `1d` bars start at
+/// midnight UTC and `1w` bars on Mondays. Each bar is stamped with the start
+/// of its period.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    period: Period,
+    current: Option<Bar>,
+    /// Sum of each input bar's VWAP times its volume, and of the VWAPs alone
+    weighted: f64,
+    unweighted: f64,
+    count: usize,
+}
+
+impl Resampler {
+    pub fn new(period: TimeDelta) -> Result<Self, String> {
+        Ok(Self::with_period(Period::new(period)?))
+    }
+
+    pub fn with_period(period: Period) -> Self {
+        Self { period, current: None, weighted: 0.0, unweighted: 0.0, count: 0 }
+    }
+
+    /// Start of the period containing `time`, counted in nanoseconds so periods under a millisecond work too.
+    pub fn period_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let origin = NaiveDate::from_ymd_opt(1970, 1, 5).expect("valid date").and_time(Default::default()).and_utc();
+        let period = Duration(self.period.0).as_nanos();
+        let elapsed = Duration(time - origin).as_nanos();
+        // A period longer than the calendar's range has one period, starting as early as possible
+        Duration::from_nanos(elapsed.div_euclid(period) * period)
+            .and_then(|offset| origin.checked_add_signed(offset.0))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    /// Add a bar. Returns the previous period's bar once `bar` starts a new one.
+    pub fn push(&mut self, bar: &Bar) -> Option<Bar> {
+        let start = self.period_start(bar.timestamp.0);
+        let finished = match &self.current {
+            Some(current) if current.timestamp.0 != start => self.take(),
+            _ => None,
+        };
+        match &mut self.current {
+            Some(current) => {
+                current.high = current.high.max(bar.high);
+                current.low = current.low.min(bar.low);
+                current.close = bar.close;
+                current.volume += bar.volume;
+            }
+            None => self.current = Some(Bar { timestamp: Timestamp(start), ..bar.clone() }),
+        }
+        self.weighted += bar.vwap * bar.volume;
+        self.unweighted += bar.vwap;
+        self.count += 1;
+        finished
+    }
+
+    /// The bar of the period in progress, which may be incomplete.
+    pub fn finish(mut self) -> Option<Bar> {
+        self.take()
+    }
+
+    fn take(&mut self) -> Option<Bar> {
+        let mut bar = self.current.take()?;
+        bar.vwap = if bar.volume > 0.0 { self.weighted / bar.volume } else { self.unweighted / self.count as f64 };
+        (self.weighted, self.unweighted, self.count) = (0.0, 0.0, 0);
+        Some(bar)
+    }
+}
+
+/// Aggregate `bars` into bars of `period`, including a final incomplete one.
+pub fn resample(bars: &[Bar], period: TimeDelta) -> Result<Vec<Bar>, String> {
+    let mut resampler = Resampler::new(period)?;
+    let mut out: Vec<Bar> = bars.iter().filter_map(|bar| resampler.push(bar)).collect();
+    out.extend(resampler.finish());
+    Ok(out)
+}
+
+#[derive(Debug, Error)]
+pub enum BarError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("line {line}: {msg}")]
+    Parse { line: usize, msg: String },
+}
+
+/// Load bars from a CSV file with columns `timestamp,open,high,low,close[,volume]`.
+///
+/// A header row is skipped if its first field isn't a timestamp.
+pub fn load_csv(path: &Path) -> Result<Vec<Bar>, BarError> {
+    let text = std::fs::read_to_string(path)?;
+    parse_csv(&text)
+}
+
+/// Parse CSV text in the format accepted by [`load_csv`].
+pub fn parse_csv(text: &str) -> Result<Vec<Bar>, BarError> {
+    let mut bars = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = raw.split(',').map(str::trim).collect();
+        let timestamp = match parse_timestamp(fields[0]) {
+            Some(ts) => ts,
+            None if i == 0 => continue,
+            None => return Err(BarError::Parse { line, msg: format!("bad timestamp `{}`", fields[0]) }),
+        };
+        if fields.len() < 5 {
+            return Err(BarError::Parse { line, msg: format!("expected at least 5 columns, found {}", fields.len()) });
+        }
+        let num = |idx: usize| -> Result<f64, BarError> {
+            fields[idx]
+                .parse::<f64>()
+                .map_err(|e| BarError::Parse { line, msg: format!("column {}: {}", idx + 1, e) })
+        };
+        let volume = if fields.len() > 5 { num(5)? } else { 0.0 };
+        bars.push(Bar::new(timestamp, num(1)?, num(2)?, num(3)?, num(4)?, volume));
+    }
+    Ok(bars)
+}
+
+/// Accepts RFC 3339, `YYYY-MM-DD HH:MM:SS`, `YYYY-MM-DDTHH:MM:SS` and `YYYY-MM-DD`, all read as UTC.
+pub fn parse_timestamp(s: &str) -> Option<Timestamp> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(Timestamp(dt.with_timezone(&Utc)));
+    }
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, fmt) {
+            return Some(Timestamp(dt.and_utc()));
+        }
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .map(|d| Timestamp(d.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_csv_with_header() {
+        let bars = parse_csv("date,open,high,low,close,volume\n2024-01-02,10,11,9,10.5,1000\n2024-01-03,10.5,12,10,11,1200\n")
+            .expect("parse");
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[1].high, 12.0);
+        assert_eq!(bars[0].timestamp, parse_timestamp("2024-01-02T00:00:00Z").unwrap());
+    }
+
+    #[test]
+    fn rejects_short_rows() {
+        assert!(parse_csv("2024-01-02,10,11\n").is_err());
+    }
+
+    #[test]
+    fn resamples_into_aligned_periods() {
+        let minutes = parse_csv(
+            "2024-01-02T09:58:00Z,10,11,9,10,100\n2024-01-02T09:59:00Z,10,12,10,11,300\n2024-01-02T10:00:00Z,11,11,8,9,0\n",
+        )
+        .unwrap();
+        let hours = resample(&minutes, TimeDelta::hours(1)).unwrap();
+        assert_eq!(hours.len(), 2);
+        let first = &hours[0];
+        assert_eq!(first.timestamp, parse_timestamp("2024-01-02T09:00:00Z").unwrap());
+        assert_eq!((first.open, first.high, first.low, first.close, first.volume), (10.0, 12.0, 9.0, 11.0, 400.0));
+        assert_eq!(first.vwap, (10.0 * 100.0 + 11.0 * 300.0) / 400.0);
+        assert_eq!(hours[1].vwap, 28.0 / 3.0);
+
+        let week = Resampler::new(TimeDelta::weeks(1)).unwrap();
+        assert_eq!(week.period_start(minutes[0].timestamp.0), parse_timestamp("2024-01-01T00:00:00Z").unwrap().0);
+        assert!(Resampler::new(TimeDelta::zero()).is_err());
+        let micros = Resampler::new(TimeDelta::microseconds(250)).unwrap();
+        let time = parse_timestamp("2024-01-02T09:58:00.000600Z").unwrap().0;
+        assert_eq!(micros.period_start(time), parse_timestamp("2024-01-02T09:58:00.0005Z").unwrap().0);
+    }
+}
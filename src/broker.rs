@@ -0,0 +1,762 @@
+//! Deterministic simulated broker.
+//!
+//! Orders emitted while a bar is evaluated are filled against the *following* bar
+//! (or the current close, depending on [`FillModel`]). Every order lives for one
+//! bar only, like EasyLanguage's `next bar` orders, so a run is a pure function of
+//! the script, the bars and the [`BrokerConfig`].
+//!
+//! Cash, cost basis and commissions are kept as [`Decimal`]s, so a long run of
+//! fills at prices like `101.25` adds up to the cent. Fills, trades and the
+//! equity curve report plain floats.
+//!
+//! [`RiskStops`] are EasyLanguage's built-in exits. Once set they stay in force
+//! for every position, and are checked on every bar after the pending orders fill,
+//! so a position entered at the open can be stopped out on the same bar.
+//!
+//! An order whose fill would take the cash, the position or its cost out of
+//! the range of a [`Decimal`] is rejected and expires like an unfilled one.
+
+use crate::ast::Timestamp;
+use crate::bars::{Bar, DataStream};
+use crate::calendar::Calendar;
+use crate::decimal::Decimal;
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub(crate) fn sign(self) -> i64 {
+        match self {
+            Side::Buy => 1,
+            Side::Sell => -1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum OrderKind {
+    Market,
+    Limit(f64),
+    Stop(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Order {
+    pub side: Side,
+    pub quantity: i64,
+    pub kind: OrderKind,
+}
+
+impl Order {
+    pub fn new(side: Side, quantity: i64, kind: OrderKind) -> Self {
+        Self { side, quantity, kind }
+    }
+}
+
+/// How much each fill costs in fees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Commission {
+    None,
+    PerShare(f64),
+    PerTrade(f64),
+    /// Fraction of notional, e.g. `0.001` for 10 bps.
+    Percent(f64),
+}
+
+impl Commission {
+    /// The fee for a fill, or `None` if it overflows or the rate is not a finite amount.
+    pub fn cost(&self, quantity: i64, price: Decimal) -> Option<Decimal> {
+        match *self {
+            Commission::None => Some(Decimal::ZERO),
+            Commission::PerShare(c) => Decimal::from_f64(c)?.checked_mul(quantity.into()),
+            Commission::PerTrade(c) => Decimal::from_f64(c),
+            Commission::Percent(p) => Decimal::from_f64(p)?.checked_mul(quantity.into())?.checked_mul(price),
+        }
+    }
+
+    fn value(&self) -> Option<f64> {
+        match *self {
+            Commission::None => None,
+            Commission::PerShare(v) | Commission::PerTrade(v) | Commission::Percent(v) => Some(v),
+        }
+    }
+}
+
+/// Parses `none`, `per-share:0.01`, `per-trade:1` or `percent:0.001`.
+impl FromStr for Commission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_model(s)? {
+            ("none", None) => Ok(Commission::None),
+            ("per-share", Some(v)) => Ok(Commission::PerShare(v)),
+            ("per-trade", Some(v)) => Ok(Commission::PerTrade(v)),
+            ("percent", Some(v)) => Ok(Commission::Percent(v)),
+            _ => Err(format!("unknown commission model `{}`", s)),
+        }
+    }
+}
+
+/// Adverse price adjustment applied to market and stop fills. Limit fills never slip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slippage {
+    None,
+    PerShare(f64),
+    /// Fraction of price, e.g. `0.0005` for 5 bps.
+    Percent(f64),
+}
+
+impl Slippage {
+    /// The price after slippage, or `None` if it overflows or the rate is not a finite amount.
+    pub fn apply(&self, side: Side, price: Decimal) -> Option<Decimal> {
+        let amount = match *self {
+            Slippage::None => Decimal::ZERO,
+            Slippage::PerShare(s) => Decimal::from_f64(s)?,
+            Slippage::Percent(p) => Decimal::from_f64(p)?.checked_mul(price)?,
+        };
+        price.checked_add(Decimal::from(side.sign()).checked_mul(amount)?)
+    }
+
+    fn value(&self) -> Option<f64> {
+        match *self {
+            Slippage::None => None,
+            Slippage::PerShare(v) | Slippage::Percent(v) => Some(v),
+        }
+    }
+}
+
+/// Parses `none`, `per-share:0.05` or `percent:0.0005`.
+impl FromStr for Slippage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_model(s)? {
+            ("none", None) => Ok(Slippage::None),
+            ("per-share", Some(v)) => Ok(Slippage::PerShare(v)),
+            ("percent", Some(v)) => Ok(Slippage::Percent(v)),
+            _ => Err(format!("unknown slippage model `{}`", s)),
+        }
+    }
+}
+
+/// Split `name[:value]` for the cost model parsers.
+fn parse_model(s: &str) -> Result<(&str, Option<f64>), String> {
+    match s.split_once(':') {
+        Some((name, value)) => match value.parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok((name, Some(v))),
+            Ok(_) => Err(format!("bad value in `{}`: not a finite number", s)),
+            Err(e) => Err(format!("bad value in `{}`: {}", s, e)),
+        },
+        None => Ok((s, None)),
+    }
+}
+
+/// When market orders are filled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillModel {
+    /// At the open of the bar after the one that generated the order.
+    NextBarOpen,
+    /// At the close of the bar that generated the order.
+    ThisBarClose,
+}
+
+impl FromStr for FillModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "next-bar-open" => Ok(FillModel::NextBarOpen),
+            "this-bar-close" => Ok(FillModel::ThisBarClose),
+            _ => Err(format!("unknown fill model `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrokerConfig {
+    pub initial_cash: f64,
+    pub commission: Commission,
+    pub slippage: Slippage,
+    pub fill_model: FillModel,
+    /// Exchange sessions the bars trade in, read by the session builtins
+    pub calendar: Calendar,
+    /// Streams read as `:data 2` onwards
+    pub data_streams: Vec<DataStream>,
+    /// Fraction of an open position's value held as margin, for `margin-used`
+    pub margin: f64,
+}
+
+impl BrokerConfig {
+    /// Check that the cash and the cost models fit in a [`Decimal`] and that no cost is
+    /// negative, which every config taken from a user should pass before a [`Broker`] is
+    /// built from it.
+    pub fn validate(&self) -> Result<(), String> {
+        if Decimal::from_f64(self.initial_cash).is_none() {
+            return Err(format!("cash {} is not a finite amount the account can hold", self.initial_cash));
+        }
+        // A negative cost would pay the account for trading
+        for value in self.commission.value().into_iter().chain(self.slippage.value()) {
+            if value < 0.0 {
+                return Err(format!("cost {} is negative", value));
+            }
+            if Decimal::from_f64(value).is_none() {
+                return Err(format!("cost {} is not a finite amount the account can hold", value));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            initial_cash: 100_000.0,
+            commission: Commission::None,
+            slippage: Slippage::None,
+            fill_model: FillModel::NextBarOpen,
+            calendar: Calendar::default(),
+            data_streams: Vec::new(),
+            margin: 1.0,
+        }
+    }
+}
+
+/// EasyLanguage's built-in exits, with amounts in money for the whole position
+/// like `SetStopPosition`. Each is off until set.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RiskStops {
+    /// `SetStopLoss`: exit once the position has lost this much.
+    pub stop_loss: Option<f64>,
+    /// `SetProfitTarget`: exit once it has made this much.
+    pub profit_target: Option<f64>,
+    /// `SetBreakEven`: once it has made this much, exit if it falls back to the entry price.
+    pub breakeven: Option<f64>,
+    /// `SetDollarTrailing`: exit once it has given back this much from its best.
+    pub trailing_stop: Option<f64>,
+    /// `SetPercentTrailing`: once it has made the first amount, exit after giving back
+    /// the second, a percentage, of its best profit.
+    pub percent_trailing: Option<(f64, f64)>,
+}
+
+/// The account as a script sees it, from [`Broker::account`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Account {
+    /// Signed share count, `0` when flat.
+    pub position: i64,
+    pub entry_price: Option<f64>,
+    /// Unrealized profit of the open position, before commission.
+    pub open_profit: f64,
+    pub equity: f64,
+    pub margin_used: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fill {
+    pub order_id: u64,
+    pub bar_index: usize,
+    pub timestamp: Timestamp,
+    pub side: Side,
+    pub quantity: i64,
+    pub price: f64,
+    pub commission: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Direction {
+    Long,
+    Short,
+}
+
+/// Account state marked at a bar's close.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EquityPoint {
+    pub bar_index: usize,
+    pub timestamp: Timestamp,
+    pub equity: f64,
+    pub position: i64,
+}
+
+/// A closed round trip. Partial exits produce one trade per exit fill.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trade {
+    pub direction: Direction,
+    pub quantity: i64,
+    pub entry_bar: usize,
+    pub entry_time: Timestamp,
+    pub entry_price: f64,
+    pub exit_bar: usize,
+    pub exit_time: Timestamp,
+    pub exit_price: f64,
+    /// Entry and exit commission attributed to this trade.
+    pub commission: f64,
+    /// Net of commission.
+    pub pnl: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Position {
+    /// Signed: positive is long, negative is short.
+    quantity: i64,
+    /// What the open shares cost, before commission: their average price times their count.
+    cost: Decimal,
+    entry_bar: usize,
+    entry_time: Timestamp,
+    /// Entry commission not yet attributed to a closed trade.
+    entry_commission: Decimal,
+    /// The most favorable price since entry: the highest for a long, the lowest for a short.
+    best: f64,
+}
+
+impl Position {
+    fn avg_price(&self) -> f64 {
+        match self.cost.checked_div(self.quantity.abs().into()) {
+            Some(price) => price.to_f64(),
+            None => self.cost.to_f64() / self.quantity.abs() as f64,
+        }
+    }
+}
+
+/// What the account looks like after a fill, worked out before any of it is applied.
+struct Settlement {
+    cash: Decimal,
+    commission: Decimal,
+    position: Option<Position>,
+    trade: Option<Trade>,
+}
+
+pub struct Broker {
+    pub config: BrokerConfig,
+    /// Exits enforced on every bar, set by the script.
+    pub risk: RiskStops,
+    cash: Decimal,
+    position: Option<Position>,
+    pending: Vec<(u64, Order)>,
+    next_order_id: u64,
+    fills: Vec<Fill>,
+    trades: Vec<Trade>,
+    equity_curve: Vec<EquityPoint>,
+}
+
+impl Broker {
+    pub fn new(config: BrokerConfig) -> Self {
+        Self {
+            // Only an unvalidated config can start with cash that is not a decimal
+            cash: Decimal::from_f64(config.initial_cash).unwrap_or_default(),
+            config,
+            risk: RiskStops::default(),
+            position: None,
+            pending: Vec::new(),
+            next_order_id: 1,
+            fills: Vec::new(),
+            trades: Vec::new(),
+            equity_curve: Vec::new(),
+        }
+    }
+
+    pub fn cash(&self) -> f64 {
+        self.cash.to_f64()
+    }
+
+    /// Signed share count, `0` when flat.
+    pub fn position(&self) -> i64 {
+        self.position.as_ref().map_or(0, |p| p.quantity)
+    }
+
+    /// Average entry price of the open position, if any.
+    pub fn entry_price(&self) -> Option<f64> {
+        self.position.as_ref().map(Position::avg_price)
+    }
+
+    /// Cash plus the open position marked at `price`.
+    pub fn equity(&self, price: f64) -> f64 {
+        let exact = Decimal::from_f64(price)
+            .and_then(|price| Decimal::from(self.position()).checked_mul(price))
+            .and_then(|value| self.cash.checked_add(value));
+        match exact {
+            Some(equity) => equity.to_f64(),
+            None => self.cash.to_f64() + self.position() as f64 * price,
+        }
+    }
+
+    /// Position, profit and margin with the open position marked at `price`.
+    pub fn account(&self, price: f64) -> Account {
+        let position = self.position();
+        let open_profit = self.position.as_ref().map_or(0.0, |p| {
+            let exact = Decimal::from_f64(price)
+                .and_then(|price| Decimal::from(p.quantity).checked_mul(price))
+                .and_then(|value| value.checked_sub(Decimal::from(p.quantity.signum()).checked_mul(p.cost)?));
+            match exact {
+                Some(profit) => profit.to_f64(),
+                None => p.quantity as f64 * price - p.quantity.signum() as f64 * p.cost.to_f64(),
+            }
+        });
+        Account {
+            position,
+            entry_price: self.entry_price(),
+            open_profit,
+            equity: self.equity(price),
+            margin_used: (position as f64 * price).abs() * self.config.margin,
+        }
+    }
+
+    pub fn fills(&self) -> &[Fill] {
+        &self.fills
+    }
+
+    pub fn trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    pub fn equity_curve(&self) -> &[EquityPoint] {
+        &self.equity_curve
+    }
+
+    /// Record equity at the close of `bar`. Called once per bar after its orders are queued.
+    pub fn mark(&mut self, bar_index: usize, bar: &Bar) {
+        self.equity_curve.push(EquityPoint {
+            bar_index,
+            timestamp: bar.timestamp.clone(),
+            equity: self.equity(bar.close),
+            position: self.position(),
+        });
+    }
+
+    /// Queue an order generated while evaluating `bar`. Returns the order id.
+    pub fn submit(&mut self, order: Order, bar_index: usize, bar: &Bar) -> u64 {
+        let id = self.next_order_id;
+        self.next_order_id += 1;
+        if order.kind == OrderKind::Market && self.config.fill_model == FillModel::ThisBarClose {
+            let price = Decimal::from_f64(bar.close).and_then(|close| self.config.slippage.apply(order.side, close));
+            if let Some(price) = price {
+                self.fill(id, &order, price, bar_index, bar);
+            }
+        } else {
+            self.pending.push((id, order));
+        }
+        id
+    }
+
+    /// Close the open position at `bar`'s close, as a market order filled there would, drop
+    /// pending orders and mark the bar again, so the run ends flat.
+    pub fn flatten(&mut self, bar_index: usize, bar: &Bar) {
+        self.pending.clear();
+        let (Some(quantity), Some(close)) = (self.position.as_ref().map(|p| p.quantity), Decimal::from_f64(bar.close))
+        else {
+            return;
+        };
+        let side = if quantity > 0 { Side::Sell } else { Side::Buy };
+        let id = self.next_order_id;
+        self.next_order_id += 1;
+        if let Some(price) = self.config.slippage.apply(side, close) {
+            self.fill(id, &Order::new(side, quantity.abs(), OrderKind::Market), price, bar_index, bar);
+        }
+        if self.equity_curve.last().is_some_and(|point| point.bar_index == bar_index) {
+            self.equity_curve.pop();
+        }
+        self.mark(bar_index, bar);
+    }
+
+    /// Try to fill every pending order against a new bar, then the risk stops. Unfilled orders expire.
+    pub fn on_bar(&mut self, bar_index: usize, bar: &Bar) {
+        for (id, order) in std::mem::take(&mut self.pending) {
+            if let Some(price) = self.fill_price(&order, bar) {
+                self.fill(id, &order, price, bar_index, bar);
+            }
+        }
+        // A protective stop is assumed to trade before the profit target when a bar reaches both
+        for exit in self.risk_exits() {
+            if let Some(price) = self.fill_price(&exit, bar) {
+                let id = self.next_order_id;
+                self.next_order_id += 1;
+                self.fill(id, &exit, price, bar_index, bar);
+                break;
+            }
+        }
+        if let Some(pos) = self.position.as_mut() {
+            pos.best = if pos.quantity > 0 { pos.best.max(bar.high) } else { pos.best.min(bar.low) };
+        }
+    }
+
+    /// Orders closing the open position at the tightest protective stop and at the profit target.
+    fn risk_exits(&self) -> Vec<Order> {
+        let Some(pos) = &self.position else { return Vec::new() };
+        let (risk, quantity) = (&self.risk, pos.quantity.abs());
+        let (entry, direction) = (pos.avg_price(), pos.quantity.signum() as f64);
+        // Money for the whole position as a distance in price, and the best profit so far
+        let per_share = |amount: f64| amount / quantity as f64;
+        let best_profit = (pos.best - entry) * direction * quantity as f64;
+
+        let mut stops = Vec::new();
+        stops.extend(risk.stop_loss.map(|amount| entry - direction * per_share(amount)));
+        stops.extend(risk.breakeven.filter(|&floor| best_profit >= floor).map(|_| entry));
+        stops.extend(risk.trailing_stop.map(|amount| pos.best - direction * per_share(amount)));
+        if let Some((floor, percent)) = risk.percent_trailing
+            && best_profit >= floor
+        {
+            stops.push(entry + (pos.best - entry) * (1.0 - percent / 100.0));
+        }
+        let side = if pos.quantity > 0 { Side::Sell } else { Side::Buy };
+        let tightest = stops.into_iter().max_by(|a, b| (a * direction).total_cmp(&(b * direction)));
+        let target = risk.profit_target.map(|amount| entry + direction * per_share(amount));
+        let stop = tightest.map(|price| Order::new(side, quantity, OrderKind::Stop(price)));
+        stop.into_iter().chain(target.map(|price| Order::new(side, quantity, OrderKind::Limit(price)))).collect()
+    }
+
+    /// The fill price against `bar`, if the order trades and the price is a finite number.
+    fn fill_price(&self, order: &Order, bar: &Bar) -> Option<Decimal> {
+        let slipped = |side, price| Decimal::from_f64(price).and_then(|price| self.config.slippage.apply(side, price));
+        match (order.kind, order.side) {
+            (OrderKind::Market, side) => slipped(side, bar.open),
+            (OrderKind::Limit(limit), Side::Buy) => Decimal::from_f64(bar.open.min(limit)).filter(|_| bar.low <= limit),
+            (OrderKind::Limit(limit), Side::Sell) => Decimal::from_f64(bar.open.max(limit)).filter(|_| bar.high >= limit),
+            (OrderKind::Stop(stop), Side::Buy) => (bar.high >= stop).then(|| slipped(Side::Buy, bar.open.max(stop)))?,
+            (OrderKind::Stop(stop), Side::Sell) => (bar.low <= stop).then(|| slipped(Side::Sell, bar.open.min(stop)))?,
+        }
+    }
+
+    /// Apply a fill, unless its amounts overflow, in which case the order is rejected and nothing changes.
+    fn fill(&mut self, order_id: u64, order: &Order, price: Decimal, bar_index: usize, bar: &Bar) {
+        let Some(settlement) = self.settle(order, price, bar_index, bar) else {
+            return;
+        };
+        self.cash = settlement.cash;
+        self.position = settlement.position;
+        self.trades.extend(settlement.trade);
+        self.fills.push(Fill {
+            order_id,
+            bar_index,
+            timestamp: bar.timestamp.clone(),
+            side: order.side,
+            quantity: order.quantity,
+            price: price.to_f64(),
+            commission: settlement.commission.to_f64(),
+        });
+    }
+
+    /// The account after filling `order` at `price`, or `None` if any amount overflows.
+    fn settle(&self, order: &Order, price: Decimal, bar_index: usize, bar: &Bar) -> Option<Settlement> {
+        let commission = self.config.commission.cost(order.quantity, price)?;
+        let signed = order.side.sign() * order.quantity;
+        let cash = self.cash.checked_sub(Decimal::from(signed).checked_mul(price)?)?.checked_sub(commission)?;
+        let mut settlement = Settlement { cash, commission, position: self.position.clone(), trade: None };
+
+        let mut opening = order.quantity;
+        if let Some(pos) = settlement.position.as_mut() {
+            if pos.quantity.signum() == signed.signum() {
+                pos.cost = pos.cost.checked_add(price.checked_mul(order.quantity.into())?)?;
+                pos.quantity = pos.quantity.checked_add(signed)?;
+                pos.entry_commission = pos.entry_commission.checked_add(commission)?;
+                pos.best = if signed > 0 { pos.best.max(price.to_f64()) } else { pos.best.min(price.to_f64()) };
+                return Some(settlement);
+            }
+
+            // Close what the order can, taking a proportional share of the cost and entry commission
+            let closing = order.quantity.min(pos.quantity.abs());
+            let held = pos.quantity.abs();
+            let share = |amount: Decimal, of: i64| share_of(amount, closing, of);
+            let (cost_share, entry_share, exit_share) =
+                (share(pos.cost, held)?, share(pos.entry_commission, held)?, share(commission, order.quantity)?);
+            let direction = if pos.quantity > 0 { Direction::Long } else { Direction::Short };
+            let gross = price.checked_mul(closing.into())?.checked_sub(cost_share)?;
+            let gross = gross.checked_mul(pos.quantity.signum().into())?;
+            let costs = entry_share.checked_add(exit_share)?;
+            settlement.trade = Some(Trade {
+                direction,
+                quantity: closing,
+                entry_bar: pos.entry_bar,
+                entry_time: pos.entry_time.clone(),
+                entry_price: pos.avg_price(),
+                exit_bar: bar_index,
+                exit_time: bar.timestamp.clone(),
+                exit_price: price.to_f64(),
+                commission: costs.to_f64(),
+                pnl: gross.checked_sub(costs)?.to_f64(),
+            });
+            pos.cost = pos.cost.checked_sub(cost_share)?;
+            pos.entry_commission = pos.entry_commission.checked_sub(entry_share)?;
+            pos.quantity += signed.signum() * closing;
+            opening -= closing;
+            if pos.quantity == 0 {
+                settlement.position = None;
+            }
+        }
+
+        if opening > 0 {
+            settlement.position = Some(Position {
+                quantity: signed.signum() * opening,
+                cost: price.checked_mul(opening.into())?,
+                entry_bar: bar_index,
+                entry_time: bar.timestamp.clone(),
+                entry_commission: share_of(commission, opening, order.quantity)?,
+                best: price.to_f64(),
+            });
+        }
+        Some(settlement)
+    }
+}
+
+/// `part` out of `of` of an amount, exactly the amount when the part is the whole.
+fn share_of(amount: Decimal, part: i64, of: i64) -> Option<Decimal> {
+    if part == of {
+        return Some(amount);
+    }
+    amount.checked_mul(part.into())?.checked_div(of.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::parse_timestamp;
+
+    fn bar(day: u32, open: f64, high: f64, low: f64, close: f64) -> Bar {
+        let ts = parse_timestamp(&format!("2024-01-{:02}", day)).unwrap();
+        Bar::new(ts, open, high, low, close, 0.0)
+    }
+
+    #[test]
+    fn market_order_fills_at_next_open() {
+        let mut broker = Broker::new(BrokerConfig::default());
+        broker.submit(Order::new(Side::Buy, 10, OrderKind::Market), 0, &bar(1, 100.0, 101.0, 99.0, 100.5));
+        assert_eq!(broker.position(), 0);
+        broker.on_bar(1, &bar(2, 102.0, 103.0, 101.0, 102.5));
+        assert_eq!(broker.position(), 10);
+        assert_eq!(broker.fills()[0].price, 102.0);
+        assert_eq!(broker.cash(), 100_000.0 - 1020.0);
+    }
+
+    #[test]
+    fn this_bar_close_fills_immediately() {
+        let config = BrokerConfig { fill_model: FillModel::ThisBarClose, ..BrokerConfig::default() };
+        let mut broker = Broker::new(config);
+        broker.submit(Order::new(Side::Buy, 1, OrderKind::Market), 0, &bar(1, 100.0, 101.0, 99.0, 100.5));
+        assert_eq!(broker.fills()[0].price, 100.5);
+    }
+
+    #[test]
+    fn limit_and_stop_fill_rules() {
+        let mut broker = Broker::new(BrokerConfig::default());
+        let signal = bar(1, 100.0, 100.0, 100.0, 100.0);
+        // Gaps below the buy limit: filled at the better open.
+        broker.submit(Order::new(Side::Buy, 1, OrderKind::Limit(99.0)), 0, &signal);
+        // Never trades up to 120: expires unfilled.
+        broker.submit(Order::new(Side::Sell, 1, OrderKind::Limit(120.0)), 0, &signal);
+        // Trades through the buy stop: filled at the stop.
+        broker.submit(Order::new(Side::Buy, 1, OrderKind::Stop(98.0)), 0, &signal);
+        broker.on_bar(1, &bar(2, 97.0, 99.0, 96.0, 98.5));
+        let prices: Vec<f64> = broker.fills().iter().map(|f| f.price).collect();
+        assert_eq!(prices, vec![97.0, 98.0]);
+        broker.on_bar(2, &bar(3, 130.0, 130.0, 130.0, 130.0));
+        assert_eq!(broker.fills().len(), 2);
+    }
+
+    #[test]
+    fn round_trip_with_costs() {
+        let config = BrokerConfig {
+            initial_cash: 10_000.0,
+            commission: Commission::PerTrade(1.0),
+            slippage: Slippage::PerShare(0.05),
+            fill_model: FillModel::NextBarOpen,
+            calendar: Calendar::default(),
+            data_streams: Vec::new(),
+            margin: 1.0,
+        };
+        let mut broker = Broker::new(config);
+        broker.submit(Order::new(Side::Buy, 100, OrderKind::Market), 0, &bar(1, 10.0, 10.0, 10.0, 10.0));
+        broker.on_bar(1, &bar(2, 10.0, 11.0, 10.0, 11.0));
+        broker.submit(Order::new(Side::Sell, 100, OrderKind::Market), 1, &bar(2, 10.0, 11.0, 10.0, 11.0));
+        broker.on_bar(2, &bar(3, 11.0, 11.0, 11.0, 11.0));
+
+        let trade = &broker.trades()[0];
+        assert_eq!(trade.direction, Direction::Long);
+        assert_eq!(trade.entry_price, 10.05);
+        assert_eq!(trade.exit_price, 10.95);
+        assert_eq!(trade.commission, 2.0);
+        assert!((trade.pnl - 88.0).abs() < 1e-9);
+        assert!((broker.cash() - 10_088.0).abs() < 1e-9);
+        assert_eq!(broker.position(), 0);
+    }
+
+    #[test]
+    fn reversal_closes_then_opens() {
+        let mut broker = Broker::new(BrokerConfig::default());
+        let signal = bar(1, 50.0, 50.0, 50.0, 50.0);
+        broker.submit(Order::new(Side::Buy, 5, OrderKind::Market), 0, &signal);
+        broker.on_bar(1, &bar(2, 50.0, 50.0, 50.0, 50.0));
+        broker.submit(Order::new(Side::Sell, 8, OrderKind::Market), 1, &signal);
+        broker.on_bar(2, &bar(3, 52.0, 52.0, 52.0, 52.0));
+        assert_eq!(broker.position(), -3);
+        assert_eq!(broker.entry_price(), Some(52.0));
+        assert_eq!(broker.trades().len(), 1);
+        assert_eq!(broker.trades()[0].pnl, 10.0);
+    }
+
+    #[test]
+    fn enforces_risk_stops() {
+        let mut broker = Broker::new(BrokerConfig::default());
+        broker.risk = RiskStops { stop_loss: Some(50.0), profit_target: Some(100.0), ..RiskStops::default() };
+        broker.submit(Order::new(Side::Buy, 10, OrderKind::Market), 0, &bar(1, 100.0, 100.0, 100.0, 100.0));
+        // Neither the stop at 95 nor the target at 110 trades
+        broker.on_bar(1, &bar(2, 100.0, 104.0, 96.0, 103.0));
+        assert_eq!(broker.position(), 10);
+        // Trailing $30 from the best price of 104 tightens the stop to 101
+        broker.risk.trailing_stop = Some(30.0);
+        broker.on_bar(2, &bar(3, 103.0, 103.0, 100.0, 101.0));
+        assert_eq!(broker.position(), 0);
+        assert_eq!(broker.trades()[0].exit_price, 101.0);
+
+        // Short from 100 with a best of 90: giving back half of the $100 best profit stops out at 95
+        broker.risk = RiskStops { stop_loss: Some(50.0), percent_trailing: Some((50.0, 50.0)), ..RiskStops::default() };
+        broker.submit(Order::new(Side::Sell, 10, OrderKind::Market), 3, &bar(4, 100.0, 100.0, 100.0, 100.0));
+        broker.on_bar(4, &bar(5, 100.0, 100.0, 90.0, 92.0));
+        broker.on_bar(5, &bar(6, 93.0, 97.0, 92.0, 96.0));
+        assert_eq!(broker.position(), 0);
+        assert_eq!(broker.trades()[1].exit_price, 95.0);
+        assert_eq!(broker.trades()[1].pnl, 50.0);
+    }
+
+    #[test]
+    fn accounting_is_exact() {
+        let config = BrokerConfig { commission: Commission::PerShare(0.01), ..BrokerConfig::default() };
+        let mut broker = Broker::new(config);
+        // Ten buys at 0.1 and one sale at 0.3: sums that drift as floats
+        for day in 1..=10 {
+            broker.submit(Order::new(Side::Buy, 1, OrderKind::Market), day - 1, &bar(day as u32, 0.1, 0.1, 0.1, 0.1));
+            broker.on_bar(day, &bar(day as u32 + 1, 0.1, 0.1, 0.1, 0.1));
+        }
+        assert_eq!(broker.entry_price(), Some(0.1));
+        broker.submit(Order::new(Side::Sell, 10, OrderKind::Market), 10, &bar(11, 0.1, 0.1, 0.1, 0.1));
+        broker.on_bar(11, &bar(12, 0.3, 0.3, 0.3, 0.3));
+
+        let trade = &broker.trades()[0];
+        assert_eq!(trade.commission, 0.2);
+        assert_eq!(trade.pnl, 1.8);
+        assert_eq!(broker.cash(), 100_001.8);
+    }
+
+    #[test]
+    fn rejects_fills_that_overflow() {
+        let mut broker = Broker::new(BrokerConfig::default());
+        broker.submit(Order::new(Side::Buy, i64::MAX, OrderKind::Market), 0, &bar(1, 20.0, 20.0, 20.0, 20.0));
+        broker.on_bar(1, &bar(2, 20.0, 20.0, 20.0, 20.0));
+        broker.mark(1, &bar(2, 20.0, 20.0, 20.0, 20.0));
+        assert!(broker.fills().is_empty());
+        assert_eq!(broker.cash(), 100_000.0);
+
+        let config = |cash| BrokerConfig { initial_cash: cash, ..BrokerConfig::default() };
+        assert!(config(f64::NAN).validate().is_err());
+        assert!(config(1e300).validate().is_err());
+        let costly = BrokerConfig { commission: Commission::PerTrade(1e300), ..BrokerConfig::default() };
+        assert!(costly.validate().is_err());
+        let rebate = BrokerConfig { commission: Commission::PerShare(-0.01), ..BrokerConfig::default() };
+        assert_eq!(rebate.validate(), Err("cost -0.01 is negative".to_string()));
+        let slipping = BrokerConfig { slippage: Slippage::Percent(f64::INFINITY), ..BrokerConfig::default() };
+        assert!(slipping.validate().is_err());
+        assert!(BrokerConfig { slippage: Slippage::PerShare(-0.05), ..BrokerConfig::default() }.validate().is_err());
+        assert!(config(1e6).validate().is_ok());
+    }
+}
@@ -0,0 +1,400 @@
+//! Exchange calendars: time zones, trading sessions and holidays.
+//!
+//! Timestamps are UTC; a [`Calendar`] says what they were on the exchange's
+//! wall clock and which session, if any, they fall in. Time zones follow POSIX
+//! `TZ` rules like `EST5EDT,M3.2.0,M11.1.0`, so they know the current daylight
+//! saving rules but not historical changes to them. A few exchange zones are
+//! built in; any other name is looked up in the system's zoneinfo database.
+//!
+//! A session belongs to a trading date. One whose close is before its open,
+//! like CME's 17:00 to 16:00, opens the evening before its trading date; one
+//! whose open equals its close lasts the whole day.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// POSIX rules for the zones of the built-in exchanges
+const ZONES: &[(&str, &str)] = &[
+    ("UTC", "UTC0"),
+    ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Chicago", "CST6CDT,M3.2.0,M11.1.0"),
+    ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Asia/Tokyo", "JST-9"),
+    ("Asia/Hong_Kong", "HKT-8"),
+    ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+];
+
+/// A fixed offset from UTC, optionally with daylight saving time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeZone {
+    name: String,
+    /// Seconds east of UTC outside daylight saving time
+    standard: i64,
+    daylight: Option<Daylight>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Daylight {
+    /// Seconds east of UTC during daylight saving time
+    offset: i64,
+    start: Transition,
+    end: Transition,
+}
+
+/// `Mm.w.d/time`: day `d` (0 is Sunday) of week `w` (5 is the last) of month `m`,
+/// at `time` seconds past local midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transition {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    time: i64,
+}
+
+impl Transition {
+    /// When the transition happens in `year`, on the local clock it interrupts.
+    fn local(&self, year: i32) -> Option<NaiveDateTime> {
+        let first = NaiveDate::from_ymd_opt(year, self.month, 1)?;
+        let first_weekday = first.weekday().num_days_from_sunday();
+        let mut day = 1 + (self.weekday + 7 - first_weekday) % 7 + (self.week - 1) * 7;
+        let (next_year, next_month) = if self.month == 12 { (year + 1, 1) } else { (year, self.month + 1) };
+        let days_in_month = NaiveDate::from_ymd_opt(next_year, next_month, 1)?.pred_opt()?.day();
+        while day > days_in_month {
+            day -= 7;
+        }
+        Some(NaiveDate::from_ymd_opt(year, self.month, day)?.and_hms_opt(0, 0, 0)? + Duration::seconds(self.time))
+    }
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        TimeZone { name: "UTC".to_string(), standard: 0, daylight: None }
+    }
+
+    /// A built-in zone, or one from the system's zoneinfo database, by name.
+    pub fn named(name: &str) -> Result<Self, String> {
+        if let Some((_, rule)) = ZONES.iter().find(|(zone, _)| *zone == name) {
+            return Self::posix(name, rule);
+        }
+        let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "_-+".contains(c));
+        if !name.split('/').all(valid) {
+            return Err(format!("unknown time zone `{}`", name));
+        }
+        // TZif files end with the POSIX rule for times after their last transition
+        let path = Path::new("/usr/share/zoneinfo").join(name);
+        let bytes = std::fs::read(&path).map_err(|e| format!("unknown time zone `{}`: {}", name, e))?;
+        let text = String::from_utf8_lossy(&bytes);
+        let rule = text.trim_end_matches('\n').rsplit('\n').next().filter(|rule| !rule.is_empty());
+        match rule {
+            Some(rule) if bytes.starts_with(b"TZif") => Self::posix(name, rule),
+            _ => Err(format!("unknown time zone `{}`: no rule in {}", name, path.display())),
+        }
+    }
+
+    /// Read a POSIX `TZ` rule like `CET-1CEST,M3.5.0,M10.5.0/3`.
+    pub fn posix(name: &str, rule: &str) -> Result<Self, String> {
+        let bad = |why: &str| format!("bad time zone rule `{}`: {}", rule, why);
+        let mut rest = rule;
+        abbreviation(&mut rest).ok_or_else(|| bad("expected a zone abbreviation"))?;
+        // POSIX offsets count hours west of UTC
+        let standard = -offset(&mut rest).ok_or_else(|| bad("expected an offset"))?;
+        if rest.is_empty() {
+            return Ok(TimeZone { name: name.to_string(), standard, daylight: None });
+        }
+        abbreviation(&mut rest).ok_or_else(|| bad("expected a daylight saving abbreviation"))?;
+        let daylight_offset = if rest.starts_with(',') {
+            standard + 3600
+        } else {
+            -offset(&mut rest).ok_or_else(|| bad("expected a daylight saving offset"))?
+        };
+        let (start, end) = rest
+            .strip_prefix(',')
+            .and_then(|rules| rules.split_once(','))
+            .ok_or_else(|| bad("expected start and end rules"))?;
+        let transition = |spec: &str| {
+            let (date, time) = spec.split_once('/').unwrap_or((spec, "2"));
+            let mut time_rest = time;
+            let time = offset(&mut time_rest).filter(|_| time_rest.is_empty())?;
+            let mut fields = date.strip_prefix('M')?.split('.').map(|n| n.parse::<u32>().ok());
+            let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+            let valid = (1..=12).contains(&month) && (1..=5).contains(&week) && weekday <= 6 && fields.next().is_none();
+            valid.then_some(Transition { month, week, weekday, time })
+        };
+        Ok(TimeZone {
+            name: name.to_string(),
+            standard,
+            daylight: Some(Daylight {
+                offset: daylight_offset,
+                start: transition(start).ok_or_else(|| bad("only `Mm.w.d[/time]` rules are supported"))?,
+                end: transition(end).ok_or_else(|| bad("only `Mm.w.d[/time]` rules are supported"))?,
+            }),
+        })
+    }
+
+    /// Seconds east of UTC in effect at `utc`.
+    pub fn offset_at(&self, utc: DateTime<Utc>) -> i64 {
+        let Some(daylight) = &self.daylight else {
+            return self.standard;
+        };
+        let year = (utc + Duration::seconds(self.standard)).year();
+        // Daylight time starts on the standard clock and ends on the daylight clock
+        let start = daylight.start.local(year).map(|t| t.and_utc() - Duration::seconds(self.standard));
+        let end = daylight.end.local(year).map(|t| t.and_utc() - Duration::seconds(daylight.offset));
+        let (Some(start), Some(end)) = (start, end) else {
+            return self.standard;
+        };
+        let in_daylight = if start < end { start <= utc && utc < end } else { !(end <= utc && utc < start) };
+        if in_daylight { daylight.offset } else { self.standard }
+    }
+
+    /// The wall clock at `utc`.
+    pub fn to_local(&self, utc: DateTime<Utc>) -> NaiveDateTime {
+        utc.naive_utc() + Duration::seconds(self.offset_at(utc))
+    }
+
+    /// The instant a wall clock reading names. A reading that a clock change
+    /// skips or repeats resolves to one of the instants around it.
+    pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let guess = local.and_utc() - Duration::seconds(self.standard);
+        local.and_utc() - Duration::seconds(self.offset_at(guess))
+    }
+}
+
+/// The zone's name.
+impl fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// A zone name like `America/New_York`, or a POSIX rule.
+impl FromStr for TimeZone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') || ZONES.iter().any(|(zone, _)| *zone == s) {
+            Self::named(s)
+        } else {
+            Self::posix(s, s)
+        }
+    }
+}
+
+/// Take `EST` or `<+09>` off the front of `s`.
+fn abbreviation(s: &mut &str) -> Option<()> {
+    let len = match s.strip_prefix('<') {
+        Some(quoted) => quoted.find('>')? + 2,
+        None => s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len()),
+    };
+    (len >= 3).then(|| *s = &s[len..])
+}
+
+/// Take `[+-]hh[:mm[:ss]]` off the front of `s`, in seconds.
+fn offset(s: &mut &str) -> Option<i64> {
+    let end = s.find(|c: char| !(c.is_ascii_digit() || "+-:".contains(c))).unwrap_or(s.len());
+    let (text, rest) = s.split_at(end);
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let mut seconds = 0;
+    for (i, part) in digits.split(':').enumerate() {
+        if i > 2 || part.is_empty() || part.len() > 3 {
+            return None;
+        }
+        seconds += part.parse::<i64>().ok()? * [3600, 60, 1][i];
+    }
+    *s = rest;
+    Some(sign * seconds)
+}
+
+/// Trading hours on the exchange's wall clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Session {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+/// Parses `09:30-16:00`.
+impl FromStr for Session {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad session `{}`: expected `HH:MM-HH:MM`", s);
+        let (open, close) = s.split_once('-').ok_or_else(bad)?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| bad());
+        Ok(Session { open: time(open)?, close: time(close)? })
+    }
+}
+
+/// One session as it happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionTimes {
+    pub date: NaiveDate,
+    pub open: DateTime<Utc>,
+    pub close: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    pub time_zone: TimeZone,
+    pub session: Session,
+    pub trading_days: Vec<Weekday>,
+    /// Days the exchange is closed, or closes early at the given time
+    pub holidays: BTreeMap<NaiveDate, Option<NaiveTime>>,
+}
+
+const WEEKDAYS: [Weekday; 5] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+const EVERY_DAY: [Weekday; 7] =
+    [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+/// Around the clock in UTC, every day of the week.
+impl Default for Calendar {
+    fn default() -> Self {
+        Calendar {
+            time_zone: TimeZone::utc(),
+            session: Session { open: NaiveTime::MIN, close: NaiveTime::MIN },
+            trading_days: EVERY_DAY.to_vec(),
+            holidays: BTreeMap::new(),
+        }
+    }
+}
+
+impl Calendar {
+    /// A built-in exchange: `nyse`, `nasdaq`, `cme`, `lse`, `xetra`, `tse` or `24x7`.
+    pub fn exchange(name: &str) -> Result<Self, String> {
+        let (zone, session) = match name {
+            "nyse" | "nasdaq" => ("America/New_York", "09:30-16:00"),
+            "cme" => ("America/Chicago", "17:00-16:00"),
+            "lse" => ("Europe/London", "08:00-16:30"),
+            "xetra" => ("Europe/Berlin", "09:00-17:30"),
+            "tse" => ("Asia/Tokyo", "09:00-15:00"),
+            "24x7" => return Ok(Calendar::default()),
+            _ => return Err(format!("unknown exchange `{}`", name)),
+        };
+        Ok(Calendar {
+            time_zone: TimeZone::named(zone)?,
+            session: session.parse()?,
+            trading_days: WEEKDAYS.to_vec(),
+            holidays: BTreeMap::new(),
+        })
+    }
+
+    /// Read holidays from a file with one `YYYY-MM-DD` per line, followed by
+    /// `HH:MM` for an early close. `#` starts a comment.
+    pub fn load_holidays(&mut self, path: &Path) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.holidays.extend(parse_holidays(&text).map_err(|e| format!("{}:{}", path.display(), e))?);
+        Ok(())
+    }
+
+    /// The wall clock on the exchange at `utc`.
+    pub fn to_local(&self, utc: DateTime<Utc>) -> NaiveDateTime {
+        self.time_zone.to_local(utc)
+    }
+
+    /// The hours of the session for trading date `date`, if the exchange opens that day.
+    pub fn session_on(&self, date: NaiveDate) -> Option<SessionTimes> {
+        if !self.trading_days.contains(&date.weekday()) {
+            return None;
+        }
+        let close = match self.holidays.get(&date) {
+            Some(None) => return None,
+            Some(Some(early)) => *early,
+            None => self.session.close,
+        };
+        let open_day = match self.session.open.cmp(&self.session.close) {
+            std::cmp::Ordering::Greater => date.pred_opt()?,
+            _ => date,
+        };
+        let close_day = if self.session.open == self.session.close { date.succ_opt()? } else { date };
+        Some(SessionTimes {
+            date,
+            open: self.time_zone.to_utc(open_day.and_time(self.session.open)),
+            close: self.time_zone.to_utc(close_day.and_time(close)),
+        })
+    }
+
+    /// The session `utc` falls in, if any. Sessions include their open but not their close.
+    pub fn session_at(&self, utc: DateTime<Utc>) -> Option<SessionTimes> {
+        let today = self.to_local(utc).date();
+        [today, today.succ_opt()?]
+            .into_iter()
+            .filter_map(|date| self.session_on(date))
+            .find(|session| session.open <= utc && utc < session.close)
+    }
+}
+
+fn parse_holidays(text: &str) -> Result<Vec<(NaiveDate, Option<NaiveTime>)>, String> {
+    let mut holidays = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad = || format!("{}: expected `YYYY-MM-DD [HH:MM]`, found `{}`", number + 1, line);
+        let mut fields = line.split_whitespace();
+        let date = fields.next().and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()).ok_or_else(bad)?;
+        let early_close = match fields.next() {
+            Some(time) => Some(NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| bad())?),
+            None => None,
+        };
+        if fields.next().is_some() {
+            return Err(bad());
+        }
+        holidays.push((date, early_close));
+    }
+    Ok(holidays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::parse_timestamp;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        parse_timestamp(s).unwrap().0
+    }
+
+    #[test]
+    fn follows_daylight_saving_rules() {
+        let new_york = TimeZone::named("America/New_York").unwrap();
+        assert_eq!(new_york.offset_at(at("2024-01-02T15:00:00Z")), -5 * 3600);
+        assert_eq!(new_york.offset_at(at("2024-07-02T15:00:00Z")), -4 * 3600);
+        // 2024-03-10 02:00 EST is 07:00 UTC
+        assert_eq!(new_york.offset_at(at("2024-03-10T06:59:59Z")), -5 * 3600);
+        assert_eq!(new_york.offset_at(at("2024-03-10T07:00:00Z")), -4 * 3600);
+        assert_eq!(new_york.to_utc(at("2024-11-04T09:30:00Z").naive_utc()), at("2024-11-04T14:30:00Z"));
+
+        let sydney: TimeZone = "Australia/Sydney".parse().unwrap();
+        assert_eq!(sydney.offset_at(at("2024-01-02T00:00:00Z")), 11 * 3600);
+        assert_eq!(sydney.offset_at(at("2024-07-02T00:00:00Z")), 10 * 3600);
+        let india: TimeZone = "IST-5:30".parse().unwrap();
+        assert_eq!(india.offset_at(at("2024-07-02T00:00:00Z")), 5 * 3600 + 1800);
+        assert!("EST5EDT,J60,J300".parse::<TimeZone>().is_err());
+    }
+
+    #[test]
+    fn finds_sessions_and_holidays() {
+        let mut nyse = Calendar::exchange("nyse").unwrap();
+        nyse.holidays.extend(parse_holidays("# 2024\n2024-07-04\n2024-11-29 13:00 # day after Thanksgiving\n").unwrap());
+        let session = nyse.session_at(at("2024-07-02T13:30:00Z")).unwrap();
+        assert_eq!((session.open, session.close), (at("2024-07-02T13:30:00Z"), at("2024-07-02T20:00:00Z")));
+        assert_eq!(nyse.session_at(at("2024-07-02T20:00:00Z")), None);
+        assert_eq!(nyse.session_at(at("2024-07-04T15:00:00Z")), None);
+        assert_eq!(nyse.session_at(at("2024-11-29T18:30:00Z")), None);
+        assert!(nyse.session_at(at("2024-11-29T17:30:00Z")).is_some());
+        assert!(parse_holidays("2024-13-01").is_err());
+
+        // Monday's CME session opens on Sunday evening
+        let cme = Calendar::exchange("cme").unwrap();
+        let session = cme.session_at(at("2024-01-07T23:30:00Z")).unwrap();
+        assert_eq!(session.date, NaiveDate::from_ymd_opt(2024, 1, 8).unwrap());
+        assert_eq!(cme.session_at(at("2024-01-06T23:30:00Z")), None);
+        assert_eq!(Calendar::default().session_at(at("2024-01-06T23:30:00Z")).unwrap().date.day(), 6);
+    }
+}
@@ -0,0 +1,603 @@
+//! Static checks run by `stonkscheme check` before a script ever sees a bar.
+//!
+//! The linter walks the spanned syntax tree and reports each finding as a
+//! [`Diagnostic`] under a named [`Lint`]. Every lint has a [`Level`]: `allow`
+//! drops its findings, `warn` reports them as warnings and `deny` as errors.
+//! A program runs once per bar with the same environment, so a name bound by
+//! `set` anywhere in it counts as bound everywhere.
+
+use indexmap::{IndexMap, IndexSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ast::Expr;
+use crate::code::CodeSpan;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::interpreter::{BAR_FIELDS, BUILTINS, SERIES_FIELDS};
+use crate::syntax::{Node, NodeKind};
+use crate::types::check_types;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A symbol that nothing binds, which evaluates to itself
+    UnboundSymbol,
+    /// An input, `vars` or `set` variable or `let` binding that is never read
+    UnusedVariable,
+    /// A builtin called with the wrong number of arguments
+    Arity,
+    /// An input that is declared twice, reassigned or named after a builtin
+    ShadowedInput,
+    /// An `if` whose condition is a literal that is not a boolean
+    ConstantCondition,
+    /// The branch of an `if` on `#t` or `#f` that can never run
+    UnreachableCode,
+    /// A bar field read at a negative offset, from a bar that has not happened yet
+    LookAhead,
+    /// A value whose type cannot be what is expected, as found by [`check_types`]
+    TypeMismatch,
+}
+
+impl Lint {
+    pub const ALL: &[Lint] = &[
+        Lint::UnboundSymbol,
+        Lint::UnusedVariable,
+        Lint::Arity,
+        Lint::ShadowedInput,
+        Lint::ConstantCondition,
+        Lint::UnreachableCode,
+        Lint::LookAhead,
+        Lint::TypeMismatch,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnboundSymbol => "unbound-symbol",
+            Lint::UnusedVariable => "unused-variable",
+            Lint::Arity => "arity",
+            Lint::ShadowedInput => "shadowed-input",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::LookAhead => "look-ahead",
+            Lint::TypeMismatch => "type-mismatch",
+        }
+    }
+
+    /// Mistakes that fail or mislead at runtime are denied, the rest warned about.
+    pub fn default_level(self) -> Level {
+        match self {
+            Lint::Arity | Lint::ConstantCondition | Lint::LookAhead | Lint::TypeMismatch => Level::Deny,
+            _ => Level::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == s).ok_or_else(|| format!("unknown lint `{}`", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        })
+    }
+}
+
+/// The level of every lint, starting from [`Lint::default_level`]
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: IndexMap<Lint, Level>,
+}
+
+impl LintConfig {
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels.get(&lint).copied().unwrap_or(lint.default_level())
+    }
+}
+
+/// Every finding in `nodes` at its configured level, in source order.
+pub fn check(nodes: &[Node], config: &LintConfig) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        config,
+        inputs: IndexMap::new(),
+        variables: IndexMap::new(),
+        scopes: Vec::new(),
+        used: IndexSet::new(),
+        diagnostics: Vec::new(),
+    };
+    for node in nodes {
+        checker.collect(node);
+    }
+    for node in nodes {
+        checker.expr(node);
+    }
+
+    let unused: Vec<_> = checker
+        .inputs
+        .iter()
+        .map(|(name, span)| (name, span, "input"))
+        .chain(checker.variables.iter().map(|(name, span)| (name, span, "variable")))
+        .filter(|(name, _, kind)| *kind == "input" || !checker.inputs.contains_key(*name))
+        .filter(|(name, _, _)| !checker.used.contains(name.as_str()))
+        .map(|(name, span, kind)| (format!("{} `{}` is never read", kind, name), span.clone()))
+        .collect();
+    for (message, span) in unused {
+        checker.report(Lint::UnusedVariable, message, span, "never read");
+    }
+    for error in check_types(nodes) {
+        let mut diagnostic = checker.diagnostic(Lint::TypeMismatch, error.message, error.span, error.label);
+        if let Some((span, label)) = error.related {
+            diagnostic = diagnostic.with_secondary(span, label);
+        }
+        if let Some(help) = error.help {
+            diagnostic = diagnostic.with_help(help);
+        }
+        checker.push(Lint::TypeMismatch, diagnostic);
+    }
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| d.primary_span().map(|span| span.start));
+    diagnostics
+}
+
+/// Whether any of `diagnostics` is an error, i.e. from a denied lint
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.severity == Severity::Error)
+}
+
+/// The positional argument counts a builtin accepts, as `(min, max)`
+fn arity(name: &str) -> Option<(usize, Option<usize>)> {
+    Some(match name {
+        "set" => (2, Some(2)),
+        "if" => (2, Some(3)),
+        "get" | "car" | "cdr" | "cons" | "not" | "write" | "display" | "buy" | "sell" => (1, Some(1)),
+        "plot" | "plot-overlay" | "plot-candles" => (1, Some(1)),
+        "decimal" | "float" | "to-exchange-time" | "DayOfWeek" => (1, Some(1)),
+        "session-open?" => (0, Some(1)),
+        "bars-since-session-open" | "position" | "entry-price" | "open-position-profit" => (0, Some(0)),
+        "account-equity" | "margin-used" => (0, Some(0)),
+        "set-stop-loss" | "set-profit-target" | "set-breakeven" | "set-trailing-stop" => (1, Some(1)),
+        "set-percent-trailing" => (2, Some(2)),
+        "buy-limit" | "sell-limit" | "buy-stop" | "sell-stop" | "round-to-tick" => (2, Some(2)),
+        "newline" => (0, Some(0)),
+        "-" | "/" => (1, None),
+        "<" | ">" | "<=" | ">=" | "=" => (2, None),
+        "let" => (1, None),
+        "+" | "*" | "begin" | "inputs" | "vars" => (0, None),
+        _ => return None,
+    })
+}
+
+struct Checker<'a> {
+    config: &'a LintConfig,
+    /// Each declared input and where it is first declared
+    inputs: IndexMap<String, CodeSpan>,
+    /// Each `vars` or `set` variable that is not an input, and where it is first declared or set
+    variables: IndexMap<String, CodeSpan>,
+    /// The bindings of each enclosing `let`, innermost last, and whether each has been read
+    scopes: Vec<IndexMap<String, (CodeSpan, bool)>>,
+    used: IndexSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    /// First pass: record every binding, so uses before a `set` in the text resolve.
+    fn collect(&mut self, node: &Node) {
+        match head(node) {
+            Some((_, "inputs", args)) => {
+                for (name, span, _) in args.iter().filter_map(declaration) {
+                    if !self.inputs.contains_key(name) {
+                        self.inputs.insert(name.to_string(), span.clone());
+                    }
+                }
+            }
+            Some((_, "set", [name, ..])) => {
+                if let Some((name, span)) = symbol(name)
+                    && !self.variables.contains_key(name)
+                {
+                    self.variables.insert(name.to_string(), span.clone());
+                }
+            }
+            Some((_, "vars", args)) => {
+                for (name, span, _) in args.iter().filter_map(declaration) {
+                    if !self.variables.contains_key(name) {
+                        self.variables.insert(name.to_string(), span.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        for child in node.children() {
+            self.collect(child);
+        }
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.inputs.contains_key(name)
+            || self.variables.contains_key(name)
+            || BAR_FIELDS.contains(&name)
+            || BUILTINS.contains(&name)
+    }
+
+    fn expr(&mut self, node: &Node) {
+        match &node.kind {
+            NodeKind::Atom(Expr::Symbol(name)) => self.reference(&name.0, &node.span),
+            NodeKind::Atom(_) => {}
+            NodeKind::Array(items) => items.iter().for_each(|item| self.expr(item)),
+            NodeKind::Combination(items) => self.combination(node, items),
+        }
+    }
+
+    fn reference(&mut self, name: &str, span: &CodeSpan) {
+        if name.starts_with(':') {
+            return;
+        }
+        // A `set` in a `let` body also records the name as a variable
+        self.used.insert(name.to_string());
+        if let Some((_, read)) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            *read = true;
+            return;
+        }
+        if !self.is_bound(name) {
+            let message = format!("cannot find `{}` in this script", name);
+            self.report(Lint::UnboundSymbol, message, span.clone(), "not bound by `set` or `inputs`");
+        }
+    }
+
+    fn combination(&mut self, node: &Node, items: &[Node]) {
+        let Some((head_span, name, args)) = head(node) else {
+            items.iter().for_each(|item| self.expr(item));
+            return;
+        };
+        self.arity(node, name, args);
+
+        match (name, args) {
+            ("inputs", _) => {
+                for decl in args {
+                    self.input(decl);
+                }
+                return;
+            }
+            ("set", [target, value, ..]) => {
+                if let Some((target, span)) = symbol(target)
+                    && let Some(declared) = self.inputs.get(target).cloned()
+                {
+                    let message = format!("`set` overwrites input `{}`", target);
+                    let diagnostic = self
+                        .diagnostic(Lint::ShadowedInput, message, span.clone(), "assigned here")
+                        .with_secondary(declared, "declared as an input here")
+                        .with_help("bind the result to a new name so the tuned value is kept");
+                    self.push(Lint::ShadowedInput, diagnostic);
+                }
+                self.expr(value);
+                return;
+            }
+            ("vars", _) => {
+                for (_, _, initial) in args.iter().filter_map(declaration) {
+                    if let Some(initial) = initial {
+                        self.expr(initial);
+                    }
+                }
+                return;
+            }
+            ("let", [bindings, body @ ..]) => {
+                self.let_form(bindings, body);
+                return;
+            }
+            ("get", [target]) => {
+                if let Some((target, span)) = symbol(target) {
+                    self.reference(target, span);
+                }
+                return;
+            }
+            ("if", [condition, then, rest @ ..]) => self.condition(condition, then, rest.first()),
+            _ => {}
+        }
+
+        if SERIES_FIELDS.contains(&name)
+            && let Some(offset) = args.first()
+            && let NodeKind::Atom(Expr::Integer(n)) = offset.kind
+            && n < 0
+        {
+            let message = format!("`({} {})` reads a bar that has not happened yet", name, n);
+            let diagnostic = self
+                .diagnostic(Lint::LookAhead, message, node.span.clone(), "look-ahead bias")
+                .with_note("offsets count bars back from the current one, so they must not be negative");
+            self.push(Lint::LookAhead, diagnostic);
+        }
+
+        self.reference(name, head_span);
+        // The value after a `:keyword` may be a bare name, like `:style line`
+        let mut after_keyword = false;
+        for arg in args {
+            if !(after_keyword && symbol(arg).is_some()) {
+                self.expr(arg);
+            }
+            after_keyword = symbol(arg).is_some_and(|(name, _)| name.starts_with(':'));
+        }
+    }
+
+    fn input(&mut self, decl: &Node) {
+        let Some((name, span, default)) = declaration(decl) else {
+            return;
+        };
+        if let Some(declared) = self.inputs.get(name)
+            && declared != span
+        {
+            let message = format!("input `{}` is declared more than once", name);
+            let diagnostic = self
+                .diagnostic(Lint::ShadowedInput, message, span.clone(), "declared again here")
+                .with_secondary(declared.clone(), "first declared here");
+            self.push(Lint::ShadowedInput, diagnostic);
+        }
+        if BAR_FIELDS.contains(&name) || BUILTINS.contains(&name) {
+            let message = format!("input `{}` shadows the builtin `{}`", name, name);
+            self.report(Lint::ShadowedInput, message, span.clone(), "rename this input");
+        }
+        if let Some(default) = default {
+            self.expr(default);
+        }
+    }
+
+    /// Values are read outside the new bindings and the body inside them.
+    fn let_form(&mut self, bindings: &Node, body: &[Node]) {
+        let pairs = match &bindings.kind {
+            NodeKind::Combination(pairs) => pairs.as_slice(),
+            _ => &[],
+        };
+        let mut scope = IndexMap::new();
+        for pair in pairs {
+            if let NodeKind::Combination(parts) = &pair.kind
+                && let [name, value] = parts.as_slice()
+                && let Some((name, span)) = symbol(name)
+            {
+                self.expr(value);
+                scope.insert(name.to_string(), (span.clone(), false));
+            } else {
+                self.expr(pair);
+            }
+        }
+        self.scopes.push(scope);
+        for form in body {
+            self.expr(form);
+        }
+        let scope = self.scopes.pop().expect("pushed above");
+        for (name, (span, read)) in scope {
+            if !read {
+                self.report(Lint::UnusedVariable, format!("`let` binding `{}` is never read", name), span, "never read");
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &Node, then: &Node, otherwise: Option<&Node>) {
+        match &condition.kind {
+            NodeKind::Atom(Expr::Boolean(value)) => {
+                let dead = if *value { otherwise } else { Some(then) };
+                if let Some(dead) = dead {
+                    let message = "this branch can never run";
+                    let always = format!("the condition is always `{}`", condition_text(*value));
+                    let diagnostic = self
+                        .diagnostic(Lint::UnreachableCode, message, dead.span.clone(), "unreachable")
+                        .with_secondary(condition.span.clone(), always);
+                    self.push(Lint::UnreachableCode, diagnostic);
+                }
+            }
+            _ if is_literal(condition) => {
+                let message = format!("`if` condition is a constant {}, not a boolean", condition.type_name());
+                let diagnostic = self
+                    .diagnostic(Lint::ConstantCondition, message, condition.span.clone(), "this is never `#t` or `#f`")
+                    .with_note("`if` fails at runtime unless its condition is a boolean");
+                self.push(Lint::ConstantCondition, diagnostic);
+            }
+            _ => {}
+        }
+    }
+
+    fn arity(&mut self, node: &Node, name: &str, args: &[Node]) {
+        let Some((min, max)) = arity(name) else {
+            return;
+        };
+        let given = positional(args);
+        if given >= min && max.is_none_or(|max| given <= max) {
+            return;
+        }
+        let expected = match max {
+            Some(max) if max == min => plural(min, "argument"),
+            Some(max) => format!("{} to {} arguments", min, max),
+            None => format!("at least {}", plural(min, "argument")),
+        };
+        let message = format!("`{}` takes {} but {} supplied", name, expected, plural_supplied(given));
+        let usage = crate::interpreter::SIGNATURES.iter().find(|(n, ..)| *n == name).map(|(_, usage, _)| *usage);
+        let label = format!("{} supplied", plural(given, "argument"));
+        let mut diagnostic = self.diagnostic(Lint::Arity, message, node.span.clone(), label);
+        if let Some(usage) = usage {
+            diagnostic = diagnostic.with_help(format!("the form is `{}`", usage));
+        }
+        self.push(Lint::Arity, diagnostic);
+    }
+
+    /// A report at the lint's level, noting when that level is the default
+    fn diagnostic(&self, lint: Lint, message: impl Into<String>, span: CodeSpan, label: impl Into<String>) -> Diagnostic {
+        let severity = if self.config.level(lint) == Level::Deny { Severity::Error } else { Severity::Warning };
+        let mut diagnostic = Diagnostic::new(severity, message).with_label(span, label);
+        if self.config.levels.get(&lint).is_none() {
+            diagnostic = diagnostic.with_note(format!("`{}` is set to `{}` by default", lint, lint.default_level()));
+        }
+        diagnostic
+    }
+
+    fn report(&mut self, lint: Lint, message: String, span: CodeSpan, label: &str) {
+        let diagnostic = self.diagnostic(lint, message, span, label);
+        self.push(lint, diagnostic);
+    }
+
+    fn push(&mut self, lint: Lint, diagnostic: Diagnostic) {
+        if self.config.level(lint) != Level::Allow {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+/// The head symbol of a combination, with its span and the arguments after it
+fn head(node: &Node) -> Option<(&CodeSpan, &str, &[Node])> {
+    match &node.kind {
+        NodeKind::Combination(items) => match &items[0].kind {
+            NodeKind::Atom(Expr::Symbol(name)) => Some((&items[0].span, name.0.as_str(), &items[1..])),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn symbol(node: &Node) -> Option<(&str, &CodeSpan)> {
+    match &node.kind {
+        NodeKind::Atom(Expr::Symbol(name)) => Some((name.0.as_str(), &node.span)),
+        _ => None,
+    }
+}
+
+/// The name of an input declaration `(name [Type] [default])` and its default
+fn declaration(decl: &Node) -> Option<(&str, &CodeSpan, Option<&Node>)> {
+    let NodeKind::Combination(parts) = &decl.kind else {
+        return None;
+    };
+    let (name, span) = symbol(&parts[0])?;
+    let default = match &parts[1..] {
+        [only] if is_type(only) => None,
+        [default] | [_, default] => Some(default),
+        _ => None,
+    };
+    Some((name, span, default))
+}
+
+/// `Price` or `(Array Price)`, as in the interpreter
+fn is_type(node: &Node) -> bool {
+    match &node.kind {
+        NodeKind::Atom(Expr::Symbol(name)) => name.starts_with(|c: char| c.is_ascii_uppercase()),
+        NodeKind::Combination(items) => is_type(&items[0]),
+        _ => false,
+    }
+}
+
+/// Arguments before the trailing `:keyword value` pairs
+fn positional(args: &[Node]) -> usize {
+    args.iter().position(|arg| symbol(arg).is_some_and(|(name, _)| name.starts_with(':'))).unwrap_or(args.len())
+}
+
+/// A value that is the same on every bar: a non-symbol atom, or an array of them
+fn is_literal(node: &Node) -> bool {
+    match &node.kind {
+        NodeKind::Atom(Expr::Symbol(_)) | NodeKind::Combination(_) => false,
+        NodeKind::Atom(_) => true,
+        NodeKind::Array(items) => items.iter().all(is_literal),
+    }
+}
+
+fn condition_text(value: bool) -> &'static str {
+    if value { "#t" } else { "#f" }
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    if n == 1 { format!("1 {}", noun) } else { format!("{} {}s", n, noun) }
+}
+
+fn plural_supplied(n: usize) -> String {
+    if n == 1 { "1 was".to_string() } else { format!("{} were", n) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_syntax;
+
+    fn messages(src: &str, config: &LintConfig) -> Vec<String> {
+        let nodes = parse_syntax(src).expect("parses");
+        check(&nodes, config).iter().map(|d| format!("{}: {}", d.severity, d.message)).collect()
+    }
+
+    #[test]
+    fn reports_each_lint() {
+        let src = "\
+(inputs (fast 10) (slow Integer 30) (close 1) (fast 5))
+(set spread (- fast slow))
+(set fast 12)
+(set unused 1)
+(if (> spread 0) (buy 10 20) (sell-limit 10))
+(if 1 (buy 1))
+(if #f (buy 1) (newline))
+(plot spread :style line :title \"Spread\")
+(if (> (close -1) close) (buy lots))
+(set stop (- close 2d))";
+        let expected = [
+            "warning: input `close` shadows the builtin `close`",
+            "warning: input `fast` is declared more than once",
+            "warning: `set` overwrites input `fast`",
+            "warning: variable `unused` is never read",
+            "error: `buy` takes 1 argument but 2 were supplied",
+            "error: `sell-limit` takes 2 arguments but 1 was supplied",
+            "error: `if` condition is a constant Integer, not a boolean",
+            "warning: this branch can never run",
+            "error: `(close -1)` reads a bar that has not happened yet",
+            "warning: cannot find `lots` in this script",
+            "warning: variable `stop` is never read",
+            "error: cannot subtract Duration from Integer",
+        ];
+        assert_eq!(messages(src, &LintConfig::default()), expected);
+    }
+
+    #[test]
+    fn reports_unused_vars_and_let_bindings() {
+        let src = "\
+(vars (count Integer 0) (peak Price 0))
+(set count (+ count 1))
+(let ((range (- high low)) (mid (/ (+ high low) 2)) (spare 1))
+  (if (> range 0) (plot (let ((mid close)) mid))))
+(plot count)";
+        let expected = [
+            "warning: variable `peak` is never read",
+            "warning: `let` binding `mid` is never read",
+            "warning: `let` binding `spare` is never read",
+        ];
+        assert_eq!(messages(src, &LintConfig::default()), expected);
+    }
+
+    #[test]
+    fn lint_levels_apply() {
+        let src = "(set x 1)\n(buy y)";
+        let mut config = LintConfig::default();
+        config.set(Lint::UnusedVariable, Level::Allow);
+        config.set(Lint::UnboundSymbol, Level::Deny);
+        assert_eq!(messages(src, &config), ["error: cannot find `y` in this script"]);
+
+        let nodes = parse_syntax(src).unwrap();
+        let report = check(&nodes, &LintConfig::default());
+        assert!(!has_errors(&report));
+        let rendered = report[1].to_string();
+        assert!(rendered.contains("--> <input>:2:6"), "{}", rendered);
+        assert!(rendered.contains("`unbound-symbol` is set to `warn` by default"), "{}", rendered);
+        assert_eq!("look-ahead".parse::<Lint>(), Ok(Lint::LookAhead));
+    }
+}
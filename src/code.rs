@@ -1,6 +1,7 @@
 use bytes::Bytes;
 use lazy_static::lazy_static;
 use nom_locate::LocatedSpan;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::hash::Hash;
 use std::{
@@ -46,10 +47,18 @@ impl Code {
     }
 
     /// Borrow `&'a str` and build the initial `ParserSpan`
-    pub fn span(arc: &Arc<Self>) -> ParserSpan {
+    pub fn span(arc: &Arc<Self>) -> ParserSpan<'_> {
         ParserSpan::new_extra(arc.text.as_str(), arc.clone())
     }
 
+    /// 1-based line and column (in characters) of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+
     fn intern(text: String, file_path: Option<String>) -> Arc<Self> {
         let sha = Bytes::from(Sha256::digest(text.as_bytes()).to_vec());
         let mut atlas = CODE_ATLAS.lock().unwrap();
@@ -82,6 +91,28 @@ impl CodeSpan {
     pub fn new(code: Arc<Code>, start: usize, end: usize) -> Self {
         Self { code, start, end }
     }
+
+    /// `path:line:column` of the start, or `<input>:line:column` for snippets
+    pub fn location(&self) -> String {
+        let (line, column) = self.code.line_col(self.start);
+        format!("{}:{}:{}", self.code.file_path.as_deref().unwrap_or("<input>"), line, column)
+    }
+}
+
+/// Source location of a span, with the 1-based line and column of its start.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpanInfo {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<&CodeSpan> for SpanInfo {
+    fn from(span: &CodeSpan) -> Self {
+        let (line, column) = span.code.line_col(span.start);
+        Self { start: span.start, end: span.end, line, column }
+    }
 }
 
 impl<'a> From<ParserSpan<'a>> for CodeSpan {
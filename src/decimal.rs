@@ -0,0 +1,215 @@
+//! Exact decimal numbers for prices and money.
+//!
+//! A [`Decimal`] is a whole number of billionths, so sums and differences of
+//! prices like `101.25` never drift the way `f64` does. Products and quotients
+//! that need more than nine fractional digits are rounded half to even, as is
+//! [`Decimal::round_to_tick`]. Floats convert through their shortest decimal
+//! spelling, so `101.25` read from a CSV becomes exactly `101.25`.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// Fractional digits kept by every decimal
+pub const SCALE: u32 = 9;
+const ONE: i128 = 10i128.pow(SCALE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Decimal(i128);
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal(0);
+
+    pub fn from_i64(n: i64) -> Self {
+        Decimal(n as i128 * ONE)
+    }
+
+    /// The nearest decimal to a finite float.
+    pub fn from_f64(f: f64) -> Option<Self> {
+        if !f.is_finite() {
+            return None;
+        }
+        // Display never uses an exponent, and gives the shortest digits that read back as `f`.
+        parse(&f.to_string(), true).ok()
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.to_string().parse().expect("decimals print as valid floats")
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Decimal)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Decimal)
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        self.0.checked_mul(other.0).map(|product| Decimal(divide_rounded(product, ONE)))
+    }
+
+    /// `None` on overflow or division by zero.
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.0 == 0 {
+            return None;
+        }
+        self.0.checked_mul(ONE).map(|scaled| Decimal(divide_rounded(scaled, other.0)))
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(Decimal)
+    }
+
+    /// The nearest multiple of `tick`, e.g. `101.237` to `101.25` with a tick of `0.05`.
+    pub fn round_to_tick(self, tick: Self) -> Option<Self> {
+        if tick.0 == 0 {
+            return None;
+        }
+        divide_rounded(self.0, tick.0.abs()).checked_mul(tick.0.abs()).map(Decimal)
+    }
+}
+
+/// Operators panic on overflow, like integer arithmetic in debug builds;
+/// use the `checked_` methods where a script supplies the operands.
+macro_rules! operator {
+    ($trait:ident, $method:ident, $checked:ident) => {
+        impl $trait for Decimal {
+            type Output = Decimal;
+
+            fn $method(self, other: Decimal) -> Decimal {
+                self.$checked(other).expect(concat!("decimal overflow in ", stringify!($method)))
+            }
+        }
+    };
+}
+
+operator!(Add, add, checked_add);
+operator!(Sub, sub, checked_sub);
+operator!(Mul, mul, checked_mul);
+operator!(Div, div, checked_div);
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        self.checked_neg().expect("decimal overflow in neg")
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(n: i64) -> Self {
+        Decimal::from_i64(n)
+    }
+}
+
+/// `n / d` rounded to the nearest integer, halves to even.
+fn divide_rounded(n: i128, d: i128) -> i128 {
+    let (quotient, remainder) = (n / d, n % d);
+    let twice = remainder.unsigned_abs() * 2;
+    let away = twice > d.unsigned_abs() || (twice == d.unsigned_abs() && quotient % 2 != 0);
+    if away {
+        quotient + if (n < 0) != (d < 0) { -1 } else { 1 }
+    } else {
+        quotient
+    }
+}
+
+/// Read `[-+]digits[.digits]`. Extra fractional digits are an error unless `round` is set.
+fn parse(s: &str, round: bool) -> Result<Decimal, String> {
+    let bad = || format!("bad decimal `{}`", s);
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(bad());
+    }
+    if fraction.len() > SCALE as usize && !round {
+        return Err(format!("`{}` has more than {} decimal places", s, SCALE));
+    }
+
+    let out_of_range = || format!("decimal `{}` is out of range", s);
+    let mut units: i128 = whole.parse::<i128>().map_err(|_| out_of_range())?.checked_mul(ONE).ok_or_else(out_of_range)?;
+    let kept = &fraction[..fraction.len().min(SCALE as usize)];
+    if !kept.is_empty() {
+        let padded: i128 = format!("{:0<width$}", kept, width = SCALE as usize).parse().map_err(|_| bad())?;
+        units += padded;
+    }
+    // Round on the dropped digits: above half, or exactly half with an odd last kept digit.
+    let dropped = &fraction[kept.len()..];
+    if let Some(first) = dropped.bytes().next() {
+        let exactly_half = first == b'5' && dropped.bytes().skip(1).all(|b| b == b'0');
+        if first > b'5' || (first == b'5' && !exactly_half) || (exactly_half && units % 2 != 0) {
+            units = units.checked_add(1).ok_or_else(out_of_range)?;
+        }
+    }
+    Ok(Decimal(if negative { -units } else { units }))
+}
+
+/// Parses `[-]digits[.digits]` with at most nine decimal places.
+impl FromStr for Decimal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s, false)
+    }
+}
+
+/// Plain digits, without trailing zeros, and with at least one decimal place.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = self.0.unsigned_abs();
+        let fraction = format!("{:0width$}", units % ONE as u128, width = SCALE as usize);
+        let fraction = fraction.trim_end_matches('0');
+        let sign = if self.0 < 0 { "-" } else { "" };
+        write!(f, "{}{}.{}", sign, units / ONE as u128, if fraction.is_empty() { "0" } else { fraction })
+    }
+}
+
+/// Serialized as a string, since JSON numbers are floats to most readers.
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn arithmetic_is_exact() {
+        let tenth = d("0.1");
+        let sum = (0..10).fold(Decimal::ZERO, |acc, _| acc.checked_add(tenth).unwrap());
+        assert_eq!(sum, d("1"));
+        assert_eq!(d("101.25").checked_mul(d("3")).unwrap().to_string(), "303.75");
+        assert_eq!(d("1").checked_div(d("3")).unwrap().to_string(), "0.333333333");
+        assert_eq!(d("2").checked_div(d("3")).unwrap().to_string(), "0.666666667");
+        assert_eq!(d("-7.5").to_string(), "-7.5");
+        assert_eq!(d("100").to_string(), "100.0");
+        assert_eq!(Decimal::from_f64(0.1 + 0.2).unwrap().to_string(), "0.3");
+        assert_eq!(Decimal::from_f64(101.25).unwrap().to_f64(), 101.25);
+        assert!(d("1").checked_div(Decimal::ZERO).is_none());
+        assert!("1.0000000001".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn rounds_to_ticks_half_to_even() {
+        assert_eq!(d("101.237").round_to_tick(d("0.05")), Some(d("101.25")));
+        assert_eq!(d("101.225").round_to_tick(d("0.01")), Some(d("101.22")));
+        assert_eq!(d("101.235").round_to_tick(d("0.01")), Some(d("101.24")));
+        assert_eq!(d("-3.3").round_to_tick(d("0.25")), Some(d("-3.25")));
+        assert_eq!(d("1").round_to_tick(Decimal::ZERO), None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Error reports that point into the source.
+//!
+//! A [`Diagnostic`] is a message with any number of labeled spans and trailing
+//! notes. [`Diagnostic::render`] lays it out the way rustc does: a header,
+//! then each labeled source line with a gutter of line numbers and `^` (or `-`
+//! for secondary labels) under the spanned text, then the notes. Colors are
+//! optional so the same report works on a terminal, in a log or in a test.
+
+use owo_colors::Style;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::Arc;
+
+use crate::code::{Code, CodeSpan};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: CodeSpan,
+    pub message: String,
+    /// Primary labels are underlined with `^`, the rest with `-`.
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    /// `note: ...` and `help: ...` lines shown under the snippet
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self { severity, message: message.into(), labels: Vec::new(), notes: Vec::new() }
+    }
+
+    /// Point at the main culprit; the report's location is its first primary label.
+    pub fn with_label(mut self, span: CodeSpan, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: true });
+        self
+    }
+
+    /// Point at something related, like where an unclosed list was opened.
+    pub fn with_secondary(mut self, span: CodeSpan, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: false });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(format!("note: {}", note.into()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.notes.push(format!("help: {}", help.into()));
+        self
+    }
+
+    /// The span the report is about: the first primary label, else the first label.
+    pub fn primary_span(&self) -> Option<&CodeSpan> {
+        self.labels.iter().find(|l| l.primary).or(self.labels.first()).map(|l| &l.span)
+    }
+
+    /// Lay the report out as text, with ANSI colors if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let paint = |style: Style, text: &str| {
+            if color { style.style(text).to_string() } else { text.to_string() }
+        };
+        let severity_style = match self.severity {
+            Severity::Error => Style::new().red().bold(),
+            Severity::Warning => Style::new().yellow().bold(),
+        };
+        let gutter_style = Style::new().blue().bold();
+
+        let mut out = format!(
+            "{}{}",
+            paint(severity_style, &self.severity.to_string()),
+            paint(Style::new().bold(), &format!(": {}", self.message))
+        );
+
+        let width = self.labels.iter().map(|l| line_of(&l.span).to_string().len()).max().unwrap_or(0);
+        let pad = " ".repeat(width);
+        let bar = paint(gutter_style, "|");
+
+        // One block per source file, the one holding the primary label first.
+        let mut files: Vec<Arc<Code>> = Vec::new();
+        let primary = self.primary_span().map(|span| span.code.clone());
+        for code in primary.into_iter().chain(self.labels.iter().map(|l| l.span.code.clone())) {
+            if !files.iter().any(|c| Arc::ptr_eq(c, &code)) {
+                files.push(code);
+            }
+        }
+
+        for (i, code) in files.iter().enumerate() {
+            let mut labels: Vec<&Label> = self.labels.iter().filter(|l| Arc::ptr_eq(&l.span.code, code)).collect();
+            let first = labels.iter().find(|l| l.primary).unwrap_or(&labels[0]).span.location();
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            out.push_str(&format!("\n{}{} {}\n{} {}", pad, paint(gutter_style, arrow), first, pad, bar));
+
+            labels.sort_by_key(|l| (l.span.start, !l.primary));
+            let mut previous_line = None;
+            for label in &labels {
+                let (line, text, column) = source_line(&label.span);
+                if previous_line != Some(line) {
+                    if previous_line.is_some_and(|p| line > p + 1) {
+                        out.push_str(&format!("\n{}", paint(gutter_style, "...")));
+                    }
+                    out.push_str(&format!("\n{} {} {}", paint(gutter_style, &format!("{:>width$}", line)), bar, text));
+                    previous_line = Some(line);
+                }
+
+                // Keep tabs in the padding so the marks line up with the source.
+                let indent: String = text.chars().take(column).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                let len = text.chars().skip(column).take(marked_chars(&label.span)).count().max(1);
+                let (mark, style) = if label.primary { ("^", severity_style) } else { ("-", gutter_style) };
+                let mut marks = mark.repeat(len);
+                if !label.message.is_empty() {
+                    marks = format!("{} {}", marks, label.message);
+                }
+                out.push_str(&format!("\n{} {} {}{}", pad, bar, indent, paint(style, &marks)));
+            }
+        }
+
+        for note in &self.notes {
+            let (kind, text) = note.split_once(": ").unwrap_or(("note", note));
+            out.push_str(&format!("\n{} {} {}: {}", pad, paint(gutter_style, "="), paint(Style::new().bold(), kind), text));
+        }
+        out
+    }
+}
+
+/// A bare message, for errors that have no source to point at.
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Self::error(message)
+    }
+}
+
+/// Plain text, as it would appear in a log.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(false))
+    }
+}
+
+/// Whether reports written to stderr should be colored: only on a terminal,
+/// and never when `NO_COLOR` is set.
+pub fn use_color() -> bool {
+    std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+fn line_of(span: &CodeSpan) -> usize {
+    span.code.line_col(span.start).0
+}
+
+/// The 1-based line number where `span` starts, that line's text and the
+/// span's 0-based column on it, in characters.
+fn source_line(span: &CodeSpan) -> (usize, &str, usize) {
+    let text = span.code.text.as_str();
+    let start = span.start.min(text.len());
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    let (line, column) = span.code.line_col(start);
+    (line, text[line_start..line_end].trim_end_matches('\r'), column - 1)
+}
+
+/// How many characters of the span's first line it covers.
+fn marked_chars(span: &CodeSpan) -> usize {
+    let text = span.code.text.as_str();
+    let start = span.start.min(text.len());
+    let line_end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+    text[start..span.end.clamp(start, line_end)].chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_labels_and_notes() {
+        let code = Code::from_snippet("(set x 1)\n(if (> x 0)\n\n    (buy x)\n  (sell\tx))");
+        let at = |needle: &str| {
+            let start = code.text.find(needle).unwrap();
+            CodeSpan::new(code.clone(), start, start + needle.len())
+        };
+        let diagnostic = Diagnostic::error("cannot sell a position you do not hold")
+            .with_label(at("sell\tx"), "sells here")
+            .with_secondary(at("(buy x)"), "only bought here")
+            .with_secondary(at("(if"), "")
+            .with_note("orders expire after one bar");
+
+        let expected = [
+            "error: cannot sell a position you do not hold",
+            " --> <input>:5:4",
+            "  |",
+            "2 | (if (> x 0)",
+            "  | ---",
+            "...",
+            "4 |     (buy x)",
+            "  |     ------- only bought here",
+            "5 |   (sell\tx))",
+            "  |    ^^^^^^ sells here",
+            "  = note: orders expire after one bar",
+        ];
+        assert_eq!(diagnostic.to_string(), expected.join("\n"));
+
+        let colored = diagnostic.render(true);
+        assert!(colored.contains("\u{1b}[") && colored.contains("sells here"));
+    }
+}
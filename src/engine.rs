@@ -0,0 +1,295 @@
+//! Bar engine: evaluates a program once per bar and routes the orders it emits
+//! to the simulated [`Broker`].
+
+use crate::ast::Expr;
+use crate::bars::{Bar, DataStream, Resampler};
+use crate::broker::{Broker, BrokerConfig, Order};
+use crate::interpreter::{EvalError, Interpreter};
+use indexmap::IndexMap;
+use std::sync::Arc;
+
+/// What the program produced on one bar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepOutput {
+    /// The value of each top-level form.
+    pub values: Vec<Expr>,
+    /// Orders handed to the broker.
+    pub orders: Vec<Order>,
+}
+
+pub struct BarEngine {
+    pub interpreter: Interpreter,
+    pub broker: Broker,
+    /// Equity held outside this engine's broker, added to `account-equity`; a portfolio's other symbols
+    pub external_equity: f64,
+    feeds: Vec<Feed>,
+}
+
+/// Where a data stream's completed bars come from. A bar is handed to the
+/// script only once it has closed, so a higher timeframe never shows a bar
+/// that is still forming.
+enum Feed {
+    /// Completes a bar when the first stream moves into the next period.
+    Resampled(Resampler),
+    /// A loaded bar closes when the next one opens; the last one after the same gap again.
+    Bars { bars: Arc<[Bar]>, next: usize },
+}
+
+impl Feed {
+    fn completed(&mut self, bar: &Bar) -> Vec<Bar> {
+        match self {
+            Feed::Resampled(resampler) => resampler.push(bar).into_iter().collect(),
+            Feed::Bars { bars, next } => {
+                let start = *next;
+                while let Some(close) = close_time(bars, *next)
+                    && close <= bar.timestamp.0
+                {
+                    *next += 1;
+                }
+                bars[start..*next].to_vec()
+            }
+        }
+    }
+}
+
+fn close_time(bars: &[Bar], index: usize) -> Option<chrono::DateTime<chrono::Utc>> {
+    match (bars.get(index), bars.get(index + 1)) {
+        (Some(_), Some(following)) => Some(following.timestamp.0),
+        (Some(last), None) if index > 0 => Some(last.timestamp.0 + (last.timestamp.0 - bars[index - 1].timestamp.0)),
+        _ => None,
+    }
+}
+
+impl BarEngine {
+    pub fn new(config: BrokerConfig) -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.calendar = config.calendar.clone();
+        interpreter.add_data_streams(config.data_streams.len());
+        let feeds = config
+            .data_streams
+            .iter()
+            .map(|stream| match stream {
+                DataStream::Resampled(period) => Feed::Resampled(Resampler::with_period(*period)),
+                DataStream::Bars(bars) => Feed::Bars { bars: bars.clone(), next: 0 },
+            })
+            .collect();
+        Self { interpreter, broker: Broker::new(config), external_equity: 0.0, feeds }
+    }
+
+    /// Override the defaults of the program's declared `inputs`.
+    pub fn with_inputs(mut self, inputs: IndexMap<String, Expr>) -> Self {
+        self.interpreter.inputs = inputs;
+        self
+    }
+
+    /// Run `program` over every bar in order.
+    pub fn run(&mut self, program: &[Expr], bars: &[Bar]) -> Result<(), EvalError> {
+        for (index, bar) in bars.iter().enumerate() {
+            self.step(program, index, bar)?;
+        }
+        Ok(())
+    }
+
+    /// Process a single bar: fill orders left over from the previous bar, bind the
+    /// bar's fields, evaluate the program and queue whatever it ordered.
+    pub fn step(&mut self, program: &[Expr], index: usize, bar: &Bar) -> Result<StepOutput, EvalError> {
+        let values = self.evaluate(program, index, bar)?;
+        let orders = std::mem::take(&mut self.interpreter.orders);
+        self.settle(&orders, index, bar);
+        Ok(StepOutput { values, orders })
+    }
+
+    /// The first half of [`step`](Self::step): fill pending orders, bind the bar
+    /// and evaluate the program, leaving its orders in `interpreter.orders`.
+    pub fn evaluate(&mut self, program: &[Expr], index: usize, bar: &Bar) -> Result<Vec<Expr>, EvalError> {
+        self.broker.on_bar(index, bar);
+
+        for (i, feed) in self.feeds.iter_mut().enumerate() {
+            for completed in feed.completed(bar) {
+                self.interpreter.push_bar(i + 2, completed);
+            }
+        }
+        self.interpreter.bind_bar(index, bar);
+        self.interpreter.account = self.broker.account(bar.close);
+        self.interpreter.account.equity += self.external_equity;
+        self.interpreter.eval_program(program)
+    }
+
+    /// The second half of [`step`](Self::step): queue `orders`, hand over the script's
+    /// risk stops and mark equity at the bar's close.
+    pub fn settle(&mut self, orders: &[Order], index: usize, bar: &Bar) {
+        self.broker.risk = self.interpreter.risk;
+        for order in orders {
+            self.broker.submit(order.clone(), index, bar);
+        }
+        self.broker.mark(index, bar);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::{parse_csv, DataStream};
+    use crate::broker::{Commission, Direction};
+    use crate::calendar::Calendar;
+    use crate::parser::parse_snippet;
+
+    const BARS: &str = "\
+2024-01-01,10,11,9,10.5
+2024-01-02,10.5,12,10,11.5
+2024-01-03,11.5,12,10.5,11
+2024-01-04,11,11.5,10,10.2
+2024-01-05,10,10.5,9.5,10.4
+";
+
+    #[test]
+    fn runs_script_against_broker() {
+        let program = parse_snippet("(if (> close open) (buy 10) (sell 10))").unwrap().value;
+        let bars = parse_csv(BARS).unwrap();
+        let config = BrokerConfig { commission: Commission::PerShare(0.01), ..BrokerConfig::default() };
+        let mut engine = BarEngine::new(config);
+        engine.run(&[program], &bars).unwrap();
+
+        let fills: Vec<(usize, i64, f64)> = engine
+            .broker
+            .fills()
+            .iter()
+            .map(|f| (f.bar_index, f.quantity, f.price))
+            .collect();
+        assert_eq!(fills, vec![(1, 10, 10.5), (2, 10, 11.5), (3, 10, 11.0), (4, 10, 10.0)]);
+        // The second buy stacks on the first, the following sells unwind both at a loss.
+        assert_eq!(engine.broker.position(), 0);
+        let trades = engine.broker.trades();
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|t| t.direction == Direction::Long));
+        assert!((trades.iter().map(|t| t.pnl).sum::<f64>() - (-10.4)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inputs_take_overrides() {
+        let program = parse_snippet("(inputs (size 10) (offset Float 0.5))").unwrap().value;
+        let bars = parse_csv(BARS).unwrap();
+        let mut engine = BarEngine::new(BrokerConfig::default())
+            .with_inputs(IndexMap::from([("size".to_string(), Expr::Integer(25))]));
+        engine.run(&[program], &bars).unwrap();
+        assert_eq!(engine.interpreter.env.get("size"), Some(Expr::Integer(25)));
+        assert_eq!(engine.interpreter.env.get("offset"), Some(Expr::Float(0.5)));
+    }
+
+    #[test]
+    fn collects_plots_per_bar() {
+        let program = crate::parser::parse_program(
+            r#"(plot-candles)
+               (plot-overlay (- close 1) :title "Lower" :color "black")
+               (plot volume :title "Volume" :style "histogram")"#,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|f| f.value)
+        .collect::<Vec<_>>();
+        let bars = parse_csv(BARS).unwrap();
+        let mut engine = BarEngine::new(BrokerConfig::default());
+        engine.run(&program, &bars).unwrap();
+
+        let plots = &engine.interpreter.plots;
+        assert_eq!(plots.charts.keys().collect::<Vec<_>>(), vec!["Price", "Volume"]);
+        let price = &plots.charts["Price"];
+        assert_eq!(price.series.len(), 2);
+        match &price.series[1].data {
+            crate::plot::SeriesData::Line(points) => {
+                assert_eq!(points.len(), 5);
+                assert_eq!(points[4].index, 4);
+                assert_eq!(points[4].value, 9.4);
+                assert_eq!(points[4].time, Some(bars[4].timestamp.clone()));
+            }
+            other => panic!("expected a line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_the_account_and_sets_stops() {
+        let program = [
+            parse_snippet("(if (= bar-index 0) (begin (buy 10) (set-stop-loss 7)))").unwrap().value,
+            parse_snippet("[(position) (entry-price) (open-position-profit) (account-equity) (margin-used)]").unwrap().value,
+        ];
+        let bars = parse_csv(BARS).unwrap();
+        let mut engine = BarEngine::new(BrokerConfig { margin: 0.5, ..BrokerConfig::default() });
+        let rows: Vec<String> = bars
+            .iter()
+            .enumerate()
+            .map(|(index, bar)| engine.step(&program, index, bar).unwrap().values[1].to_string())
+            .collect();
+        // Filled at 10.5 on the second bar; the stop 0.7 a share below is hit when the last bar trades down to 9.5
+        assert_eq!(rows[1], "[#quantity:10 #price:10.5 #money:10.0 #money:100010.0 #money:57.5]");
+        assert_eq!(rows[4], "[#quantity:0 () #money:0.0 #money:99993.0 #money:0.0]");
+        assert_eq!(engine.broker.trades()[0].exit_price, 9.8);
+    }
+
+    #[test]
+    fn binds_exchange_time_and_sessions() {
+        let program = [parse_snippet("[Date Time (DayOfWeek Date) (session-open?) (bars-since-session-open)]").unwrap().value];
+        let times = ["2024-07-02T13:00:00Z", "2024-07-02T13:30:00Z", "2024-07-02T14:00:00Z", "2024-07-03T13:30:00Z"];
+        let bars = parse_csv(&times.map(|t| format!("{},1,1,1,1\n", t)).concat()).unwrap();
+        let config = BrokerConfig { calendar: Calendar::exchange("nyse").unwrap(), ..BrokerConfig::default() };
+        let mut engine = BarEngine::new(config);
+        let rows: Vec<String> = bars
+            .iter()
+            .enumerate()
+            .map(|(index, bar)| engine.step(&program, index, bar).unwrap().values[0].to_string())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "[1240702 900 2 #f ()]",
+                "[1240702 930 2 #t 0]",
+                "[1240702 1000 2 #t 1]",
+                "[1240703 930 3 #t 0]",
+            ]
+        );
+        let exchange_time = parse_snippet("(to-exchange-time #2024-01-02T14:30:00Z)").unwrap().value;
+        assert_eq!(engine.interpreter.eval(&exchange_time).unwrap().to_string(), "#2024-01-02T09:30:00Z");
+    }
+
+    #[test]
+    fn higher_timeframes_show_only_closed_bars() {
+        let program = [parse_snippet("[(close 0 :data 2) (high 0 :data 2) (close 1) (open 0 :data 3)]").unwrap().value];
+        let minutes = parse_csv(
+            "2024-01-02T09:58:00Z,10,11,9,10\n2024-01-02T09:59:00Z,10,12,10,11\n2024-01-02T10:00:00Z,11,13,8,9\n",
+        )
+        .unwrap();
+        let daily = parse_csv("2024-01-01,1,1,1,1\n2024-01-02,2,2,2,2\n").unwrap();
+        let config = BrokerConfig {
+            data_streams: vec!["1h".parse().unwrap(), DataStream::Bars(daily.into())],
+            ..BrokerConfig::default()
+        };
+        let mut engine = BarEngine::new(config);
+        let rows: Vec<String> = minutes
+            .iter()
+            .enumerate()
+            .map(|(index, bar)| engine.step(&program, index, bar).unwrap().values[0].to_string())
+            .collect();
+        // The 09:00 hour closes when the 10:00 bar opens; the 2024-01-02 daily bar is still forming.
+        assert_eq!(
+            rows,
+            [
+                "[() () () #price:1.0]",
+                "[() () #price:10.0 #price:1.0]",
+                "[#price:11.0 #price:12.0 #price:11.0 #price:1.0]",
+            ]
+        );
+
+        let ahead = parse_snippet("(close -1)").unwrap().value;
+        assert!(engine.interpreter.eval(&ahead).unwrap_err().contains("has not happened yet"));
+        let missing = parse_snippet("(close 0 :data 4)").unwrap().value;
+        assert!(engine.interpreter.eval(&missing).is_err());
+    }
+
+    #[test]
+    fn reports_bar_of_failing_eval() {
+        let program = parse_snippet("(buy close)").unwrap().value;
+        let bars = parse_csv(BARS).unwrap();
+        let err = BarEngine::new(BrokerConfig::default()).run(&[Expr::Nil, program], &bars).unwrap_err();
+        assert_eq!((err.form, err.bar), (1, Some(0)));
+        assert!(err.to_string().starts_with("bar 0:"), "{}", err);
+    }
+}
@@ -0,0 +1,222 @@
+//! Canonical layout for scripts, as printed by `stonkscheme fmt`.
+//!
+//! A form that fits within the width stays on one line. One that does not
+//! keeps its head and first argument on the opening line and puts each further
+//! argument on its own line, aligned under the first, with any `:keyword` kept
+//! beside its value. Forms with a rule, like `define` or `let`, instead keep
+//! that many arguments on the opening line and indent the rest as a body.
+//! Atoms keep their source spelling, closing parentheses trail the last item,
+//! comments stay where they were, and a blank line between items survives as
+//! one blank line.
+
+use indexmap::IndexMap;
+use std::sync::Arc;
+
+use crate::ast::Expr;
+use crate::code::Code;
+use crate::parser::{parse_with_comments, ParseError, Recovered};
+use crate::syntax::{Node, NodeKind};
+
+#[derive(Debug, Clone)]
+pub struct FormatConfig {
+    /// Spaces a body is indented by
+    pub indent: usize,
+    /// Lines longer than this are broken where possible
+    pub width: usize,
+    /// Forms laid out as a body, with how many arguments stay on their first line
+    pub rules: IndexMap<String, usize>,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        let rules = [("define", 1), ("let", 1), ("loop", 1), ("if", 1), ("set", 1), ("begin", 0), ("inputs", 0), ("vars", 0)];
+        Self {
+            indent: 2,
+            width: 100,
+            rules: rules.into_iter().map(|(name, n)| (name.to_string(), n)).collect(),
+        }
+    }
+}
+
+/// The formatted source, or every parse error if it does not parse.
+pub fn format_code(code: &Arc<Code>, config: &FormatConfig) -> Result<String, Vec<ParseError>> {
+    let Recovered { nodes, errors } = parse_with_comments(code);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let formatter = Formatter { config, text: &code.text };
+    let mut out = String::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            formatter.separate(&mut out, &nodes[i - 1], node, 0);
+        }
+        out.push_str(&formatter.layout(node, 0, 0));
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+struct Formatter<'a> {
+    config: &'a FormatConfig,
+    text: &'a str,
+}
+
+impl Formatter<'_> {
+    /// Lay `node` out starting at `column`, with `closers` closing delimiters
+    /// still to follow it on its last line.
+    fn layout(&self, node: &Node, column: usize, closers: usize) -> String {
+        if let Some(flat) = self.flat(node)
+            && column + flat.chars().count() + closers <= self.config.width
+        {
+            return flat;
+        }
+        match &node.kind {
+            NodeKind::Atom(_) => self.source(node).to_string(),
+            NodeKind::Combination(items) => self.list(('(', ')'), items, column, closers),
+            NodeKind::Array(items) => self.list(('[', ']'), items, column, closers),
+        }
+    }
+
+    /// The node on one line, unless it holds a comment or a multi-line string.
+    fn flat(&self, node: &Node) -> Option<String> {
+        let text = match &node.kind {
+            NodeKind::Atom(Expr::Comment(_)) => return None,
+            NodeKind::Atom(_) => self.source(node).to_string(),
+            NodeKind::Combination(items) => format!("({})", self.flat_items(items)?),
+            NodeKind::Array(items) => format!("[{}]", self.flat_items(items)?),
+        };
+        (!text.contains('\n')).then_some(text)
+    }
+
+    fn flat_items(&self, items: &[Node]) -> Option<String> {
+        Some(items.iter().map(|item| self.flat(item)).collect::<Option<Vec<_>>>()?.join(" "))
+    }
+
+    fn list(&self, (open, close): (char, char), items: &[Node], column: usize, closers: usize) -> String {
+        let head = items.first().filter(|_| open == '(');
+        let (on_first_line, body_column) = match head.map(|h| &h.kind) {
+            Some(NodeKind::Atom(Expr::Symbol(name))) if self.config.rules.contains_key(&name.0) => {
+                (1 + self.config.rules[&name.0], column + self.config.indent)
+            }
+            Some(NodeKind::Atom(atom)) if !matches!(atom, Expr::Comment(_)) => {
+                let head = head.expect("matched above");
+                (2, column + 2 + self.source(head).chars().count())
+            }
+            _ => (1, column + 1),
+        };
+
+        let mut out = open.to_string();
+        for (i, item) in items.iter().enumerate() {
+            let closers = if i + 1 == items.len() { closers + 1 } else { 0 };
+            if i == 0 {
+                out.push_str(&self.layout(item, column + 1, closers));
+                continue;
+            }
+            let previous = &items[i - 1];
+            let same_line = (i < on_first_line && !out.contains('\n')) || is_keyword(previous);
+            if same_line && !is_comment(previous) && !is_comment(item) {
+                out.push(' ');
+                let at = match out.rfind('\n') {
+                    Some(newline) => out[newline + 1..].chars().count(),
+                    None => column + out.chars().count(),
+                };
+                out.push_str(&self.layout(item, at, closers));
+            } else {
+                self.separate(&mut out, previous, item, body_column);
+                let at = if is_trailing_comment(self.text, previous, item) { 0 } else { body_column };
+                out.push_str(&self.layout(item, at, closers));
+            }
+        }
+        if items.last().is_some_and(is_comment) {
+            out.push('\n');
+            out.push_str(&" ".repeat(column));
+        }
+        out.push(close);
+        out
+    }
+
+    /// What goes between two items: a space before a comment on the same
+    /// source line, else a line break (two if the source had a blank line)
+    /// and the indentation.
+    fn separate(&self, out: &mut String, previous: &Node, next: &Node, column: usize) {
+        if is_trailing_comment(self.text, previous, next) {
+            out.push(' ');
+            return;
+        }
+        let gap = &self.text[previous.span.end..next.span.start];
+        out.push_str(if gap.matches('\n').count() > 1 { "\n\n" } else { "\n" });
+        out.push_str(&" ".repeat(column));
+    }
+
+    fn source(&self, node: &Node) -> &str {
+        self.text[node.span.start..node.span.end].trim_end_matches('\r')
+    }
+}
+
+fn is_comment(node: &Node) -> bool {
+    matches!(node.kind, NodeKind::Atom(Expr::Comment(_)))
+}
+
+/// `:name`, which keeps its value on the same line
+fn is_keyword(node: &Node) -> bool {
+    matches!(&node.kind, NodeKind::Atom(Expr::Symbol(name)) if name.0.starts_with(':'))
+}
+
+/// A comment that shares a source line with what comes before it
+fn is_trailing_comment(text: &str, previous: &Node, comment: &Node) -> bool {
+    is_comment(comment) && !text[previous.span.end..comment.span.start].contains('\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+
+    fn format(src: &str, config: &FormatConfig) -> String {
+        format_code(&Code::from_snippet(src), config).expect("parses")
+    }
+
+    #[test]
+    fn lays_out_forms_and_keeps_comments() {
+        let src = "; header\n\n\n(inputs\n\t(size 10)\n\t)\n(define (f x) ; doc\n\t(let ((y (* x 2)))\n\t\t(if (> y 1e-7)\n y\n\t0)))\n(buy   size)";
+        let narrow = FormatConfig { width: 20, ..FormatConfig::default() };
+        let expected = [
+            "; header",
+            "",
+            "(inputs (size 10))",
+            "(define (f x) ; doc",
+            "  (let ((y (* x 2)))",
+            "    (if (> y 1e-7)",
+            "      y",
+            "      0)))",
+            "(buy size)",
+            "",
+        ];
+        assert_eq!(format(src, &narrow), expected.join("\n"));
+
+        let call = "(plot (+ (* c1 average) (* c2 previous)) :title \"Smooth\" :color \"steelblue\")\n";
+        assert_eq!(
+            format(call, &FormatConfig { width: 30, ..FormatConfig::default() }),
+            "(plot (+ (* c1 average)\n         (* c2 previous))\n      :title \"Smooth\"\n      :color \"steelblue\")\n"
+        );
+        assert_eq!(format("(vars\n  (a 1) ; first\n  )", &FormatConfig::default()), "(vars\n  (a 1) ; first\n)\n");
+        assert!(format_code(&Code::from_snippet("(set x"), &narrow).is_err());
+    }
+
+    #[test]
+    fn formatting_is_stable_and_keeps_meaning() {
+        let src = include_str!("../examples/supersmoother.scm");
+        for width in [20, 40, 100] {
+            let config = FormatConfig { width, ..FormatConfig::default() };
+            let once = format(src, &config);
+            assert_eq!(format(&once, &config), once, "width {}", width);
+
+            let values = |src: &str| parse_program(src).unwrap().into_iter().map(|f| f.value).collect::<Vec<_>>();
+            assert_eq!(values(&once), values(src));
+            assert_eq!(once.matches(';').count(), src.matches(';').count());
+            assert!(!once.contains('\t'));
+        }
+    }
+}
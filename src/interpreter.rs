@@ -1,19 +1,122 @@
-use crate::ast::Expr;
+use crate::ast::{Duration, Expr, Timestamp, Unit};
+use crate::bars::Bar;
+use crate::broker::{Account, Order, OrderKind, RiskStops, Side};
+use crate::calendar::Calendar;
+use crate::code::Spanned;
+use crate::decimal::Decimal;
+use crate::diagnostics::Diagnostic;
+use crate::plot::{Candle, PlotPoint, Plots, SeriesData};
+use crate::types::{arithmetic_type, Type};
+use chrono::{Datelike, NaiveDate, Timelike};
+use dashmap::DashMap;
 use indexmap::IndexMap;
+use std::cmp::Ordering;
 use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
 use std::sync::{Arc, Mutex};
 
+/// Special forms and builtin procedures
+pub const BUILTINS: &[&str] = &[
+    "set", "get", "if", "inputs", "vars", "let", "begin", "car", "cdr", "cons", "+", "-", "*", "/", "<", ">", "<=", ">=", "=", "not",
+    "write", "display", "newline", "plot", "plot-overlay", "plot-candles", "buy", "sell", "buy-limit", "sell-limit", "buy-stop", "sell-stop", "round-to-tick", "decimal", "float",
+    "to-exchange-time", "session-open?", "bars-since-session-open", "DayOfWeek", "position", "entry-price",
+    "open-position-profit", "account-equity", "margin-used", "set-stop-loss", "set-profit-target", "set-breakeven",
+    "set-trailing-stop", "set-percent-trailing",
+];
+
+/// Names bound to the current bar by [`Interpreter::bind_bar`]
+pub const BAR_FIELDS: &[&str] =
+    &["open", "high", "low", "close", "volume", "vwap", "time", "bar-index", "Date", "Time"];
+
+/// Bar fields that read back through history, as `(close 3)` or `(close 0 :data 2)`
+pub const SERIES_FIELDS: &[&str] = &["open", "high", "low", "close", "volume", "vwap", "time"];
+
+/// Usage and a one-line summary of every entry in [`BUILTINS`], for hover and help
+pub const SIGNATURES: &[(&str, &str, &str)] = &[
+    ("set", "(set name value)", "Bind `name` to the value of `value`."),
+    ("get", "(get name)", "The value bound to `name`, or `()`."),
+    ("if", "(if condition then [else])", "Evaluate `then` if `condition` is `#t`, else `else`."),
+    ("inputs", "(inputs (name [Type] default) ...)", "Declare tunable parameters with their defaults."),
+    ("vars", "(vars (name [Type] initial) ...)", "Declare variables that keep their values from bar to bar."),
+    ("let", "(let ((name value) ...) body ...)", "Evaluate `body` with each `name` bound to its `value`."),
+    ("begin", "(begin form ...)", "Evaluate each form in turn and return the last value."),
+    ("car", "(car list)", "The first element of a list."),
+    ("cdr", "(cdr list)", "A list without its first element."),
+    ("cons", "(cons list)", "A list built from another."),
+    (
+        "+",
+        "(+ number ...)",
+        "The sum of the numbers, or a timestamp moved by durations.",
+    ),
+    (
+        "-",
+        "(- number ...)",
+        "The first number minus the rest, the negation of one, or the time between timestamps.",
+    ),
+    (
+        "*",
+        "(* number ...)",
+        "The product of the numbers, or a duration scaled by integers.",
+    ),
+    (
+        "/",
+        "(/ number ...)",
+        "The first number divided by the rest, the reciprocal of one, or a duration divided.",
+    ),
+    ("<", "(< a b ...)", "Whether the numbers are strictly increasing."),
+    (">", "(> a b ...)", "Whether the numbers are strictly decreasing."),
+    ("<=", "(<= a b ...)", "Whether the numbers are non-decreasing."),
+    (">=", "(>= a b ...)", "Whether the numbers are non-increasing."),
+    ("=", "(= a b ...)", "Whether the numbers are all equal."),
+    ("not", "(not boolean)", "The negation of a boolean."),
+    ("write", "(write value)", "Print a value in a form that reads back."),
+    ("display", "(display value)", "Print a value, with strings unquoted."),
+    ("newline", "(newline)", "Print a line break."),
+    ("plot", "(plot value [:title t] [:style s] [:color c] [:x-label l] [:y-label l])", "Plot a value on its own chart."),
+    ("plot-overlay", "(plot-overlay value [:on chart] [:title t] [:color c])", "Plot a value over the price chart."),
+    ("plot-candles", "(plot-candles [open high low close] [:on chart])", "Plot the bar's OHLC as a candle."),
+    ("buy", "(buy quantity)", "Buy at the market on the next fill."),
+    ("sell", "(sell quantity)", "Sell at the market on the next fill."),
+    ("buy-limit", "(buy-limit quantity price)", "Buy at `price` or lower."),
+    ("sell-limit", "(sell-limit quantity price)", "Sell at `price` or higher."),
+    ("buy-stop", "(buy-stop quantity price)", "Buy once the price rises to `price`."),
+    ("sell-stop", "(sell-stop quantity price)", "Sell once the price falls to `price`."),
+    ("round-to-tick", "(round-to-tick price tick)", "The multiple of `tick` nearest `price`, as a decimal."),
+    ("decimal", "(decimal number)", "A number as an exact decimal, from the float's shortest spelling."),
+    ("float", "(float number)", "A number as a float."),
+    ("to-exchange-time", "(to-exchange-time timestamp)", "The timestamp moved to read as the exchange's wall clock."),
+    ("session-open?", "(session-open? [timestamp])", "Whether the exchange is in session at the bar's time or `timestamp`."),
+    ("bars-since-session-open", "(bars-since-session-open)", "Bars since the session's first bar, or `()` outside a session."),
+    ("DayOfWeek", "(DayOfWeek date)", "The weekday of a `Date` or timestamp, from 0 for Sunday to 6 for Saturday."),
+    ("position", "(position)", "Shares held, negative when short and 0 when flat."),
+    ("entry-price", "(entry-price)", "Average entry price of the open position, or `()` when flat."),
+    ("open-position-profit", "(open-position-profit)", "Unrealized profit of the open position at the bar's close."),
+    ("account-equity", "(account-equity)", "Cash plus open positions at the bar's close."),
+    ("margin-used", "(margin-used)", "Margin held against the open position."),
+    ("set-stop-loss", "(set-stop-loss amount)", "Exit once the position has lost `amount`; 0 turns it off."),
+    ("set-profit-target", "(set-profit-target amount)", "Exit once the position has made `amount`; 0 turns it off."),
+    ("set-breakeven", "(set-breakeven floor)", "Once the position has made `floor`, exit at the entry price; 0 turns it off."),
+    ("set-trailing-stop", "(set-trailing-stop amount)", "Exit once the position gives back `amount` from its best."),
+    (
+        "set-percent-trailing",
+        "(set-percent-trailing floor percent)",
+        "Once the position has made `floor`, exit after giving back `percent` of its best profit.",
+    ),
+];
+
 #[derive(Clone)]
 pub struct Env {
     pub scope_stack: VecDeque<Arc<Mutex<Scope>>>,
 }
 
 /// Internal representation of numbers
+#[derive(Clone, Copy)]
 enum Number {
     Zero,
-    Unsigned(u64),
     Signed(i64),
     Float(f64),
+    Decimal(Decimal),
 }
 
 impl Env {
@@ -39,30 +142,288 @@ impl Env {
         }
         None
     }
+    /// Every bound name, innermost scope first, without duplicates
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for scope in self.scope_stack.iter().rev() {
+            for key in scope.lock().unwrap().0.keys() {
+                if !names.contains(key) {
+                    names.push(key.clone());
+                }
+            }
+        }
+        names
+    }
+
     pub fn set(&mut self, key: String, value: Expr) {
         if let Some(scope) = self.scope_stack.back_mut() {
             let mut scope = scope.lock().unwrap();
             scope.0.insert(key, value);
         }
     }
+
+    /// Rebind `key` in the innermost scope that has it, so `set` inside a `let` can
+    /// update a variable bound outside it; a new name is bound in the innermost scope
+    pub fn assign(&mut self, key: String, value: Expr) {
+        let scope = self.scope_stack.iter().rev().find(|scope| scope.lock().unwrap().0.contains_key(&key));
+        if let Some(scope) = scope.or(self.scope_stack.back()) {
+            scope.lock().unwrap().0.insert(key, value);
+        }
+    }
 }
 
 pub struct Scope(IndexMap<String, Expr>);
 
+impl Scope {
+    /// Bindings in the order they were first made
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Expr)> {
+        self.0.iter()
+    }
+}
+
 pub struct Interpreter {
     pub env: Env,
+    /// Orders emitted since the bar engine last drained them
+    pub orders: Vec<Order>,
+    /// Values that replace the defaults of declared `inputs`
+    pub inputs: IndexMap<String, Expr>,
+    /// Charts built by `plot`, `plot-overlay` and `plot-candles`
+    pub plots: Plots,
+    /// Index and time of the bar being evaluated, set by the bar engine
+    pub current_bar: Option<(usize, Timestamp)>,
+    /// Exchange sessions and time zone, for `Date`, `Time` and the session builtins
+    pub calendar: Calendar,
+    /// Trading date of the current bar's session and the index of its first bar
+    session: Option<(NaiveDate, usize)>,
+    /// Completed bars of each data stream, oldest first; the first is the bars bound so far
+    history: Vec<Vec<Bar>>,
+    /// Every symbol's bars so far in a portfolio backtest, for `(close 0 :symbol "SPY")`
+    pub symbols: Option<Arc<DashMap<String, Vec<Bar>>>>,
+    /// The broker's account at the current bar, set by the bar engine
+    pub account: Account,
+    /// Exits set by `set-stop-loss` and friends, handed to the broker after every bar
+    pub risk: RiskStops,
+    /// Where `write`, `display` and `newline` print, stdout by default
+    pub output: Box<dyn Write + Send + Sync>,
+}
+
+/// A top-level form that failed to evaluate
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    /// Index of the form within the program
+    pub form: usize,
+    /// Bar being processed, if any
+    pub bar: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bar {
+            Some(bar) => write!(f, "bar {}: {}", bar, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl EvalError {
+    /// The error as a report pointing at the failing form of `forms`, the
+    /// program it came from.
+    pub fn diagnostic(&self, forms: &[Spanned<Expr>]) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(self.message.clone());
+        if let Some(form) = forms.get(self.form) {
+            diagnostic = diagnostic.with_label(form.span.clone(), "while evaluating this form");
+        }
+        match self.bar {
+            Some(bar) => diagnostic.with_note(format!("on bar {}", bar)),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<EvalError> for String {
+    fn from(e: EvalError) -> Self {
+        e.to_string()
+    }
+}
+
+/// One entry of an `(inputs ...)` form: `(name default)`, `(name Type)` or `(name Type default)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputDecl {
+    pub name: String,
+    pub type_expr: Option<Expr>,
+    pub default: Option<Expr>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         Self {
             env: Env::new(),
+            orders: Vec::new(),
+            inputs: IndexMap::new(),
+            plots: Plots::default(),
+            current_bar: None,
+            calendar: Calendar::default(),
+            session: None,
+            history: vec![Vec::new()],
+            symbols: None,
+            account: Account::default(),
+            risk: RiskStops::default(),
+            output: Box::new(std::io::stdout()),
         }
     }
 
+    /// Make `bar` the current bar and bind its fields
+    pub fn bind_bar(&mut self, index: usize, bar: &Bar) {
+        self.current_bar = Some((index, bar.timestamp.clone()));
+        self.env.set("bar-index".to_string(), Expr::Integer(index as i64));
+        self.env.set("open".to_string(), price(bar.open));
+        self.env.set("high".to_string(), price(bar.high));
+        self.env.set("low".to_string(), price(bar.low));
+        self.env.set("close".to_string(), price(bar.close));
+        self.env.set("volume".to_string(), Expr::Float(bar.volume));
+        self.env.set("vwap".to_string(), price(bar.vwap));
+        self.env.set("time".to_string(), Expr::Timestamp(bar.timestamp.clone()));
+
+        let local = self.calendar.to_local(bar.timestamp.0);
+        self.env.set("Date".to_string(), Expr::Integer(easy_date(local.date())));
+        self.env.set("Time".to_string(), Expr::Integer(local.hour() as i64 * 100 + local.minute() as i64));
+        self.session = match (self.calendar.session_at(bar.timestamp.0), self.session) {
+            (Some(session), Some((date, first))) if session.date == date => Some((date, first)),
+            (Some(session), _) => Some((session.date, index)),
+            (None, _) => None,
+        };
+        self.history[0].push(bar.clone());
+    }
+
+    /// Forget every bar seen so far, leaving one empty data stream
+    pub fn clear_history(&mut self) {
+        self.history = vec![Vec::new()];
+        self.current_bar = None;
+        self.session = None;
+    }
+
+    /// Make room for `count` data streams after the first, for `:data 2` onwards
+    pub fn add_data_streams(&mut self, count: usize) {
+        self.history.resize(self.history.len() + count, Vec::new());
+    }
+
+    /// Record a completed bar of data stream `data`, counted from 2
+    pub fn push_bar(&mut self, data: usize, bar: Bar) {
+        self.history[data - 1].push(bar);
+    }
+
+    /// Evaluate each top-level form in order, returning their values
+    pub fn eval_program(&mut self, program: &[Expr]) -> Result<Vec<Expr>, EvalError> {
+        program
+            .iter()
+            .enumerate()
+            .map(|(form, expr)| {
+                self.eval(expr).map_err(|message| EvalError {
+                    form,
+                    bar: self.current_bar.as_ref().map(|(index, _)| *index),
+                    message,
+                })
+            })
+            .collect()
+    }
+
     pub fn eval(&mut self, expr: &Expr) -> Result<Expr, String> {
         match expr {
             Expr::Combination(target, args) => {
+                // Special forms see their arguments unevaluated
+                if let Expr::Symbol(symbol) = target.as_ref() {
+                    match symbol.0.as_str() {
+                        "set" => {
+                            if let Some(Expr::Symbol(key)) = args.first() {
+                                if let Some(value) = args.get(1) {
+                                    let value = self.eval(value)?;
+                                    builtin_set(&mut self.env, key.to_string(), value);
+                                    return Ok(Expr::Nil);
+                                } else {
+                                    return Err("set requires two arguments".to_string());
+                                }
+                            } else {
+                                return Err("set requires a Symbol key".to_string());
+                            }
+                        }
+                        "get" => {
+                            if let Some(Expr::Symbol(key)) = args.first() {
+                                return Ok(builtin_get(&self.env, key));
+                            } else {
+                                return Err("get requires a Symbol key".to_string());
+                            }
+                        }
+                        "if" => {
+                            if args.len() != 2 && args.len() != 3 {
+                                return Err("if requires two or three arguments".to_string());
+                            }
+                            return match self.eval(&args[0])? {
+                                Expr::Boolean(true) => self.eval(&args[1]),
+                                Expr::Boolean(false) => match args.get(2) {
+                                    Some(alternative) => self.eval(alternative),
+                                    None => Ok(Expr::Nil),
+                                },
+                                other => Err(format!("if requires a boolean condition, found {:?}", other)),
+                            };
+                        }
+                        "inputs" => {
+                            for decl in parse_input_decls(args)? {
+                                let value = match (self.inputs.get(&decl.name), &decl.default) {
+                                    (Some(value), _) => value.clone(),
+                                    (None, Some(default)) => self.eval(default)?,
+                                    (None, None) => {
+                                        return Err(format!("input `{}` has no default and no value was supplied", decl.name));
+                                    }
+                                };
+                                let value = in_declared_unit(&decl, value)?;
+                                self.env.set(decl.name, value);
+                            }
+                            return Ok(Expr::Nil);
+                        }
+                        // Unlike inputs, variables are only initialized on the first bar
+                        "vars" => {
+                            for decl in parse_input_decls(args)? {
+                                if self.env.get(&decl.name).is_some() {
+                                    continue;
+                                }
+                                let Some(initial) = &decl.default else {
+                                    return Err(format!("variable `{}` has no initial value", decl.name));
+                                };
+                                let value = self.eval(initial)?;
+                                let value = in_declared_unit(&decl, value)?;
+                                self.env.set(decl.name, value);
+                            }
+                            return Ok(Expr::Nil);
+                        }
+                        "let" => {
+                            let Some((bindings, body)) = args.split_first() else {
+                                return Err("let requires a list of bindings".to_string());
+                            };
+                            let mut scope = self.env.clone_child();
+                            for (name, value) in let_bindings(bindings)? {
+                                scope.set(name, self.eval(value)?);
+                            }
+                            let outer = std::mem::replace(&mut self.env, scope);
+                            let result = body.iter().try_fold(Expr::Nil, |_, form| self.eval(form));
+                            self.env = outer;
+                            return result;
+                        }
+                        field if SERIES_FIELDS.contains(&field) => {
+                            let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+                            return builtin_history(&self.history, self.symbols.as_deref(), field, &args);
+                        }
+                        "begin" => {
+                            let mut result = Expr::Nil;
+                            for arg in args {
+                                result = self.eval(arg)?;
+                            }
+                            return Ok(result);
+                        }
+                        _ => {}
+                    }
+                }
+
                 let target = self.eval(target)?;
                 let mut new_args = Vec::new();
                 for arg in args {
@@ -72,151 +433,843 @@ impl Interpreter {
                 match target.clone() {
                     Expr::Symbol(symbol) => {
                         match symbol.0.as_str() {
-                            "set" => {
-                                if let Some(Expr::Symbol(key)) = new_args.get(0) {
-                                    if let Some(value) = new_args.get(1) {
-                                        builtin_set(&mut self.env, (*key.clone()).to_string(), value.clone());
-                                        return Ok(Expr::Nil);
-                                    } else {
-                                        panic!("set requires two arguments");
-                                    }
-                                } else {
-                                    panic!("set requires a Symbol key");
+                            "car" => match new_args.first() {
+                                Some(Expr::Combination(target, _)) => Ok(*target.clone()),
+                                other => Err(format!("car requires a list, found {}", describe(other))),
+                            },
+                            "cdr" => match new_args.first() {
+                                Some(Expr::Combination(_, args)) if args.len() > 1 => {
+                                    Ok(Expr::Combination(Box::new(args[0].clone()), args[1..].to_vec()))
                                 }
-                            }
-                            "get" => {
-                                if let Some(Expr::Symbol(key)) = new_args.get(0) {
-                                    return Ok(builtin_get(&self.env, &*key));
-                                } else {
-                                    panic!("get requires a Symbol key");
+                                Some(Expr::Combination(..)) => {
+                                    Err("cdr requires a list with at least two elements".to_string())
                                 }
-                            }
-                            "car" => {
-                                if let Some(Expr::Combination(target, _)) = new_args.get(0) {
-                                    Ok(*target.clone())
-                                } else {
-                                    panic!("car requires a list");
+                                other => Err(format!("cdr requires a list, found {}", describe(other))),
+                            },
+                            "cons" => match new_args.first() {
+                                Some(Expr::Combination(target, args)) if !args.is_empty() => {
+                                    Ok(Expr::Combination(Box::new(*target.clone()), args.clone()))
                                 }
+                                other => Err(format!("cons requires a list, found {}", describe(other))),
+                            },
+                            "+" | "-" | "*" | "/" => builtin_arithmetic(&symbol, &new_args),
+                            "<" | ">" | "<=" | ">=" | "=" => builtin_compare(&symbol, &new_args),
+                            "round-to-tick" | "decimal" | "float" => builtin_convert(&symbol, &new_args),
+                            "to-exchange-time" | "session-open?" | "bars-since-session-open" | "DayOfWeek" => {
+                                builtin_session(&self.calendar, self.current_bar.as_ref(), self.session, &symbol, &new_args)
                             }
-                            "cdr" => {
-                                if let Some(Expr::Combination(_, args)) = new_args.get(0) {
-                                    if args.len() > 1 {
-                                        Ok(Expr::Combination(Box::new(args[0].clone()), args[1..].to_vec()))
-                                    } else {
-                                        panic!("cdr requires a list with at least two elements");
-                                    }
-                                } else {
-                                    panic!("car requires a list");
-                                }
+                            "position" | "entry-price" | "open-position-profit" | "account-equity" | "margin-used" => {
+                                builtin_account(&self.account, &symbol, &new_args)
                             }
-                            "cons" => {
-                                if let Some(Expr::Combination(target, args)) = new_args.get(0) {
-                                    if args.len() > 0 {
-                                        Ok(Expr::Combination(Box::new(*target.clone()), args.clone()))
-                                    } else {
-                                        panic!("cons requires a list");
-                                    }
-                                } else {
-                                    panic!("cons requires a list");
-                                }
+                            "set-stop-loss" | "set-profit-target" | "set-breakeven" | "set-trailing-stop"
+                            | "set-percent-trailing" => {
+                                builtin_risk(&mut self.risk, &symbol, &new_args)?;
+                                Ok(Expr::Nil)
                             }
-                            "if" => {
-                                if new_args.len() == 3 {
-                                    let condition = self.eval(&new_args[0])?;
-                                    if let Expr::Boolean(true) = condition {
-                                        return Ok(new_args[1].clone());
-                                    } else if let Expr::Boolean(false) = condition {
-                                        return Ok(new_args[2].clone());
-                                    } else {
-                                        panic!("if requires a boolean condition");
-                                    }
-                                } else {
-                                    panic!("if requires three arguments");
-                                }
+                            "not" => match new_args.as_slice() {
+                                [Expr::Boolean(b)] => Ok(Expr::Boolean(!b)),
+                                _ => Err("not requires one boolean argument".to_string()),
+                            },
+                            "write" | "display" => {
+                                let [value] = new_args.as_slice() else {
+                                    return Err(format!("{} requires one argument", symbol.0));
+                                };
+                                let text = match value {
+                                    Expr::String(text) if symbol.0 == "display" => text.clone(),
+                                    other => other.to_string(),
+                                };
+                                self.output.write_all(text.as_bytes()).map_err(|e| e.to_string())?;
+                                Ok(Expr::Nil)
                             }
-                            "+" => {
-                                // Initialize accumulator as mutable
-                                let mut number = Number::Zero;
-
-                                for arg in new_args.iter() {
-                                    // Match on both the current accumulator state and the argument type
-                                    match (number, arg) {
-                                        // Accumulator is Zero, initialize with the first number
-                                        (Number::Zero, Expr::Integer(i)) => {
-                                            number = Number::Signed(*i);
-                                        }
-                                        (Number::Zero, Expr::Float(f)) => {
-                                            number = Number::Float(*f);
-                                        }
-
-                                        // Accumulator is Signed
-                                        (Number::Signed(n), Expr::Integer(i)) => {
-                                            // Add integer + integer
-                                            // Consider using checked_add for overflow safety if needed
-                                            number = Number::Signed(n + *i);
-                                        }
-                                        (Number::Signed(n), Expr::Float(f)) => {
-                                            // Add integer + float -> promote to float
-                                            number = Number::Float(n as f64 + *f);
-                                        }
-
-                                        // Accumulator is Float
-                                        (Number::Float(n), Expr::Float(f)) => {
-                                            // Add float + float
-                                            number = Number::Float(n + *f);
-                                        }
-                                        (Number::Float(n), Expr::Integer(i)) => {
-                                            // Add float + integer -> stays float
-                                            number = Number::Float(n + *i as f64);
-                                        }
-
-                                        // Handle non-numeric arguments
-                                        (_, other_expr) => {
-                                            return Err(format!("Invalid argument for '+': expected Integer or Float, found {:?}", other_expr));
-                                        }
-                                    }
-                                }
-
-                                // Convert the final accumulator value back to an Expr
-                                // This now becomes the return value for the '+' case
-                                match number {
-                                    // If no arguments were provided, or they summed to zero in their initial type
-                                    Number::Zero => Ok(Expr::Integer(0)), // Default to integer 0 if no args
-                                    Number::Signed(n) => Ok(Expr::Integer(n)),
-                                    Number::Float(f) => Ok(Expr::Float(f)),
-                                    // Assuming Number::Unsigned is not used in this logic based on Expr types
-                                    Number::Unsigned(_) => unreachable!("Unsigned numbers not handled in addition logic"),
-                                }
+                            "newline" => {
+                                self.output.write_all(b"\n").map_err(|e| e.to_string())?;
+                                Ok(Expr::Nil)
+                            }
+                            "plot" | "plot-overlay" | "plot-candles" => {
+                                builtin_plot(&mut self.plots, self.current_bar.as_ref(), &self.env, &symbol, &new_args)
+                            }
+                            "buy" | "sell" | "buy-limit" | "sell-limit" | "buy-stop" | "sell-stop" => {
+                                let order = builtin_order(&symbol, &new_args)?;
+                                self.orders.push(order);
+                                Ok(Expr::Nil)
                             }
                             _ => {
                                 // Handle other operators
-                                return Ok(Expr::Combination(Box::new(target), new_args));
+                                Ok(Expr::Combination(Box::new(target), new_args))
                             }
                         }
                     }
                     _ => {
                         // Handle other combinations
-                        return Ok(Expr::Combination(Box::new(target), new_args));
+                        Ok(Expr::Combination(Box::new(target), new_args))
                     }
                 }
             }
             Expr::Nil => Ok(expr.clone()),
             Expr::Comment(_) => Ok(expr.clone()),
             Expr::Boolean(_) => Ok(expr.clone()),
-            Expr::Symbol(_) => Ok(expr.clone()),
+            // Bound symbols evaluate to their value, unbound ones to themselves
+            Expr::Symbol(symbol) => Ok(self.env.get(symbol).unwrap_or_else(|| expr.clone())),
             Expr::Float(_) => Ok(expr.clone()),
             Expr::String(_) => Ok(expr.clone()),
             Expr::Duration(_) => Ok(expr.clone()),
             Expr::Timestamp(_) => Ok(expr.clone()),
             Expr::Integer(_) => Ok(expr.clone()),
+            Expr::Decimal(_) => Ok(expr.clone()),
+            Expr::Amount(..) => Ok(expr.clone()),
+            Expr::Array(items) => Ok(Expr::Array(items.iter().map(|item| self.eval(item)).collect::<Result<_, _>>()?)),
         }
     }
 }
 
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Type expressions are capitalized symbols like `Price` or applications like `(Array Price)`
+fn is_type_expr(expr: &Expr) -> bool {
+    match expr {
+        Expr::Symbol(symbol) => symbol.starts_with(|c: char| c.is_ascii_uppercase()),
+        Expr::Combination(head, _) => is_type_expr(head),
+        _ => false,
+    }
+}
+
+pub fn parse_input_decls(args: &[Expr]) -> Result<Vec<InputDecl>, String> {
+    let mut decls = Vec::new();
+    for arg in args {
+        let decl = match arg {
+            Expr::Combination(name, rest) => match (name.as_ref(), rest.as_slice()) {
+                (Expr::Symbol(name), [t]) if is_type_expr(t) => {
+                    InputDecl { name: name.to_string(), type_expr: Some(t.clone()), default: None }
+                }
+                (Expr::Symbol(name), [default]) => {
+                    InputDecl { name: name.to_string(), type_expr: None, default: Some(default.clone()) }
+                }
+                (Expr::Symbol(name), [t, default]) => InputDecl {
+                    name: name.to_string(),
+                    type_expr: Some(t.clone()),
+                    default: Some(default.clone()),
+                },
+                _ => return Err(format!("malformed input declaration {:?}", arg)),
+            },
+            _ => return Err(format!("malformed input declaration {:?}", arg)),
+        };
+        decls.push(decl);
+    }
+    Ok(decls)
+}
+
+/// The `(name value)` pairs of a `let`, or none for `()`
+fn let_bindings(bindings: &Expr) -> Result<Vec<(String, &Expr)>, String> {
+    let pairs: Vec<&Expr> = match bindings {
+        Expr::Nil => return Ok(Vec::new()),
+        Expr::Combination(first, rest) => std::iter::once(first.as_ref()).chain(rest).collect(),
+        other => return Err(format!("let requires a list of bindings, found {}", other)),
+    };
+    pairs
+        .into_iter()
+        .map(|pair| match pair {
+            Expr::Combination(name, value) => match (name.as_ref(), value.as_slice()) {
+                (Expr::Symbol(name), [value]) => Ok((name.to_string(), value)),
+                _ => Err(format!("malformed let binding {}", pair)),
+            },
+            other => Err(format!("malformed let binding {}", other)),
+        })
+        .collect()
+}
+
+/// Every input declared by the top-level `(inputs ...)` forms of a program
+pub fn declared_inputs(program: &[Expr]) -> Result<Vec<InputDecl>, String> {
+    let mut decls = Vec::new();
+    for form in program {
+        if let Expr::Combination(head, args) = form
+            && matches!(head.as_ref(), Expr::Symbol(s) if &**s == "inputs")
+        {
+            decls.extend(parse_input_decls(args)?);
+        }
+    }
+    Ok(decls)
+}
+
+/// An input declared as `Price`, `Quantity` or `Money` holds a number in that unit
+fn in_declared_unit(decl: &InputDecl, value: Expr) -> Result<Expr, String> {
+    let Some(Expr::Symbol(name)) = &decl.type_expr else {
+        return Ok(value);
+    };
+    let Some(unit) = Unit::from_name(&name.to_lowercase()) else {
+        return Ok(value);
+    };
+    match value {
+        Expr::Integer(_) | Expr::Float(_) | Expr::Decimal(_) => Ok(Expr::Amount(unit, Box::new(value))),
+        Expr::Amount(u, _) if u == unit => Ok(value),
+        other => Err(format!("input `{}` is declared {} but is {}", decl.name, Type::from(unit), other)),
+    }
+}
+
+/// A bar's price field
+fn price(value: f64) -> Expr {
+    Expr::Amount(Unit::Price, Box::new(Expr::Float(value)))
+}
+
+/// The number inside an amount, or the value itself
+fn plain(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Amount(_, value) => value,
+        other => other,
+    }
+}
+
+fn with_unit(unit: Option<Unit>, value: Expr) -> Expr {
+    match unit {
+        Some(unit) => Expr::Amount(unit, Box::new(value)),
+        None => value,
+    }
+}
+
 fn builtin_set(env: &mut Env, key: String, value: Expr) {
-    env.set(key, value);
+    env.assign(key, value);
 }
 
 fn builtin_get(env: &Env, key: &str) -> Expr {
     env.get(key).unwrap_or(Expr::Nil)
-}
\ No newline at end of file
+}
+impl Number {
+    fn from_expr(op: &str, expr: &Expr) -> Result<Number, String> {
+        match expr {
+            Expr::Integer(i) => Ok(Number::Signed(*i)),
+            Expr::Float(f) => Ok(Number::Float(*f)),
+            Expr::Decimal(d) => Ok(Number::Decimal(*d)),
+            other => Err(format!("Invalid argument for '{}': expected a number, found {:?}", op, other)),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Zero => 0.0,
+            Number::Signed(n) => n as f64,
+            Number::Float(f) => f,
+            Number::Decimal(d) => d.to_f64(),
+        }
+    }
+
+    /// Only meaningful for non-float numbers
+    fn as_i64(self) -> i64 {
+        match self {
+            Number::Zero => 0,
+            Number::Signed(n) => n,
+            Number::Float(f) => f as i64,
+            Number::Decimal(d) => d.to_f64() as i64,
+        }
+    }
+
+    fn into_expr(self) -> Expr {
+        match self {
+            Number::Zero => Expr::Integer(0),
+            Number::Signed(n) => Expr::Integer(n),
+            Number::Float(f) => Expr::Float(f),
+            Number::Decimal(d) => Expr::Decimal(d),
+        }
+    }
+
+    /// The exact value, with a float taken at its shortest spelling
+    fn as_decimal(self, op: &str) -> Result<Decimal, String> {
+        match self {
+            Number::Zero => Ok(Decimal::ZERO),
+            Number::Signed(n) => Ok(Decimal::from_i64(n)),
+            Number::Float(f) => Decimal::from_f64(f).ok_or_else(|| format!("'{}' cannot make a Decimal of {}", op, f)),
+            Number::Decimal(d) => Ok(d),
+        }
+    }
+}
+
+fn builtin_arithmetic(op: &str, args: &[Expr]) -> Result<Expr, String> {
+    if args.iter().any(|arg| matches!(arg, Expr::Amount(..))) {
+        return unit_arithmetic(op, args);
+    }
+    if args.iter().any(|arg| matches!(arg, Expr::Duration(_) | Expr::Timestamp(_))) {
+        return time_arithmetic(op, args);
+    }
+    let mut numbers = Vec::with_capacity(args.len());
+    for arg in args {
+        numbers.push(Number::from_expr(op, arg)?);
+    }
+
+    // Identity element, and the implicit left operand of unary `-` and `/`
+    let mut number = match (op, numbers.len()) {
+        ("*", 0) => Number::Signed(1),
+        ("-" | "/", 0) => return Err(format!("'{}' requires at least one argument", op)),
+        (_, 0) => Number::Zero,
+        ("-", 1) => Number::Zero,
+        ("/", 1) => Number::Signed(1),
+        _ => numbers.remove(0),
+    };
+
+    for next in numbers {
+        number = match (op, number, next) {
+            ("/", _, n) if n.as_f64() == 0.0 => return Err("Division by zero".to_string()),
+            // Decimals are exact, so they win over floats as well as integers
+            (_, Number::Decimal(_), _) | (_, _, Number::Decimal(_)) => {
+                let (n, m) = (number.as_decimal(op)?, next.as_decimal(op)?);
+                let result = match op {
+                    "+" => n.checked_add(m),
+                    "-" => n.checked_sub(m),
+                    "*" => n.checked_mul(m),
+                    _ => n.checked_div(m),
+                };
+                Number::Decimal(result.ok_or_else(|| format!("Decimal overflow in '{}'", op))?)
+            }
+            ("/", n, m) => Number::Float(n.as_f64() / m.as_f64()),
+            (_, Number::Float(_), _) | (_, _, Number::Float(_)) => {
+                let (n, m) = (number.as_f64(), next.as_f64());
+                Number::Float(match op {
+                    "+" => n + m,
+                    "-" => n - m,
+                    _ => n * m,
+                })
+            }
+            (_, n, m) => {
+                let (n, m) = (n.as_i64(), m.as_i64());
+                let result = match op {
+                    "+" => n.checked_add(m),
+                    "-" => n.checked_sub(m),
+                    _ => n.checked_mul(m),
+                };
+                Number::Signed(result.ok_or_else(|| format!("Integer overflow in '{}'", op))?)
+            }
+        };
+    }
+    Ok(number.into_expr())
+}
+
+/// Amounts combine by the rules `check` uses, from [`arithmetic_type`]: plain numbers
+/// scale or offset any unit, a price times a quantity is money, and money divided by a
+/// price is a quantity. An amount divided by one in the same unit is a plain ratio.
+fn unit_arithmetic(op: &str, args: &[Expr]) -> Result<Expr, String> {
+    let types: Vec<Type> = args.iter().map(|arg| Type::of_literal(arg).unwrap_or(Type::Any)).collect();
+    let result = match types.as_slice() {
+        // `(- x)` is `(- 0 x)` and `(/ x)` is `(/ 1 x)`
+        [only] if matches!(op, "-" | "/") => arithmetic_type(op, &Type::Integer, only)
+            .ok_or_else(|| format!("'{}' is not defined for a single {}", op, only))?,
+        [first, rest @ ..] => rest.iter().try_fold(first.clone(), |acc, next| {
+            arithmetic_type(op, &acc, next).ok_or_else(|| format!("'{}' is not defined for {} and {}", op, acc, next))
+        })?,
+        [] => Type::Integer,
+    };
+    let numbers: Vec<Expr> = args.iter().map(|arg| plain(arg).clone()).collect();
+    Ok(with_unit(result.unit(), builtin_arithmetic(op, &numbers)?))
+}
+
+/// Timestamps move by durations and differ by one; durations add up, scale by
+/// integers and divide into each other. Anything else, like the sum of two
+/// timestamps, has no meaning and is an error.
+fn time_arithmetic(op: &str, args: &[Expr]) -> Result<Expr, String> {
+    let overflow = || format!("'{}' overflows the range of times", op);
+    if let ("-", [Expr::Duration(d)]) = (op, args) {
+        return Ok(Expr::Duration(Duration(-d.0)));
+    }
+    let (first, rest) = args.split_first().ok_or_else(|| format!("'{}' requires at least one argument", op))?;
+    if rest.is_empty() && matches!(op, "-" | "/") {
+        return Err(format!("'{}' is not defined for a single {}", op, first.type_name()));
+    }
+    let timestamp = |t: Option<_>| t.map(|t| Expr::Timestamp(Timestamp(t))).ok_or_else(overflow);
+    let duration = |d: Option<_>| d.map(|d| Expr::Duration(Duration(d))).ok_or_else(overflow);
+    let factor = |n: &i64| i32::try_from(*n).ok();
+    rest.iter().try_fold(first.clone(), |acc, next| match (op, &acc, next) {
+        ("+", Expr::Timestamp(t), Expr::Duration(d)) | ("+", Expr::Duration(d), Expr::Timestamp(t)) => {
+            timestamp(t.0.checked_add_signed(d.0))
+        }
+        ("-", Expr::Timestamp(t), Expr::Duration(d)) => timestamp(t.0.checked_sub_signed(d.0)),
+        ("-", Expr::Timestamp(t), Expr::Timestamp(u)) => duration(Some(t.0.signed_duration_since(u.0))),
+        ("+", Expr::Duration(d), Expr::Duration(e)) => duration(d.0.checked_add(&e.0)),
+        ("-", Expr::Duration(d), Expr::Duration(e)) => duration(d.0.checked_sub(&e.0)),
+        ("*", Expr::Duration(d), Expr::Integer(n)) | ("*", Expr::Integer(n), Expr::Duration(d)) => {
+            duration(factor(n).and_then(|n| d.0.checked_mul(n)))
+        }
+        ("/", Expr::Duration(_), divisor) if is_zero(divisor) => Err("Division by zero".to_string()),
+        ("/", Expr::Duration(d), Expr::Integer(n)) => duration(factor(n).and_then(|n| d.0.checked_div(n))),
+        ("/", Expr::Duration(d), Expr::Duration(e)) => {
+            Ok(Expr::Float(d.as_nanos() as f64 / e.as_nanos() as f64))
+        }
+        (op, a, b) => Err(format!("'{}' is not defined for {} and {}", op, a.type_name(), b.type_name())),
+    })
+}
+
+fn is_zero(expr: &Expr) -> bool {
+    match expr {
+        Expr::Integer(n) => *n == 0,
+        Expr::Duration(d) => d.0.is_zero(),
+        _ => false,
+    }
+}
+
+/// Numbers compare with numbers in the same unit or none, timestamps with timestamps and
+/// durations with durations.
+fn builtin_compare(op: &str, args: &[Expr]) -> Result<Expr, String> {
+    if args.len() < 2 {
+        return Err(format!("'{}' requires at least two arguments", op));
+    }
+    let mut amounts = args.iter().filter(|arg| matches!(arg, Expr::Amount(..)));
+    if let Some(first) = amounts.next()
+        && let Some(other) = amounts.find(|arg| arg.type_name() != first.type_name())
+    {
+        return Err(format!("'{}' cannot compare {} with {}", op, first.type_name(), other.type_name()));
+    }
+    let args: Vec<Expr> = args.iter().map(|arg| plain(arg).clone()).collect();
+    let mut orderings = Vec::with_capacity(args.len() - 1);
+    for pair in args.windows(2) {
+        orderings.push(match (&pair[0], &pair[1]) {
+            (Expr::Timestamp(a), Expr::Timestamp(b)) => Some(a.0.cmp(&b.0)),
+            (Expr::Duration(a), Expr::Duration(b)) => Some(a.0.cmp(&b.0)),
+            (a @ (Expr::Timestamp(_) | Expr::Duration(_)), b) | (a, b @ (Expr::Timestamp(_) | Expr::Duration(_))) => {
+                return Err(format!("'{}' cannot compare {} with {}", op, a.type_name(), b.type_name()));
+            }
+            (a, b) => match (Number::from_expr(op, a)?, Number::from_expr(op, b)?) {
+                (a @ Number::Decimal(_), b) | (a, b @ Number::Decimal(_)) => {
+                    Some(a.as_decimal(op)?.cmp(&b.as_decimal(op)?))
+                }
+                (a, b) => a.as_f64().partial_cmp(&b.as_f64()),
+            },
+        });
+    }
+    let holds = orderings.into_iter().all(|ordering| match op {
+        "<" => ordering == Some(Ordering::Less),
+        ">" => ordering == Some(Ordering::Greater),
+        "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        _ => ordering == Some(Ordering::Equal),
+    });
+    Ok(Expr::Boolean(holds))
+}
+
+/// `(round-to-tick price tick)`, `(decimal x)` and `(float x)`, which keep the unit of `price` or `x`
+fn builtin_convert(op: &str, args: &[Expr]) -> Result<Expr, String> {
+    let unit = match args.first() {
+        Some(Expr::Amount(unit, _)) => Some(*unit),
+        _ => None,
+    };
+    if let [value, tick @ Expr::Amount(..)] = args
+        && tick.type_name() != value.type_name()
+    {
+        return Err(format!("round-to-tick cannot round {} to a tick in {}", value.type_name(), tick.type_name()));
+    }
+    let numbers = args.iter().map(|arg| Number::from_expr(op, plain(arg))).collect::<Result<Vec<_>, _>>()?;
+    let value = match (op, numbers.as_slice()) {
+        ("round-to-tick", [price, tick]) => {
+            let (price, tick) = (price.as_decimal(op)?, tick.as_decimal(op)?);
+            if tick <= Decimal::ZERO {
+                return Err(format!("round-to-tick requires a positive tick, found {}", tick));
+            }
+            price.round_to_tick(tick).map(Expr::Decimal).ok_or_else(|| format!("Decimal overflow in '{}'", op))
+        }
+        ("decimal", [n]) => n.as_decimal(op).map(Expr::Decimal),
+        ("float", [n]) => Ok(Expr::Float(n.as_f64())),
+        ("round-to-tick", _) => Err("round-to-tick requires a price and a tick".to_string()),
+        _ => Err(format!("{} requires one argument", op)),
+    }?;
+    Ok(with_unit(unit, value))
+}
+
+/// `(close)`, `(close 3)`, `(close 0 :data 2)` or `(close 0 :symbol "SPY")`: a field
+/// of a completed bar, counting back from the latest, or `()` before there are enough bars
+fn builtin_history(
+    history: &[Vec<Bar>],
+    symbols: Option<&DashMap<String, Vec<Bar>>>,
+    field: &str,
+    args: &[Expr],
+) -> Result<Expr, String> {
+    let (positional, keywords) = keyword_args(field, args)?;
+    let offset = match positional.as_slice() {
+        [] => 0,
+        [Expr::Integer(n)] if *n < 0 => {
+            return Err(format!("`({} {})` would read a bar that has not happened yet", field, n));
+        }
+        [Expr::Integer(n)] => *n as usize,
+        _ => return Err(format!("{} takes one Integer offset of bars back", field)),
+    };
+    let data = match keywords.get("data") {
+        None => 1,
+        Some(Expr::Integer(n)) if *n >= 1 && (*n as usize) <= history.len() => *n as usize,
+        Some(other) => {
+            return Err(format!("{}: there is no data stream {}; there are {}", field, other, history.len()));
+        }
+    };
+    let other;
+    let bars = match keywords.get("symbol") {
+        None => &history[data - 1],
+        Some(_) if data != 1 => return Err(format!("{}: `:symbol` reads the first data stream only", field)),
+        Some(Expr::String(symbol)) => {
+            let symbols = symbols.ok_or_else(|| format!("{}: `:symbol` needs a portfolio backtest", field))?;
+            other = symbols.get(symbol).ok_or_else(|| format!("{}: there is no symbol \"{}\"", field, symbol))?;
+            other.value()
+        }
+        Some(other) => return Err(format!("{}: `:symbol` expects a string, found {}", field, other)),
+    };
+    let Some(bar) = bars.len().checked_sub(offset + 1).map(|i| &bars[i]) else {
+        return Ok(Expr::Nil);
+    };
+    Ok(match field {
+        "open" => price(bar.open),
+        "high" => price(bar.high),
+        "low" => price(bar.low),
+        "close" => price(bar.close),
+        "volume" => Expr::Float(bar.volume),
+        "vwap" => price(bar.vwap),
+        _ => Expr::Timestamp(bar.timestamp.clone()),
+    })
+}
+
+/// EasyLanguage's date number: `1240102` for 2024-01-02
+fn easy_date(date: NaiveDate) -> i64 {
+    (date.year() as i64 - 1900) * 10_000 + date.month() as i64 * 100 + date.day() as i64
+}
+
+/// `(to-exchange-time t)`, `(session-open? [t])`, `(bars-since-session-open)` and `(DayOfWeek date)`
+fn builtin_session(
+    calendar: &Calendar,
+    bar: Option<&(usize, Timestamp)>,
+    session: Option<(NaiveDate, usize)>,
+    op: &str,
+    args: &[Expr],
+) -> Result<Expr, String> {
+    let bar_time = || bar.map(|(_, time)| time.0).ok_or_else(|| format!("{} without a timestamp needs a current bar", op));
+    match (op, args) {
+        ("to-exchange-time", [Expr::Timestamp(t)]) => Ok(Expr::Timestamp(Timestamp(calendar.to_local(t.0).and_utc()))),
+        ("session-open?", []) => Ok(Expr::Boolean(calendar.session_at(bar_time()?).is_some())),
+        ("session-open?", [Expr::Timestamp(t)]) => Ok(Expr::Boolean(calendar.session_at(t.0).is_some())),
+        ("bars-since-session-open", []) => Ok(match (bar, session) {
+            (Some((index, _)), Some((_, first))) => Expr::Integer((index - first) as i64),
+            _ => Expr::Nil,
+        }),
+        ("DayOfWeek", [Expr::Integer(n)]) => {
+            let (year, month, day) = (1900 + n / 10_000, n / 100 % 100, n % 100);
+            let date = i32::try_from(year).ok().and_then(|y| NaiveDate::from_ymd_opt(y, month as u32, day as u32));
+            let date = date.filter(|_| *n >= 0).ok_or_else(|| format!("DayOfWeek: {} is not a date like 1240102", n))?;
+            Ok(Expr::Integer(date.weekday().num_days_from_sunday() as i64))
+        }
+        ("DayOfWeek", [Expr::Timestamp(t)]) => {
+            Ok(Expr::Integer(calendar.to_local(t.0).weekday().num_days_from_sunday() as i64))
+        }
+        ("bars-since-session-open", _) => Err("bars-since-session-open takes no arguments".to_string()),
+        ("session-open?", _) => Err("session-open? takes an optional Timestamp".to_string()),
+        _ => Err(format!("{} requires one {}", op, if op == "DayOfWeek" { "date" } else { "Timestamp" })),
+    }
+}
+
+/// An argument for an error message, or "nothing" when it is missing
+fn describe(arg: Option<&Expr>) -> String {
+    arg.map_or_else(|| "nothing".to_string(), |arg| arg.to_string())
+}
+
+/// `(position)`, `(entry-price)`, `(open-position-profit)`, `(account-equity)` and `(margin-used)`
+fn builtin_account(account: &Account, op: &str, args: &[Expr]) -> Result<Expr, String> {
+    if !args.is_empty() {
+        return Err(format!("{} takes no arguments", op));
+    }
+    Ok(match op {
+        "position" => Expr::Amount(Unit::Quantity, Box::new(Expr::Integer(account.position))),
+        "entry-price" => account.entry_price.map_or(Expr::Nil, price),
+        "open-position-profit" => money(account.open_profit),
+        "account-equity" => money(account.equity),
+        _ => money(account.margin_used),
+    })
+}
+
+fn money(value: f64) -> Expr {
+    Expr::Amount(Unit::Money, Box::new(Expr::Float(value)))
+}
+
+/// `(set-stop-loss amount)`, `(set-percent-trailing floor percent)` and friends. An amount of 0 turns a stop off.
+fn builtin_risk(risk: &mut RiskStops, op: &str, args: &[Expr]) -> Result<(), String> {
+    // Every argument is money but the percent of `set-percent-trailing`
+    let amounts = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| match arg {
+            Expr::Integer(_) | Expr::Float(_) | Expr::Decimal(_) => Ok(Number::from_expr(op, arg)?.as_f64()),
+            Expr::Amount(Unit::Money, amount) if i == 0 => Ok(Number::from_expr(op, amount)?.as_f64()),
+            other => Err(format!("{} requires amounts of money, found {}", op, other)),
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    if let Some(bad) = amounts.iter().find(|amount| !(amount.is_finite() && **amount >= 0.0)) {
+        return Err(format!("{}: {} is not a positive amount", op, bad));
+    }
+    let set = |amount: f64| (amount > 0.0).then_some(amount);
+    match (op, amounts.as_slice()) {
+        ("set-stop-loss", [amount]) => risk.stop_loss = set(*amount),
+        ("set-profit-target", [amount]) => risk.profit_target = set(*amount),
+        ("set-breakeven", [floor]) => risk.breakeven = set(*floor),
+        ("set-trailing-stop", [amount]) => risk.trailing_stop = set(*amount),
+        ("set-percent-trailing", [floor, percent]) if *percent <= 100.0 => {
+            risk.percent_trailing = set(*percent).map(|percent| (*floor, percent));
+        }
+        ("set-percent-trailing", [_, percent]) => return Err(format!("set-percent-trailing: {}% is over 100", percent)),
+        ("set-percent-trailing", _) => return Err("set-percent-trailing takes a floor and a percent".to_string()),
+        _ => return Err(format!("{} takes one amount", op)),
+    }
+    Ok(())
+}
+
+/// `(buy qty)`, `(buy-limit qty price)`, `(sell-stop qty price)` and friends
+fn builtin_order(op: &str, args: &[Expr]) -> Result<Order, String> {
+    let (side, kind) = op.split_once('-').unwrap_or((op, "market"));
+    let side = if side == "buy" { Side::Buy } else { Side::Sell };
+    let quantity = match args.first() {
+        Some(Expr::Amount(Unit::Quantity, q)) => q.as_ref(),
+        Some(other) => other,
+        None => &Expr::Nil,
+    };
+    let quantity = match quantity {
+        Expr::Integer(q) if *q > 0 => *q,
+        _ => return Err(format!("{} requires a positive Integer quantity, found {:?}", op, args.first())),
+    };
+    let price = || match args.get(1) {
+        Some(price @ (Expr::Integer(_) | Expr::Float(_) | Expr::Decimal(_))) => Ok(Number::from_expr(op, price)?.as_f64()),
+        Some(Expr::Amount(Unit::Price, price)) => Ok(Number::from_expr(op, price)?.as_f64()),
+        other => Err(format!("{} requires a price, found {:?}", op, other)),
+    };
+    let kind = match kind {
+        "limit" => OrderKind::Limit(price()?),
+        "stop" => OrderKind::Stop(price()?),
+        _ => OrderKind::Market,
+    };
+    Ok(Order::new(side, quantity, kind))
+}
+
+/// Split trailing `:keyword value` pairs off the positional arguments
+fn keyword_args(op: &str, args: &[Expr]) -> Result<(Vec<Expr>, IndexMap<String, Expr>), String> {
+    let mut positional = Vec::new();
+    let mut keywords = IndexMap::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg {
+            Expr::Symbol(symbol) if symbol.starts_with(':') => {
+                let value = iter.next().ok_or_else(|| format!("{} keyword {} needs a value", op, symbol.0))?;
+                keywords.insert(symbol[1..].to_string(), value.clone());
+            }
+            _ if !keywords.is_empty() => return Err(format!("{}: positional argument after keywords", op)),
+            other => positional.push(other.clone()),
+        }
+    }
+    Ok((positional, keywords))
+}
+
+fn keyword_string(keywords: &IndexMap<String, Expr>, name: &str) -> Result<Option<String>, String> {
+    match keywords.get(name) {
+        None => Ok(None),
+        Some(Expr::String(s)) => Ok(Some(s.clone())),
+        Some(Expr::Symbol(s)) => Ok(Some(s.to_string())),
+        Some(other) => Err(format!(":{} expects a string, found {:?}", name, other)),
+    }
+}
+
+fn plot_number(op: &str, expr: &Expr) -> Result<f64, String> {
+    Number::from_expr(op, plain(expr)).map(Number::as_f64)
+}
+
+/// `(plot value ...)`, `(plot-overlay value :on chart ...)` and `(plot-candles [open high low close] ...)`
+///
+/// A number adds one point at the current bar; a list of numbers replaces the whole series.
+fn builtin_plot(
+    plots: &mut Plots,
+    bar: Option<&(usize, Timestamp)>,
+    env: &Env,
+    op: &str,
+    args: &[Expr],
+) -> Result<Expr, String> {
+    let (positional, keywords) = keyword_args(op, args)?;
+    let title = keyword_string(&keywords, "title")?;
+    let default_chart = if op == "plot" { title.as_deref().unwrap_or("Plot") } else { "Price" };
+    let chart_name = match (keyword_string(&keywords, "on")?, keyword_string(&keywords, "chart")?) {
+        (Some(name), _) | (None, Some(name)) => name,
+        (None, None) => default_chart.to_string(),
+    };
+
+    let chart = plots.chart_mut(&chart_name);
+    if let Some(label) = keyword_string(&keywords, "x-label")? {
+        chart.x_label = Some(label);
+    }
+    if let Some(label) = keyword_string(&keywords, "y-label")? {
+        chart.y_label = Some(label);
+    }
+    let color = keyword_string(&keywords, "color")?;
+    let time = bar.map(|(_, t)| t.clone());
+
+    if op == "plot-candles" {
+        let ohlc = match positional.as_slice() {
+            [] => ["open", "high", "low", "close"]
+                .iter()
+                .map(|name| env.get(name).ok_or_else(|| format!("plot-candles: `{}` is not bound", name)))
+                .collect::<Result<Vec<_>, _>>()?,
+            [_, _, _, _] => positional.clone(),
+            _ => return Err("plot-candles takes no arguments or open, high, low and close".to_string()),
+        };
+        let series = chart.series_mut(title.as_deref().unwrap_or("OHLC"), color, SeriesData::Candles(vec![]));
+        if let SeriesData::Candles(candles) = &mut series.data {
+            let index = bar.map_or(candles.len(), |(i, _)| *i);
+            candles.push(Candle {
+                index,
+                time,
+                open: plot_number(op, &ohlc[0])?,
+                high: plot_number(op, &ohlc[1])?,
+                low: plot_number(op, &ohlc[2])?,
+                close: plot_number(op, &ohlc[3])?,
+            });
+        }
+        return Ok(Expr::Nil);
+    }
+
+    let [value] = positional.as_slice() else {
+        return Err(format!("{} requires one value to plot", op));
+    };
+    let histogram = match keyword_string(&keywords, "style")?.as_deref() {
+        None | Some("line") => false,
+        Some("histogram") => true,
+        Some(other) => return Err(format!("unknown plot style `{}`", other)),
+    };
+    let series_name = title.as_deref().unwrap_or(if op == "plot" { "Plot" } else { "Overlay" });
+    let empty = if histogram { SeriesData::Histogram(vec![]) } else { SeriesData::Line(vec![]) };
+    let series = chart.series_mut(series_name, color, empty);
+    let (SeriesData::Line(points) | SeriesData::Histogram(points)) = &mut series.data else {
+        return Err(format!("{}: `{}` is already a candlestick series", op, series_name));
+    };
+    let items: Option<Vec<&Expr>> = match value {
+        Expr::Combination(head, rest) => Some(std::iter::once(head.as_ref()).chain(rest).collect()),
+        Expr::Array(items) => Some(items.iter().collect()),
+        _ => None,
+    };
+    match (items, value) {
+        (Some(items), _) => {
+            points.clear();
+            for (index, item) in items.into_iter().enumerate() {
+                points.push(PlotPoint { index, time: None, value: plot_number(op, item)? });
+            }
+        }
+        (None, other) => {
+            let index = bar.map_or(points.len(), |(i, _)| *i);
+            points.push(PlotPoint { index, time, value: plot_number(op, other)? });
+        }
+    }
+    Ok(Expr::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+
+    /// Collects what the interpreter prints.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn does_arithmetic_on_times() {
+        let eval = |src: &str| Interpreter::new().eval(&parse_program(src).unwrap()[0].value).map(|v| v.to_string());
+        assert_eq!(eval("(+ #2024-01-02T15:30:00Z 1h 30m)"), Ok("#2024-01-02T17:00:00Z".to_string()));
+        assert_eq!(eval("(- #2024-01-03T00:00:00Z #2024-01-02T00:00:00Z)"), Ok("1d".to_string()));
+        assert_eq!(eval("(* 2 (- 1w 2d))"), Ok("10d".to_string()));
+        assert_eq!(eval("(/ 1s 2)"), Ok("500ms".to_string()));
+        assert_eq!(eval("(+ 1s 1ns)"), Ok("1000000001ns".to_string()));
+        assert_eq!(eval("(/ 1d 6h)"), Ok("4.0".to_string()));
+        assert_eq!(eval("(/ 1ms 250us)"), Ok("4.0".to_string()));
+        assert_eq!(eval("(< #2024-01-02T00:00:00Z #2024-01-03T00:00:00Z)"), Ok("#t".to_string()));
+        assert_eq!(
+            eval("(+ #2024-01-02T00:00:00Z #2024-01-03T00:00:00Z)"),
+            Err("'+' is not defined for Timestamp and Timestamp".to_string())
+        );
+        assert_eq!(eval("(/ 1d 0)"), Err("Division by zero".to_string()));
+        assert!(eval("(< 1d 5)").is_err());
+    }
+
+    #[test]
+    fn decimals_are_exact_and_contagious() {
+        let eval = |src: &str| Interpreter::new().eval(&parse_program(src).unwrap()[0].value).map(|v| v.to_string());
+        assert_eq!(eval("(+ 0.1m 0.2m)"), Ok("0.3m".to_string()));
+        assert_eq!(eval("(* 101.25m 3)"), Ok("303.75m".to_string()));
+        assert_eq!(eval("(- 0.5 #d0.25)"), Ok("0.25m".to_string()));
+        assert_eq!(eval("(/ 1.0m 4)"), Ok("0.25m".to_string()));
+        assert_eq!(eval("(= (+ 0.1m 0.2) 0.3)"), Ok("#t".to_string()));
+        assert_eq!(eval("(round-to-tick 101.237 0.05)"), Ok("101.25m".to_string()));
+        assert_eq!(eval("(float 2.5m)"), Ok("2.5".to_string()));
+        assert_eq!(eval("(/ 1.0m 0)"), Err("Division by zero".to_string()));
+        assert!(eval("(round-to-tick 1.0m 0)").is_err());
+    }
+
+    #[test]
+    fn amounts_keep_their_units() {
+        let eval = |src: &str| {
+            let mut interpreter = Interpreter::new();
+            interpreter.bind_bar(0, &Bar::new(Timestamp(chrono::DateTime::UNIX_EPOCH), 10.0, 11.0, 9.0, 10.5, 0.0));
+            interpreter.eval(&parse_program(src).unwrap()[0].value).map(|v| v.to_string())
+        };
+        assert_eq!(eval("(* close #quantity:10)"), Ok("#money:105.0".to_string()));
+        assert_eq!(eval("(/ #money:105 close)"), Ok("#quantity:10.0".to_string()));
+        assert_eq!(eval("(- (* close 2) open)"), Ok("#price:11.0".to_string()));
+        assert_eq!(eval("(/ (- close open) open)"), Ok("0.05".to_string()));
+        assert_eq!(eval("(round-to-tick close 0.25)"), Ok("#price:10.5m".to_string()));
+        assert_eq!(eval("(> close 10)"), Ok("#t".to_string()));
+        assert_eq!(eval("(* close close)"), Err("'*' is not defined for Price and Price".to_string()));
+        assert_eq!(eval("(+ close #quantity:1)"), Err("'+' is not defined for Price and Quantity".to_string()));
+        assert_eq!(eval("(/ close)"), Err("'/' is not defined for a single Price".to_string()));
+        assert_eq!(eval("(< close #money:5)"), Err("'<' cannot compare Price with Money".to_string()));
+        assert!(eval("(buy close)").is_err());
+        assert!(eval("(set-stop-loss close)").is_err());
+
+        let mut interpreter = Interpreter::new();
+        let program: Vec<Expr> = parse_program("(inputs (size Quantity 5)) (buy-limit size #price:9.5) size")
+            .unwrap()
+            .into_iter()
+            .map(|f| f.value)
+            .collect();
+        assert_eq!(interpreter.eval_program(&program).unwrap()[2].to_string(), "#quantity:5");
+        assert_eq!(interpreter.orders.len(), 1);
+    }
+
+    #[test]
+    fn vars_keep_their_values_and_let_binds_locally() {
+        let program: Vec<Expr> = parse_program(
+            "(vars (count 0) (total Money 0)) (set count (+ count 1)) \
+             (let ((step 2) (count 10)) (set total (+ total step count))) [count total (get step)]",
+        )
+        .unwrap()
+        .into_iter()
+        .map(|f| f.value)
+        .collect();
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_program(&program).unwrap();
+        let values = interpreter.eval_program(&program).unwrap();
+        assert_eq!(values[3].to_string(), "[2 #money:24 ()]");
+        assert!(interpreter.eval(&parse_program("(let (x 1) x)").unwrap()[0].value).is_err());
+    }
+
+    #[test]
+    fn write_reads_back_and_display_does_not_quote() {
+        let captured = Captured::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.output = Box::new(captured.clone());
+        let program: Vec<Expr> = parse_program(r#"(write "a\"b") (newline) (display "a\"b") (newline) (write [1.0 5m #t])"#)
+            .unwrap()
+            .into_iter()
+            .map(|f| f.value)
+            .collect();
+        interpreter.eval_program(&program).unwrap();
+        assert_eq!(String::from_utf8(captured.0.lock().unwrap().clone()).unwrap(), "\"a\\\"b\"\na\"b\n[1.0 5m #t]");
+    }
+}
@@ -0,0 +1,24 @@
+pub mod ast;
+pub mod backtest;
+pub mod bars;
+pub mod broker;
+pub mod calendar;
+pub mod check;
+pub mod code;
+pub mod decimal;
+pub mod diagnostics;
+pub mod engine;
+pub mod format;
+pub mod interpreter;
+pub mod lsp;
+pub mod montecarlo;
+pub mod optimize;
+pub mod parser;
+pub mod plot;
+pub mod portfolio;
+pub mod repl;
+pub mod report;
+pub mod server;
+pub mod syntax;
+pub mod types;
+pub mod walkforward;
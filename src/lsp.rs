@@ -0,0 +1,537 @@
+//! Language server for scripts, spoken over stdio.
+//!
+//! Covers what an editor needs day to day: parse errors and [`crate::check`]
+//! findings when a document is opened or saved, hover with builtin signatures,
+//! completion, go-to-definition, document symbols and rename. Names are defined by `(set name ...)` and
+//! `(inputs (name ...))`. There is no LSP crate here; messages are JSON-RPC
+//! bodies framed by `Content-Length` headers, built with `serde_json`, and
+//! documents are synced in full on every change.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use crate::ast::Expr;
+use crate::check::{check, LintConfig};
+use crate::code::{Code, CodeSpan};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::interpreter::{BUILTINS, SIGNATURES};
+use crate::parser::{is_symbol_char, parse_recovering, ParseError, Recovered};
+use crate::syntax::{Node, NodeKind};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// Serve one client until it sends `exit` or closes the input.
+///
+/// A frame without a `Content-Length` or with a body that is not JSON gets a
+/// parse error reply and the server carries on with the next one.
+pub fn run<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server::new();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let error = json!({ "code": PARSE_ERROR, "message": e.to_string() });
+                write_message(&mut output, &json!({ "jsonrpc": "2.0", "id": Value::Null, "error": error }))?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+/// The next message, or `None` at the end of the input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Open documents and the requests about them.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The responses and notifications one incoming message calls for.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let method = message["method"].as_str().unwrap_or_default();
+
+        // Notifications
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                if let Some(text) = params["contentChanges"].as_array().and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri, text.to_string());
+                }
+                return vec![];
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, vec![])];
+            }
+            _ => {}
+        }
+
+        let Some(id) = message.get("id").cloned() else {
+            return vec![];
+        };
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": { "includeText": true } },
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["("] },
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": { "name": "stonkscheme", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Ok(Value::Null),
+            "textDocument/hover"
+            | "textDocument/completion"
+            | "textDocument/definition"
+            | "textDocument/documentSymbol"
+            | "textDocument/rename" => match self.documents.get(&uri) {
+                Some(text) => {
+                    let analysis = Analysis::new(text);
+                    match method {
+                        "textDocument/hover" => Ok(analysis.hover(params)),
+                        "textDocument/completion" => Ok(analysis.completion(params)),
+                        "textDocument/definition" => Ok(analysis.definition(&uri, params)),
+                        "textDocument/documentSymbol" => Ok(analysis.symbols()),
+                        _ => analysis.rename(&uri, params),
+                    }
+                }
+                None => Err((INVALID_PARAMS, format!("`{}` is not open", uri))),
+            },
+            other => Err((METHOD_NOT_FOUND, format!("unsupported method `{}`", other))),
+        };
+        vec![match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        }]
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map(String::as_str).unwrap_or_default();
+        let analysis = Analysis::new(text);
+        let reports = analysis.errors.iter().map(ParseError::diagnostic).chain(check(&analysis.nodes, &LintConfig::default()));
+        let diagnostics = reports.filter_map(|report| analysis.diagnostic(uri, &report)).collect();
+        publish(uri, diagnostics)
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefinitionKind {
+    Input,
+    Variable,
+}
+
+/// Where a name is bound
+struct Definition {
+    name: String,
+    kind: DefinitionKind,
+    /// The name itself
+    span: CodeSpan,
+    /// The `set` form or input declaration around it
+    form: CodeSpan,
+}
+
+/// One parse of a document and the names it defines
+struct Analysis {
+    code: Arc<Code>,
+    nodes: Vec<Node>,
+    errors: Vec<ParseError>,
+    /// First definition of each name, in source order
+    definitions: Vec<Definition>,
+}
+
+impl Analysis {
+    fn new(text: &str) -> Self {
+        let code = Code::from_snippet(text);
+        let Recovered { nodes, errors } = parse_recovering(&code);
+        let mut definitions = Vec::new();
+        for node in &nodes {
+            collect_definitions(node, &mut definitions);
+        }
+        Self { code, nodes, errors, definitions }
+    }
+
+    /// The LSP form of a report, which needs a span to point at
+    fn diagnostic(&self, uri: &str, report: &Diagnostic) -> Option<Value> {
+        let span = report.primary_span()?;
+        let related: Vec<Value> = report
+            .labels
+            .iter()
+            .filter(|label| !label.primary)
+            .map(|label| json!({ "location": self.location(uri, &label.span), "message": label.message }))
+            .collect();
+        Some(json!({
+            "range": self.range(span),
+            "severity": if report.severity == Severity::Error { 1 } else { 2 },
+            "source": "stonkscheme",
+            "message": report.message,
+            "relatedInformation": related,
+        }))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some((name, span)) = self.symbol_at(params) else {
+            return Value::Null;
+        };
+        let contents = if let Some((_, signature, summary)) = SIGNATURES.iter().find(|(n, _, _)| *n == name) {
+            format!("```scheme\n{}\n```\n{}", signature, summary)
+        } else if let Some(def) = self.definition_of(name) {
+            let kind = if def.kind == DefinitionKind::Input { "input" } else { "variable" };
+            format!("```scheme\n{}\n```\n{} `{}`", &self.code.text[def.form.start..def.form.end], kind, name)
+        } else {
+            return Value::Null;
+        };
+        json!({ "contents": { "kind": "markdown", "value": contents }, "range": self.range(&span) })
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let offset = self.offset(&params["position"]);
+        let text = &self.code.text[..offset];
+        let prefix = &text[text.trim_end_matches(is_symbol_char).len()..];
+
+        let mut items = Vec::new();
+        for (name, signature, _) in SIGNATURES {
+            items.push(json!({ "label": name, "kind": 3, "detail": signature }));
+        }
+        for def in &self.definitions {
+            let detail = if def.kind == DefinitionKind::Input { "input" } else { "variable" };
+            items.push(json!({ "label": def.name, "kind": 6, "detail": detail }));
+        }
+        items.retain(|item| item["label"].as_str().is_some_and(|label| label.starts_with(prefix)));
+        json!(items)
+    }
+
+    fn definition(&self, uri: &str, params: &Value) -> Value {
+        self.symbol_at(params)
+            .and_then(|(name, _)| self.definition_of(name))
+            .map_or(Value::Null, |def| self.location(uri, &def.span))
+    }
+
+    fn symbols(&self) -> Value {
+        let symbols: Vec<Value> = self
+            .definitions
+            .iter()
+            .map(|def| {
+                json!({
+                    "name": def.name,
+                    "detail": if def.kind == DefinitionKind::Input { "input" } else { "variable" },
+                    "kind": if def.kind == DefinitionKind::Input { 14 } else { 13 },
+                    "range": self.range(&def.form),
+                    "selectionRange": self.range(&def.span),
+                })
+            })
+            .collect();
+        json!(symbols)
+    }
+
+    fn rename(&self, uri: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let Some((name, _)) = self.symbol_at(params) else {
+            return Err((REQUEST_FAILED, "there is no name here to rename".to_string()));
+        };
+        if BUILTINS.contains(&name) {
+            return Err((REQUEST_FAILED, format!("`{}` is a builtin and cannot be renamed", name)));
+        }
+        let new_name = params["newName"].as_str().unwrap_or_default();
+        if new_name.is_empty() || !new_name.chars().all(is_symbol_char) || new_name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err((INVALID_PARAMS, format!("`{}` is not a valid name", new_name)));
+        }
+
+        let mut edits = Vec::new();
+        for node in &self.nodes {
+            visit_references(node, &mut |symbol, span| {
+                if symbol == name {
+                    edits.push(json!({ "range": self.range(span), "newText": new_name }));
+                }
+            });
+        }
+        Ok(json!({ "changes": { uri: edits } }))
+    }
+
+    fn definition_of(&self, name: &str) -> Option<&Definition> {
+        self.definitions.iter().find(|def| def.name == name)
+    }
+
+    /// The symbol under `params.position`, including right after its last character
+    fn symbol_at(&self, params: &Value) -> Option<(&str, CodeSpan)> {
+        let offset = self.offset(&params["position"]);
+        let mut found = None;
+        for node in &self.nodes {
+            visit_symbols(node, &mut |_, span| {
+                if span.start <= offset && offset <= span.end {
+                    found = Some(span.clone());
+                }
+            });
+        }
+        found.map(|span| (&self.code.text[span.start..span.end], span))
+    }
+
+    fn location(&self, uri: &str, span: &CodeSpan) -> Value {
+        json!({ "uri": uri, "range": self.range(span) })
+    }
+
+    fn range(&self, span: &CodeSpan) -> Value {
+        json!({ "start": position(&self.code.text, span.start), "end": position(&self.code.text, span.end) })
+    }
+
+    fn offset(&self, position: &Value) -> usize {
+        offset(&self.code.text, position["line"].as_u64().unwrap_or(0), position["character"].as_u64().unwrap_or(0))
+    }
+}
+
+/// Record the names bound by `set` and `inputs` forms anywhere in `node`.
+fn collect_definitions(node: &Node, definitions: &mut Vec<Definition>) {
+    let mut define = |name: &Node, kind, form: &Node| {
+        if let NodeKind::Atom(Expr::Symbol(symbol)) = &name.kind
+            && !definitions.iter().any(|def| def.name == symbol.0)
+        {
+            definitions.push(Definition { name: symbol.0.clone(), kind, span: name.span.clone(), form: form.span.clone() });
+        }
+    };
+    if let NodeKind::Combination(items) = &node.kind
+        && let NodeKind::Atom(Expr::Symbol(head)) = &items[0].kind
+    {
+        match head.0.as_str() {
+            "set" if items.len() > 1 => define(&items[1], DefinitionKind::Variable, node),
+            "inputs" => {
+                for decl in &items[1..] {
+                    if let NodeKind::Combination(parts) = &decl.kind {
+                        define(&parts[0], DefinitionKind::Input, decl);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for child in node.children() {
+        collect_definitions(child, definitions);
+    }
+}
+
+fn visit_symbols(node: &Node, f: &mut impl FnMut(&str, &CodeSpan)) {
+    match &node.kind {
+        NodeKind::Atom(Expr::Symbol(symbol)) => f(&symbol.0, &node.span),
+        _ => {
+            for child in node.children() {
+                visit_symbols(child, f);
+            }
+        }
+    }
+}
+
+/// Like [`visit_symbols`], but skips bare values after keywords, as `line` in
+/// `:style line`, which name an option rather than a variable.
+fn visit_references(node: &Node, f: &mut impl FnMut(&str, &CodeSpan)) {
+    match &node.kind {
+        NodeKind::Combination(items) | NodeKind::Array(items) => {
+            let mut after_keyword = false;
+            for item in items {
+                let is_keyword = matches!(&item.kind, NodeKind::Atom(Expr::Symbol(name)) if name.starts_with(':'));
+                if !(after_keyword && matches!(item.kind, NodeKind::Atom(Expr::Symbol(_)))) {
+                    visit_references(item, f);
+                }
+                after_keyword = is_keyword;
+            }
+        }
+        _ => visit_symbols(node, f),
+    }
+}
+
+/// LSP position of a byte offset: a 0-based line and a column in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({ "line": before.matches('\n').count(), "character": before[line_start..].encode_utf16().count() })
+}
+
+/// Byte offset of an LSP position, clamped to its line.
+fn offset(text: &str, line: u64, character: u64) -> usize {
+    let line_start = text.split_inclusive('\n').take(line as usize).map(str::len).sum::<usize>().min(text.len());
+    let line_text = text[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line_text.char_indices() {
+        if units >= character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line_text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notify(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn at(line: u64, character: u64) -> Value {
+        json!({ "textDocument": { "uri": "file:///s.scm" }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn answers_a_session_over_stdio() {
+        let uri = "file:///s.scm";
+        let broken = "(inputs (size 10))\n(set fast (+ close 1)\n(buy size)";
+        let fixed = "(inputs (size 10))\n(set fast (+ close 1))\n(if (> fast open) (buy size))";
+        let mut rename = at(2, 26);
+        rename["newName"] = json!("qty");
+        let messages = [
+            request(1, "initialize", json!({ "capabilities": {} })),
+            notify("initialized", json!({})),
+            notify("textDocument/didOpen", json!({ "textDocument": { "uri": uri, "languageId": "scheme", "version": 1, "text": broken } })),
+            notify("textDocument/didChange", json!({ "textDocument": { "uri": uri, "version": 2 }, "contentChanges": [{ "text": fixed }] })),
+            notify("textDocument/didSave", json!({ "textDocument": { "uri": uri } })),
+            request(2, "textDocument/hover", at(2, 20)),
+            request(3, "textDocument/definition", at(2, 8)),
+            request(4, "textDocument/completion", at(2, 9)),
+            request(5, "textDocument/documentSymbol", json!({ "textDocument": { "uri": uri } })),
+            request(6, "textDocument/rename", rename),
+            request(7, "textDocument/formatting", json!({})),
+            request(8, "shutdown", Value::Null),
+            notify("exit", Value::Null),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        assert_eq!(replies.len(), 10);
+        assert_eq!(replies[0]["result"]["capabilities"]["renameProvider"], true);
+
+        let diagnostics = &replies[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["message"], "expected `)` to close `(` opened at 2:1");
+        assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 1, "character": 0 }));
+        assert_eq!(diagnostics[1]["message"], "variable `fast` is never read");
+        assert_eq!(diagnostics[1]["severity"], 2);
+        assert_eq!(replies[2]["params"]["diagnostics"], json!([]));
+
+        assert!(replies[3]["result"]["contents"]["value"].as_str().unwrap().contains("(buy quantity)"));
+        assert_eq!(replies[4]["result"]["range"]["start"], json!({ "line": 1, "character": 5 }));
+        let labels: Vec<&str> = replies[5]["result"].as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect();
+        assert_eq!(labels, ["fast"]);
+        let symbols: Vec<&str> = replies[6]["result"].as_array().unwrap().iter().map(|s| s["name"].as_str().unwrap()).collect();
+        assert_eq!(symbols, ["size", "fast"]);
+        let edits = replies[7]["result"]["changes"][uri].as_array().unwrap();
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[1]["range"]["start"], json!({ "line": 2, "character": 23 }));
+        assert_eq!(replies[8]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(replies[9]["result"], Value::Null);
+    }
+
+    #[test]
+    fn answers_malformed_frames_and_keeps_keyword_values() {
+        let uri = "file:///s.scm";
+        let mut rename = at(1, 7);
+        rename["newName"] = json!("width");
+        let mut input = b"Content-Length: 3\r\n\r\n{x}X-Other: 1\r\n\r\n".to_vec();
+        let text = "(set line 1)\n(plot line :style line)";
+        write_message(&mut input, &notify("textDocument/didOpen", json!({ "textDocument": { "uri": uri, "text": text } })))
+            .unwrap();
+        write_message(&mut input, &request(1, "textDocument/rename", rename)).unwrap();
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0]["error"]["code"], PARSE_ERROR);
+        assert_eq!(replies[1]["error"]["code"], PARSE_ERROR);
+        let edits = replies[3]["result"]["changes"][uri].as_array().unwrap();
+        let starts: Vec<&Value> = edits.iter().map(|edit| &edit["range"]["start"]).collect();
+        assert_eq!(starts, [&json!({ "line": 0, "character": 5 }), &json!({ "line": 1, "character": 6 })]);
+    }
+
+    #[test]
+    fn converts_utf16_positions() {
+        let text = "(display \"📈\")\n(buy 1)";
+        let after_emoji = text.find("\")").unwrap();
+        assert_eq!(position(text, after_emoji), json!({ "line": 0, "character": 12 }));
+        assert_eq!(offset(text, 0, 12), after_emoji);
+        assert_eq!(offset(text, 1, 1), text.find("buy").unwrap());
+        assert_eq!(offset(text, 1, 99), text.len());
+    }
+}
@@ -1,14 +1,27 @@
-use crate::ast::Expr;
-use crate::interpreter::Interpreter;
 use clap::{Parser, Subcommand};
-use std::io::Write;
-use std::path::PathBuf;
-use std::str::FromStr;
-
-mod ast;
-mod parser;
-mod code;
-mod interpreter;
+use indexmap::IndexMap;
+use stonkscheme::ast::{Duration, Expr};
+use stonkscheme::backtest;
+use stonkscheme::bars::{self, Bar, DataStream};
+use stonkscheme::broker::{BrokerConfig, Commission, FillModel, Slippage, Trade};
+use stonkscheme::calendar::{Calendar, Session, TimeZone};
+use stonkscheme::check::{check, Level, Lint, LintConfig};
+use stonkscheme::code::{Code, Spanned};
+use stonkscheme::diagnostics::{use_color, Diagnostic, Severity};
+use stonkscheme::format::{format_code, FormatConfig};
+use stonkscheme::lsp;
+use stonkscheme::montecarlo::{self, MonteCarloConfig, Resampling};
+use stonkscheme::optimize::{self, parse_param, Metric, OptimizeConfig, ParamSpace, Search};
+use stonkscheme::parser::{parse_recovering_file, ParseError, Recovered};
+use stonkscheme::portfolio::{self, PortfolioConfig, Sizing};
+use stonkscheme::repl;
+use stonkscheme::report::format_trades;
+use stonkscheme::server::{self, ServerConfig};
+use stonkscheme::syntax::{format_tree, Node};
+use stonkscheme::walkforward::{self, WalkForwardConfig};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[derive(Parser, Debug)]
 #[clap(version, about, long_about = None)]
@@ -21,8 +34,259 @@ struct Cli {
 enum Commands {
     /// Run the REPL
     Repl { file: Option<PathBuf> },
-    /// Run the parser on a file
-    Parse { file: PathBuf },
+    /// Parse a file and print its syntax tree
+    Parse {
+        file: PathBuf,
+        /// Print the tree as JSON, with the span of every node
+        #[clap(long)]
+        json: bool,
+    },
+    /// Lint scripts without running them
+    Check {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+        /// Silence a lint, e.g. `unused-variable`; repeatable
+        #[clap(long, value_name = "LINT")]
+        allow: Vec<Lint>,
+        /// Report a lint as a warning; repeatable
+        #[clap(long, value_name = "LINT")]
+        warn: Vec<Lint>,
+        /// Report a lint as an error and fail; repeatable
+        #[clap(long, value_name = "LINT")]
+        deny: Vec<Lint>,
+    },
+    /// Rewrite scripts in the canonical layout
+    Fmt {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+        /// List the files that would change and fail instead of rewriting them
+        #[clap(long)]
+        check: bool,
+        /// Spaces a form's body is indented by
+        #[clap(long, default_value_t = 2)]
+        indent: usize,
+        /// Line width to fit forms within
+        #[clap(long, default_value_t = 100)]
+        width: usize,
+        /// `name=N` to lay out `name` forms as a body after N arguments, once per form
+        #[clap(long = "rule")]
+        rules: Vec<String>,
+    },
+    /// Backtest a script against a CSV of OHLCV bars
+    Backtest {
+        file: PathBuf,
+        #[clap(flatten)]
+        broker: BrokerArgs,
+        /// Print the report and trade list as JSON
+        #[clap(long)]
+        json: bool,
+        /// Also print the trade list
+        #[clap(long)]
+        trades: bool,
+        /// Resample the trade list this many times and report outcome percentiles
+        #[clap(long = "monte-carlo")]
+        monte_carlo: Option<usize>,
+        /// `bootstrap` or `shuffle`
+        #[clap(long, default_value = "bootstrap")]
+        resampling: Resampling,
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+        /// Fraction of starting capital at which an account counts as ruined
+        #[clap(long, default_value_t = 0.5)]
+        ruin_level: f64,
+        /// Render the script's plots into this directory as SVG files
+        #[clap(long)]
+        plot_dir: Option<PathBuf>,
+    },
+    /// Sweep a script's inputs and rank the backtests
+    Optimize {
+        file: PathBuf,
+        #[clap(flatten)]
+        broker: BrokerArgs,
+        #[clap(flatten)]
+        sweep: SweepArgs,
+        /// How many of the best results to print
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Optimize on rolling in-sample windows and trade the following out-of-sample windows
+    WalkForward {
+        file: PathBuf,
+        #[clap(flatten)]
+        broker: BrokerArgs,
+        #[clap(flatten)]
+        sweep: SweepArgs,
+        /// In-sample window length, e.g. `180d`
+        #[clap(long)]
+        in_sample: Duration,
+        /// Out-of-sample window length, e.g. `30d`
+        #[clap(long)]
+        out_of_sample: Duration,
+        /// Window step, defaults to the out-of-sample length and may not be shorter
+        #[clap(long)]
+        step: Option<Duration>,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Backtest a script on every symbol at once, from one shared account.
+    /// `--data` is a directory with one CSV per symbol, named after it
+    Portfolio {
+        file: PathBuf,
+        #[clap(flatten)]
+        broker: BrokerArgs,
+        /// Only these symbols, e.g. `SPY,QQQ`, defaults to every CSV in `--data`
+        #[clap(long, value_delimiter = ',')]
+        symbols: Vec<String>,
+        /// `script`, `percent-equity:F` or `equal-weight`, for orders that open or add to a position
+        #[clap(long, default_value = "script")]
+        sizing: Sizing,
+        /// Most the open positions may be worth together, as a multiple of equity
+        #[clap(long)]
+        max_exposure: Option<f64>,
+        /// Worker threads, defaults to the number of CPUs
+        #[clap(long)]
+        workers: Option<usize>,
+        #[clap(long)]
+        json: bool,
+        /// Also print each symbol's trade list
+        #[clap(long)]
+        trades: bool,
+    },
+    /// Run a language server for editors over stdio
+    Lsp,
+    /// Serve the web dashboard
+    Serve {
+        /// Directory of CSV datasets offered in the dashboard
+        #[clap(long, default_value = ".")]
+        data_dir: PathBuf,
+        #[clap(long, default_value = "127.0.0.1:3000")]
+        addr: SocketAddr,
+        /// Scripts run at once, defaults to the number of CPUs
+        #[clap(long)]
+        workers: Option<usize>,
+    },
+}
+
+/// Parameter sweep settings shared by `optimize` and `walk-forward`
+#[derive(clap::Args, Debug)]
+struct SweepArgs {
+    /// `name=start:stop:step` or `name=v1,v2,...`, once per input
+    #[clap(long = "param", required = true)]
+    params: Vec<String>,
+    /// `net-profit`, `profit-factor`, `sharpe`, `sortino`, `cagr`, `win-rate` or `max-drawdown`
+    #[clap(long, default_value = "net-profit")]
+    metric: Metric,
+    /// `grid`, `random:N` or `genetic:POPxGENERATIONS`
+    #[clap(long, default_value = "grid")]
+    search: Search,
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    /// Worker threads, defaults to the number of CPUs
+    #[clap(long)]
+    workers: Option<usize>,
+}
+
+impl SweepArgs {
+    fn space(&self) -> Result<ParamSpace, String> {
+        let mut space = ParamSpace::new();
+        for spec in &self.params {
+            let (name, values) = parse_param(spec)?;
+            space.insert(name, values);
+        }
+        Ok(space)
+    }
+
+    fn config(&self, broker: &BrokerArgs) -> Result<OptimizeConfig, String> {
+        let mut config = OptimizeConfig {
+            broker: broker.config()?,
+            metric: self.metric,
+            search: self.search,
+            seed: self.seed,
+            ..OptimizeConfig::default()
+        };
+        if let Some(workers) = self.workers {
+            config.workers = workers;
+        }
+        Ok(config)
+    }
+}
+
+/// Data and account settings shared by the backtesting commands
+#[derive(clap::Args, Debug)]
+struct BrokerArgs {
+    /// CSV with `timestamp,open,high,low,close[,volume]` rows
+    #[clap(long)]
+    data: PathBuf,
+    #[clap(long, default_value_t = 100_000.0)]
+    cash: f64,
+    /// `none`, `per-share:X`, `per-trade:X` or `percent:X`
+    #[clap(long, default_value = "none")]
+    commission: Commission,
+    /// `none`, `per-share:X` or `percent:X`
+    #[clap(long, default_value = "none")]
+    slippage: Slippage,
+    /// `next-bar-open` or `this-bar-close`
+    #[clap(long, default_value = "next-bar-open")]
+    fill: FillModel,
+    /// `nyse`, `nasdaq`, `cme`, `lse`, `xetra`, `tse` or `24x7`, for the session builtins
+    #[clap(long, default_value = "24x7")]
+    exchange: String,
+    /// Overrides the exchange's time zone, e.g. `America/New_York` or `EST5EDT,M3.2.0,M11.1.0`
+    #[clap(long)]
+    time_zone: Option<TimeZone>,
+    /// Overrides the exchange's trading hours, e.g. `09:30-16:00`
+    #[clap(long)]
+    session: Option<Session>,
+    /// File of `YYYY-MM-DD [HH:MM]` lines: days the exchange is closed or closes early
+    #[clap(long)]
+    holidays: Option<PathBuf>,
+    /// A period like `1h` to resample `--data` into, or another CSV; each is read as `:data 2` onwards
+    #[clap(long = "stream")]
+    streams: Vec<String>,
+    /// Fraction of a position's value held as margin, as reported by `margin-used`
+    #[clap(long, default_value_t = 1.0)]
+    margin: f64,
+}
+
+impl BrokerArgs {
+    fn config(&self) -> Result<BrokerConfig, String> {
+        let mut calendar = Calendar::exchange(&self.exchange)?;
+        if let Some(time_zone) = &self.time_zone {
+            calendar.time_zone = time_zone.clone();
+        }
+        if let Some(session) = self.session {
+            calendar.session = session;
+        }
+        if let Some(path) = &self.holidays {
+            calendar.load_holidays(path)?;
+        }
+        if !(self.margin.is_finite() && self.margin >= 0.0) {
+            return Err(format!("`--margin {}` must be a fraction of at least 0", self.margin));
+        }
+        let mut data_streams = Vec::new();
+        for stream in &self.streams {
+            data_streams.push(match stream.parse::<DataStream>() {
+                Ok(resampled) => resampled,
+                Err(_) if Path::new(stream).is_file() => {
+                    DataStream::Bars(bars::load_csv(Path::new(stream)).map_err(|e| format!("{}: {}", stream, e))?.into())
+                }
+                Err(e) => return Err(format!("`--stream {}` is neither a period nor a file: {}", stream, e)),
+            });
+        }
+        let config = BrokerConfig {
+            initial_cash: self.cash,
+            commission: self.commission,
+            slippage: self.slippage,
+            fill_model: self.fill,
+            calendar,
+            data_streams,
+            margin: self.margin,
+        };
+        config.validate()?;
+        Ok(config)
+    }
 }
 
 
@@ -31,37 +295,331 @@ fn main() {
     let command = cli.command.unwrap_or(Commands::Repl { file: None });
 
     match command {
-        Commands::Parse { file } => {
-            println!("Parsing file: {:?}", file);
+        Commands::Parse { file, json } => exit_on_error(parse(&file, json)),
+        Commands::Check { files, allow, warn, deny } => {
+            let mut config = LintConfig::default();
+            for (lints, level) in [(allow, Level::Allow), (warn, Level::Warn), (deny, Level::Deny)] {
+                for lint in lints {
+                    config.set(lint, level);
+                }
+            }
+            exit_on_error(lint(&files, &config))
+        }
+        Commands::Fmt { files, check, indent, width, rules } => {
+            let config = format_config(indent, width, &rules).map_err(Diagnostic::from);
+            exit_on_error(config.and_then(|config| fmt(&files, check, &config)))
         }
         Commands::Repl { file } => {
-            let mut interpreter = Interpreter::new();
+            let mut session = repl::Session::new();
             if let Some(file) = file {
-                println!("Running REPL with file: {:?}", file);
-            } else {
-                println!("Running REPL without file");
+                match session.load(&file) {
+                    Ok(loaded) => println!("{}", loaded),
+                    Err(e) => eprintln!("{}", e.render(use_color())),
+                }
+            }
+            if let Err(e) = repl::run(&mut session) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Backtest { file, broker, json, trades, monte_carlo, resampling, seed, ruin_level, plot_dir } => {
+            let monte_carlo = monte_carlo.map(|simulations| MonteCarloConfig {
+                simulations,
+                resampling,
+                seed,
+                starting_capital: broker.cash,
+                ruin_level,
+            });
+            exit_on_error(backtest(&file, &broker, json, trades, monte_carlo, plot_dir.as_deref()));
+        }
+        Commands::Optimize { file, broker, sweep, top, json } => {
+            exit_on_error(optimize(&file, &broker, &sweep, top, json));
+        }
+        Commands::WalkForward { file, broker, sweep, in_sample, out_of_sample, step, json } => {
+            exit_on_error(walk_forward(&file, &broker, &sweep, in_sample, out_of_sample, step, json));
+        }
+        Commands::Portfolio { file, broker, symbols, sizing, max_exposure, workers, json, trades } => {
+            let workers = workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            let run = || {
+                let config = PortfolioConfig { broker: broker.config()?, sizing, max_exposure, workers };
+                portfolio(&file, &broker.data, &symbols, &config, json, trades)
+            };
+            exit_on_error(run());
+        }
+        Commands::Lsp => exit_on_error(lsp::run(std::io::stdin().lock(), std::io::stdout().lock()).map_err(|e| e.to_string().into())),
+        Commands::Serve { data_dir, addr, workers } => {
+            let workers = workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            exit_on_error(serve(ServerConfig { addr, data_dir, workers }));
+        }
+    }
+}
+
+fn exit_on_error(result: Result<(), Diagnostic>) {
+    if let Err(e) = result {
+        eprintln!("{}", e.render(use_color()));
+        std::process::exit(1);
+    }
+}
+
+/// Print the syntax tree, as far as it could be read, then any errors.
+fn parse(file: &Path, json: bool) -> Result<(), Diagnostic> {
+    let Recovered { nodes, errors } = parse_recovering_file(file).map_err(|e| e.diagnostic())?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&nodes).map_err(|e| e.to_string())?);
+    } else {
+        print!("{}", format_tree(&nodes));
+    }
+    report_parse_errors(&errors)
+}
+
+/// Print every parse error and lint finding, failing if any is an error.
+fn lint(files: &[PathBuf], config: &LintConfig) -> Result<(), Diagnostic> {
+    let (mut errors, mut warnings) = (0, 0);
+    for file in files {
+        let Recovered { nodes, errors: parse_errors } = parse_recovering_file(file).map_err(|e| e.diagnostic())?;
+        let reports = parse_errors.iter().map(ParseError::diagnostic).chain(check(&nodes, config));
+        for report in reports {
+            match report.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+            }
+            eprintln!("{}\n", report.render(use_color()));
+        }
+    }
+    let plural = |n: usize, noun: &str| format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" });
+    match (errors, warnings) {
+        (0, _) => Ok(()),
+        (errors, 0) => Err(format!("aborting due to {}", plural(errors, "error")).into()),
+        (errors, warnings) => {
+            Err(format!("aborting due to {}; {} emitted", plural(errors, "error"), plural(warnings, "warning")).into())
+        }
+    }
+}
+
+fn format_config(indent: usize, width: usize, rules: &[String]) -> Result<FormatConfig, String> {
+    let mut config = FormatConfig { indent, width, ..FormatConfig::default() };
+    for rule in rules {
+        let (name, n) = rule.split_once('=').ok_or_else(|| format!("expected `name=N`, got `{}`", rule))?;
+        let n = n.parse().map_err(|_| format!("invalid argument count in `{}`", rule))?;
+        config.rules.insert(name.to_string(), n);
+    }
+    Ok(config)
+}
+
+/// Format every file, or with `check` list those that would change. A file that cannot be
+/// read, parsed or written has its errors printed and is skipped, and the run fails at the end.
+fn fmt(files: &[PathBuf], check: bool, config: &FormatConfig) -> Result<(), Diagnostic> {
+    let (mut unformatted, mut failed) = (0, 0);
+    for file in files {
+        match fmt_file(file, check, config) {
+            Ok(changed) => unformatted += changed as usize,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}\n", error.render(use_color()));
+                }
+                failed += 1;
             }
-            repl(&mut interpreter);
         }
     }
+    let would_reformat = if check { unformatted } else { 0 };
+    match (failed, would_reformat) {
+        (0, 0) => Ok(()),
+        (0, n) => Err(format!("{} of {} files would be reformatted", n, files.len()).into()),
+        (failed, 0) => Err(format!("{} of {} files could not be formatted", failed, files.len()).into()),
+        (failed, n) => Err(format!(
+            "{} of {} files could not be formatted and {} would be reformatted",
+            failed,
+            files.len(),
+            n
+        )
+        .into()),
+    }
+}
+
+/// Whether `file` changed, or would with `check`
+fn fmt_file(file: &Path, check: bool, config: &FormatConfig) -> Result<bool, Vec<Diagnostic>> {
+    let code = Code::from_file(file).map_err(|e| vec![format!("{}: {}", file.display(), e).into()])?;
+    let formatted = format_code(&code, config)
+        .map_err(|errors| errors.iter().map(ParseError::diagnostic).collect::<Vec<_>>())?;
+    if formatted == *code.text {
+        return Ok(false);
+    }
+    if check {
+        println!("Would reformat {}", file.display());
+    } else {
+        std::fs::write(file, formatted).map_err(|e| vec![format!("{}: {}", file.display(), e).into()])?;
+    }
+    Ok(true)
 }
 
-fn repl(interpreter: &mut Interpreter) {
-    let mut input = String::new();
-    loop {
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-        if std::io::stdin().read_line(&mut input).is_err() {
-            break;
+/// Return the only error, or print them all and return a summary.
+fn report_parse_errors(errors: &[ParseError]) -> Result<(), Diagnostic> {
+    match errors {
+        [] => Ok(()),
+        [only] => Err(only.diagnostic()),
+        all => {
+            for error in all {
+                eprintln!("{}\n", error.diagnostic().render(use_color()));
+            }
+            Err(format!("aborting due to {} syntax errors", all.len()).into())
         }
-        if input.trim() == "exit" {
-            break;
+    }
+}
+
+fn load_program(file: &Path) -> Result<Vec<Expr>, Diagnostic> {
+    Ok(load_forms(file)?.into_iter().map(|form| form.value).collect())
+}
+
+fn load_forms(file: &Path) -> Result<Vec<Spanned<Expr>>, Diagnostic> {
+    let Recovered { nodes, errors } = parse_recovering_file(file).map_err(|e| e.diagnostic())?;
+    report_parse_errors(&errors)?;
+    Ok(nodes.iter().map(Node::to_spanned).collect())
+}
+
+fn backtest(
+    file: &Path,
+    broker: &BrokerArgs,
+    json: bool,
+    trades: bool,
+    monte_carlo: Option<MonteCarloConfig>,
+    plot_dir: Option<&Path>,
+) -> Result<(), Diagnostic> {
+    let forms = load_forms(file)?;
+    let program: Vec<Expr> = forms.iter().map(|form| form.value.clone()).collect();
+    let bars = bars::load_csv(&broker.data).map_err(|e| e.to_string())?;
+    let result = backtest::run(&program, &bars, broker.config()?, IndexMap::new()).map_err(|e| e.diagnostic(&forms))?;
+    if let Some(dir) = plot_dir {
+        if result.plots.is_empty() {
+            eprintln!("The script made no plots");
         }
-        let expr = Expr::from_str(&input).expect("Failed to parse input");
-        match interpreter.eval(&expr) {
-            Ok(result) => println!("{:?}", result),
-            Err(e) => eprintln!("Error: {:?}", e),
+        for path in result.plots.write_svgs(dir).map_err(|e| e.to_string())? {
+            eprintln!("Wrote {}", path.display());
         }
-        input.clear();
     }
-}
\ No newline at end of file
+    let monte_carlo = monte_carlo.map(|config| montecarlo::simulate(&result.trades, &config));
+
+    if json {
+        let out = serde_json::json!({ "report": result.report, "trades": result.trades, "monte_carlo": monte_carlo });
+        println!("{}", serde_json::to_string_pretty(&out).map_err(|e| e.to_string())?);
+    } else {
+        println!("{}", result.report);
+        if trades {
+            print!("{}", format_trades(&result.trades));
+        }
+        if let Some(monte_carlo) = monte_carlo {
+            println!("\n{}", monte_carlo);
+        }
+    }
+    Ok(())
+}
+
+fn optimize(file: &Path, broker: &BrokerArgs, sweep: &SweepArgs, top: usize, json: bool) -> Result<(), Diagnostic> {
+    let program: Arc<[Expr]> = load_program(file)?.into();
+    let bars: Arc<[Bar]> = bars::load_csv(&broker.data).map_err(|e| e.to_string())?.into();
+    let config = sweep.config(broker)?;
+
+    let results = optimize::optimize(program, bars, &sweep.space()?, &config)?;
+    let best = &results[..top.min(results.len())];
+    if json {
+        println!("{}", serde_json::to_string_pretty(best).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    println!("{} of {} candidates, ranked by {:?}", best.len(), results.len(), config.metric);
+    for (rank, result) in best.iter().enumerate() {
+        let inputs: Vec<String> = result.inputs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        println!(
+            "{:>4}  score {:>12.4}  net {:>12.2}  trades {:>5}  {}",
+            rank + 1,
+            result.score,
+            result.report.net_profit,
+            result.report.total_trades,
+            inputs.join(" ")
+        );
+    }
+    Ok(())
+}
+
+fn portfolio(
+    file: &Path,
+    data: &Path,
+    only: &[String],
+    config: &PortfolioConfig,
+    json: bool,
+    trades: bool,
+) -> Result<(), Diagnostic> {
+    let forms = load_forms(file)?;
+    let program: Vec<Expr> = forms.iter().map(|form| form.value.clone()).collect();
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(data)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect())
+        .map_err(|e| format!("{}: {}", data.display(), e))?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "csv"));
+    paths.sort();
+    let mut symbols = IndexMap::new();
+    for path in paths {
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+        if only.is_empty() || only.iter().any(|symbol| symbol == name) {
+            let bars = bars::load_csv(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            symbols.insert(name.to_string(), bars);
+        }
+    }
+    if let Some(missing) = only.iter().find(|symbol| !symbols.contains_key(*symbol)) {
+        return Err(format!("there is no {}.csv in {}", missing, data.display()).into());
+    }
+    if symbols.is_empty() {
+        return Err(format!("{} has no CSV files", data.display()).into());
+    }
+
+    let result = portfolio::run(&program, &symbols, config, IndexMap::new()).map_err(|e| {
+        let mut diagnostic = e.error.diagnostic(&forms);
+        diagnostic.message = format!("{}: {}", e.symbol, diagnostic.message);
+        diagnostic
+    })?;
+    if json {
+        let out = serde_json::json!({ "report": result.report, "symbols": result.symbols, "trades": result.trades });
+        println!("{}", serde_json::to_string_pretty(&out).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+    println!("{}", result.report);
+    for (symbol, report) in &result.symbols {
+        println!("{:<10} net {:>12.2}  trades {:>5}", symbol, report.net_profit, report.total_trades);
+    }
+    if trades {
+        for symbol in result.symbols.keys() {
+            let trades: Vec<Trade> =
+                result.trades.iter().filter(|t| &t.symbol == symbol).map(|t| t.trade.clone()).collect();
+            print!("\n{}\n{}", symbol, format_trades(&trades));
+        }
+    }
+    Ok(())
+}
+
+fn serve(config: ServerConfig) -> Result<(), Diagnostic> {
+    tracing_subscriber::fmt().with_env_filter("info").init();
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    runtime.block_on(server::serve(config)).map_err(|e| e.to_string().into())
+}
+
+fn walk_forward(
+    file: &Path,
+    broker: &BrokerArgs,
+    sweep: &SweepArgs,
+    in_sample: Duration,
+    out_of_sample: Duration,
+    step: Option<Duration>,
+    json: bool,
+) -> Result<(), Diagnostic> {
+    let program: Arc<[Expr]> = load_program(file)?.into();
+    let bars = bars::load_csv(&broker.data).map_err(|e| e.to_string())?;
+    let config = WalkForwardConfig { in_sample, out_of_sample, step, optimize: sweep.config(broker)? };
+
+    let result = walkforward::walk_forward(program, &bars, &sweep.space()?, &config)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?);
+    } else {
+        print!("{}", result.format_windows());
+        println!("Walk-forward efficiency: {:.2}\n", result.efficiency);
+        println!("{}", result.report);
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Monte Carlo analysis of a backtest's trade list.
+//!
+//! Each simulation replays the trades' P&L in a new order, either a permutation
+//! of the original list or a bootstrap sample drawn with replacement, and tracks
+//! the resulting equity path. The RNG is seeded so a report is reproducible.
+
+use crate::broker::Trade;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Resampling {
+    /// Same trades, random order. Terminal equity never changes, drawdowns do.
+    Shuffle,
+    /// As many trades as the original, drawn with replacement.
+    Bootstrap,
+}
+
+impl FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shuffle" => Ok(Resampling::Shuffle),
+            "bootstrap" => Ok(Resampling::Bootstrap),
+            _ => Err(format!("unknown resampling `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonteCarloConfig {
+    pub simulations: usize,
+    pub resampling: Resampling,
+    pub seed: u64,
+    pub starting_capital: f64,
+    /// A path is ruined once equity falls to this fraction of the starting capital.
+    pub ruin_level: f64,
+}
+
+impl Default for MonteCarloConfig {
+    fn default() -> Self {
+        Self {
+            simulations: 1000,
+            resampling: Resampling::Bootstrap,
+            seed: 0,
+            starting_capital: 100_000.0,
+            ruin_level: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p5: f64,
+    pub p25: f64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p95: f64,
+}
+
+impl Percentiles {
+    /// Linearly interpolated percentiles of `values`.
+    fn of(mut values: Vec<f64>) -> Self {
+        values.sort_by(f64::total_cmp);
+        let at = |q: f64| {
+            if values.is_empty() {
+                return 0.0;
+            }
+            let rank = q * (values.len() - 1) as f64;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            values[lo] + (values[hi] - values[lo]) * (rank - lo as f64)
+        };
+        Self { p5: at(0.05), p25: at(0.25), p50: at(0.5), p75: at(0.75), p95: at(0.95) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonteCarloReport {
+    pub simulations: usize,
+    pub resampling: Resampling,
+    pub terminal_equity: Percentiles,
+    pub max_drawdown: Percentiles,
+    pub max_drawdown_pct: Percentiles,
+    /// Fraction of paths that hit the ruin level.
+    pub risk_of_ruin: f64,
+}
+
+pub fn simulate(trades: &[Trade], config: &MonteCarloConfig) -> MonteCarloReport {
+    let pnls: Vec<f64> = trades.iter().map(|t| t.pnl).collect();
+    let ruin_equity = config.starting_capital * config.ruin_level;
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut path = pnls.clone();
+
+    let mut terminal = Vec::with_capacity(config.simulations);
+    let mut drawdown = Vec::with_capacity(config.simulations);
+    let mut drawdown_pct = Vec::with_capacity(config.simulations);
+    let mut ruined = 0;
+    for _ in 0..config.simulations {
+        match config.resampling {
+            Resampling::Shuffle => path.shuffle(&mut rng),
+            Resampling::Bootstrap => {
+                for slot in path.iter_mut() {
+                    *slot = pnls[rng.random_range(0..pnls.len())];
+                }
+            }
+        }
+
+        let mut equity = config.starting_capital;
+        let mut peak = equity;
+        let (mut dd, mut dd_pct) = (0.0_f64, 0.0_f64);
+        let mut hit_ruin = equity <= ruin_equity;
+        for pnl in &path {
+            equity += pnl;
+            peak = peak.max(equity);
+            dd = dd.max(peak - equity);
+            if peak > 0.0 {
+                dd_pct = dd_pct.max((peak - equity) / peak);
+            }
+            hit_ruin |= equity <= ruin_equity;
+        }
+        terminal.push(equity);
+        drawdown.push(dd);
+        drawdown_pct.push(dd_pct);
+        ruined += hit_ruin as usize;
+    }
+
+    MonteCarloReport {
+        simulations: config.simulations,
+        resampling: config.resampling,
+        terminal_equity: Percentiles::of(terminal),
+        max_drawdown: Percentiles::of(drawdown),
+        max_drawdown_pct: Percentiles::of(drawdown_pct),
+        risk_of_ruin: if config.simulations == 0 { 0.0 } else { ruined as f64 / config.simulations as f64 },
+    }
+}
+
+impl fmt::Display for MonteCarloReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Monte Carlo ({} simulations, {:?})", self.simulations, self.resampling)?;
+        writeln!(f, "{:<18} {:>12} {:>12} {:>12} {:>12} {:>12}", "", "5%", "25%", "50%", "75%", "95%")?;
+        let row = |f: &mut fmt::Formatter<'_>, label: &str, p: &Percentiles, scale: f64| {
+            writeln!(
+                f,
+                "{:<18} {:>12.2} {:>12.2} {:>12.2} {:>12.2} {:>12.2}",
+                label,
+                p.p5 * scale,
+                p.p25 * scale,
+                p.p50 * scale,
+                p.p75 * scale,
+                p.p95 * scale
+            )
+        };
+        row(f, "Terminal Equity", &self.terminal_equity, 1.0)?;
+        row(f, "Max Drawdown", &self.max_drawdown, 1.0)?;
+        row(f, "Max Drawdown %", &self.max_drawdown_pct, 100.0)?;
+        write!(f, "Risk of Ruin: {:.2}%", self.risk_of_ruin * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::parse_timestamp;
+    use crate::broker::Direction;
+
+    fn trades(pnls: &[f64]) -> Vec<Trade> {
+        let ts = parse_timestamp("2024-01-01").unwrap();
+        pnls.iter()
+            .enumerate()
+            .map(|(i, &pnl)| Trade {
+                direction: Direction::Long,
+                quantity: 1,
+                entry_bar: i,
+                entry_time: ts.clone(),
+                entry_price: 100.0,
+                exit_bar: i + 1,
+                exit_time: ts.clone(),
+                exit_price: 100.0 + pnl,
+                commission: 0.0,
+                pnl,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shuffle_keeps_terminal_equity() {
+        let config = MonteCarloConfig { simulations: 200, resampling: Resampling::Shuffle, starting_capital: 1000.0, ..Default::default() };
+        let report = simulate(&trades(&[100.0, -50.0, 30.0, -80.0, 60.0]), &config);
+        assert_eq!(report.terminal_equity.p5, 1060.0);
+        assert_eq!(report.terminal_equity.p95, 1060.0);
+        // Best case loses nothing before the first winner; worst case strings both losers together.
+        assert!(report.max_drawdown.p95 <= 130.0);
+        assert!(report.max_drawdown.p5 >= 50.0);
+    }
+
+    #[test]
+    fn bootstrap_is_reproducible_and_finds_ruin() {
+        let config = MonteCarloConfig { simulations: 500, seed: 42, starting_capital: 1000.0, ruin_level: 0.5, ..Default::default() };
+        let trades = trades(&[300.0, -400.0, 250.0, -350.0]);
+        let a = simulate(&trades, &config);
+        let b = simulate(&trades, &config);
+        assert_eq!(a, b);
+        assert!(a.risk_of_ruin > 0.0 && a.risk_of_ruin < 1.0);
+        assert!(a.terminal_equity.p5 < a.terminal_equity.p95);
+    }
+
+    #[test]
+    fn no_trades_no_risk() {
+        let report = simulate(&[], &MonteCarloConfig::default());
+        assert_eq!(report.risk_of_ruin, 0.0);
+        assert_eq!(report.terminal_equity.p50, 100_000.0);
+    }
+}
@@ -0,0 +1,488 @@
+//! Parameter sweeps over a program's declared `inputs`.
+//!
+//! Every candidate is a full backtest. Candidates are spread over a pool of worker
+//! threads that share the program and bars, and the results are ranked by a
+//! [`Metric`]. Grid search tries every combination; random and genetic search
+//! sample the grid with a seeded RNG so a sweep is reproducible.
+
+use crate::ast::Expr;
+use crate::backtest;
+use crate::bars::Bar;
+use crate::broker::BrokerConfig;
+use crate::interpreter::declared_inputs;
+use crate::report::PerformanceReport;
+use indexmap::IndexMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Candidate values for each input being swept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParamSpace {
+    pub params: IndexMap<String, Vec<Expr>>,
+}
+
+impl ParamSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, values: Vec<Expr>) {
+        self.params.insert(name.into(), values);
+    }
+
+    /// Number of grid points, saturating at `usize::MAX`.
+    pub fn size(&self) -> usize {
+        self.params.values().try_fold(1usize, |n, values| n.checked_mul(values.len())).unwrap_or(usize::MAX)
+    }
+
+    /// Input overrides for a point given as one value index per parameter.
+    pub fn point(&self, indices: &[usize]) -> IndexMap<String, Expr> {
+        self.params
+            .iter()
+            .zip(indices)
+            .map(|((name, values), &i)| (name.clone(), values[i].clone()))
+            .collect()
+    }
+
+    fn grid(&self) -> Vec<Vec<usize>> {
+        let mut points = vec![vec![]];
+        for values in self.params.values() {
+            points = points
+                .into_iter()
+                .flat_map(|p| {
+                    (0..values.len()).map(move |i| {
+                        let mut p = p.clone();
+                        p.push(i);
+                        p
+                    })
+                })
+                .collect();
+        }
+        points
+    }
+
+    fn random_point(&self, rng: &mut StdRng) -> Vec<usize> {
+        self.params.values().map(|v| rng.random_range(0..v.len())).collect()
+    }
+}
+
+/// Most candidates a grid search, or one parameter's range, may have.
+pub const MAX_GRID: usize = 1_000_000;
+
+/// Parse `name=start:stop:step` (inclusive) or `name=v1,v2,...`.
+pub fn parse_param(spec: &str) -> Result<(String, Vec<Expr>), String> {
+    let (name, values) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected `name=values` in `{}`", spec))?;
+    let parse_value = |s: &str| Expr::from_str(s.trim()).map_err(|e| format!("bad value `{}`: {}", s, e));
+
+    let values = match values.split(':').collect::<Vec<_>>().as_slice() {
+        [start, stop, step] => match (parse_value(start)?, parse_value(stop)?, parse_value(step)?) {
+            (Expr::Integer(start), Expr::Integer(stop), Expr::Integer(step)) if step > 0 => {
+                let count = (i128::from(stop) - i128::from(start)) / i128::from(step) + 1;
+                if count > MAX_GRID as i128 {
+                    return Err(format!("`{}` has {} values, more than the {} allowed", spec, count, MAX_GRID));
+                }
+                (start..=stop).step_by(step as usize).map(Expr::Integer).collect()
+            }
+            (start, stop, step) => {
+                let (start, stop, step) = (as_f64(&start)?, as_f64(&stop)?, as_f64(&step)?);
+                if !(step > 0.0 && step.is_finite() && start.is_finite() && stop.is_finite()) {
+                    return Err(format!("step must be positive and the bounds finite in `{}`", spec));
+                }
+                let count = ((stop - start) / step + 1e-9).floor().max(-1.0) + 1.0;
+                if count > MAX_GRID as f64 {
+                    return Err(format!("`{}` has {} values, more than the {} allowed", spec, count, MAX_GRID));
+                }
+                (0..count as usize).map(|i| Expr::Float(start + i as f64 * step)).collect()
+            }
+        },
+        [list] => list.split(',').map(parse_value).collect::<Result<_, _>>()?,
+        _ => return Err(format!("bad range in `{}`", spec)),
+    };
+    Ok((name.trim().to_string(), values))
+}
+
+fn as_f64(expr: &Expr) -> Result<f64, String> {
+    match expr {
+        Expr::Integer(i) => Ok(*i as f64),
+        Expr::Float(f) => Ok(*f),
+        other => Err(format!("expected a number, found {:?}", other)),
+    }
+}
+
+/// What a sweep maximizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    NetProfit,
+    ProfitFactor,
+    Sharpe,
+    Sortino,
+    Cagr,
+    WinRate,
+    /// Minimized.
+    MaxDrawdown,
+}
+
+impl Metric {
+    /// Higher is better. A run without a losing trade scores an infinite profit factor.
+    pub fn score(&self, report: &PerformanceReport) -> f64 {
+        match self {
+            Metric::NetProfit => report.net_profit,
+            Metric::ProfitFactor => report.profit_factor,
+            Metric::Sharpe => report.sharpe,
+            Metric::Sortino => report.sortino,
+            Metric::Cagr => report.cagr,
+            Metric::WinRate => report.win_rate,
+            Metric::MaxDrawdown => -report.max_drawdown,
+        }
+    }
+
+    /// Best first: by score, then, for profit factor, by the number of winners, which
+    /// orders the runs that never lost among themselves.
+    fn rank(&self, a: &OptimizationResult, b: &OptimizationResult) -> std::cmp::Ordering {
+        let by_score = b.score.total_cmp(&a.score);
+        match self {
+            Metric::ProfitFactor => by_score.then(b.report.winning_trades.cmp(&a.report.winning_trades)),
+            _ => by_score,
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "net-profit" => Ok(Metric::NetProfit),
+            "profit-factor" => Ok(Metric::ProfitFactor),
+            "sharpe" => Ok(Metric::Sharpe),
+            "sortino" => Ok(Metric::Sortino),
+            "cagr" => Ok(Metric::Cagr),
+            "win-rate" => Ok(Metric::WinRate),
+            "max-drawdown" => Ok(Metric::MaxDrawdown),
+            _ => Err(format!("unknown metric `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    Grid,
+    Random { samples: usize },
+    Genetic { population: usize, generations: usize },
+}
+
+/// Parses `grid`, `random:N` or `genetic:POPxGENERATIONS`.
+impl FromStr for Search {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("unknown search `{}`", s);
+        match s.split_once(':') {
+            None if s == "grid" => Ok(Search::Grid),
+            Some(("random", n)) => Ok(Search::Random { samples: n.parse().map_err(|_| bad())? }),
+            Some(("genetic", spec)) => {
+                let (population, generations) = spec.split_once('x').ok_or_else(bad)?;
+                Ok(Search::Genetic {
+                    population: population.parse().map_err(|_| bad())?,
+                    generations: generations.parse().map_err(|_| bad())?,
+                })
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizeConfig {
+    pub broker: BrokerConfig,
+    pub metric: Metric,
+    pub search: Search,
+    pub seed: u64,
+    pub workers: usize,
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        Self {
+            broker: BrokerConfig::default(),
+            metric: Metric::NetProfit,
+            search: Search::Grid,
+            seed: 0,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptimizationResult {
+    #[serde(serialize_with = "serialize_inputs")]
+    pub inputs: IndexMap<String, Expr>,
+    #[serde(serialize_with = "crate::report::serialize_ratio")]
+    pub score: f64,
+    pub report: PerformanceReport,
+}
+
+fn serialize_inputs<S: Serializer>(inputs: &IndexMap<String, Expr>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(inputs.iter().map(|(name, value)| {
+        let value = match value {
+            Expr::Integer(i) => serde_json::json!(i),
+            Expr::Float(f) => serde_json::json!(f),
+            other => serde_json::json!(other.to_string()),
+        };
+        (name, value)
+    }))
+}
+
+/// Sweep `space` and return every evaluated candidate, best first.
+pub fn optimize(
+    program: Arc<[Expr]>,
+    bars: Arc<[Bar]>,
+    space: &ParamSpace,
+    config: &OptimizeConfig,
+) -> Result<Vec<OptimizationResult>, String> {
+    let declared = declared_inputs(&program)?;
+    for name in space.params.keys() {
+        if !declared.iter().any(|d| &d.name == name) {
+            return Err(format!("`{}` is not a declared input", name));
+        }
+    }
+    if space.params.values().any(Vec::is_empty) {
+        return Err("every parameter needs at least one value".to_string());
+    }
+
+    let mut sweep = Sweep { program, bars, space, config, evaluated: HashMap::new(), order: Vec::new() };
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    match config.search {
+        Search::Grid if space.size() > MAX_GRID => {
+            return Err(format!(
+                "the grid has {} candidates, more than the {} allowed; try `--search random:N` or `genetic:POPxGENERATIONS`",
+                space.size(),
+                MAX_GRID
+            ));
+        }
+        Search::Grid => sweep.evaluate(space.grid())?,
+        Search::Random { samples } if samples > MAX_GRID => {
+            return Err(format!("random search takes at most {} samples, not {}", MAX_GRID, samples));
+        }
+        Search::Random { samples } => {
+            // Drawn with replacement, stopping early once every point has come up
+            let mut seen = HashSet::new();
+            let mut points = Vec::new();
+            for _ in 0..samples {
+                if seen.len() == space.size() {
+                    break;
+                }
+                let point = space.random_point(&mut rng);
+                if seen.insert(point.clone()) {
+                    points.push(point);
+                }
+            }
+            sweep.evaluate(points)?;
+        }
+        Search::Genetic { population, generations } => {
+            let population = population.max(2);
+            let mut current: Vec<Vec<usize>> = (0..population).map(|_| space.random_point(&mut rng)).collect();
+            sweep.evaluate(current.clone())?;
+            for _ in 0..generations {
+                let mut seen = HashSet::new();
+                current.retain(|p| seen.insert(p.clone()));
+                current.sort_by(|a, b| sweep.score(b).total_cmp(&sweep.score(a)));
+                current.truncate((population / 2).max(2));
+                let elites = current.clone();
+                while current.len() < population {
+                    let a = &elites[rng.random_range(0..elites.len())];
+                    let b = &elites[rng.random_range(0..elites.len())];
+                    let child = crossover(space, a, b, &mut rng);
+                    current.push(child);
+                }
+                sweep.evaluate(current.clone())?;
+            }
+        }
+    }
+    Ok(sweep.ranked())
+}
+
+/// Uniform crossover followed by per-gene mutation.
+fn crossover(space: &ParamSpace, a: &[usize], b: &[usize], rng: &mut StdRng) -> Vec<usize> {
+    let mutation_rate = 1.0 / space.params.len().max(1) as f64;
+    space
+        .params
+        .values()
+        .enumerate()
+        .map(|(i, values)| {
+            if rng.random_bool(mutation_rate) {
+                rng.random_range(0..values.len())
+            } else if rng.random_bool(0.5) {
+                a[i]
+            } else {
+                b[i]
+            }
+        })
+        .collect()
+}
+
+struct Sweep<'a> {
+    program: Arc<[Expr]>,
+    bars: Arc<[Bar]>,
+    space: &'a ParamSpace,
+    config: &'a OptimizeConfig,
+    evaluated: HashMap<Vec<usize>, OptimizationResult>,
+    /// Points in the order they were first evaluated, for stable ranking.
+    order: Vec<Vec<usize>>,
+}
+
+impl Sweep<'_> {
+    fn score(&self, point: &[usize]) -> f64 {
+        self.evaluated.get(point).map_or(f64::NEG_INFINITY, |r| r.score)
+    }
+
+    /// Backtest every point not seen yet, in parallel.
+    fn evaluate(&mut self, points: Vec<Vec<usize>>) -> Result<(), String> {
+        let mut pending = HashSet::new();
+        let mut todo: Vec<Vec<usize>> = Vec::new();
+        for point in points {
+            if !self.evaluated.contains_key(&point) && pending.insert(point.clone()) {
+                todo.push(point);
+            }
+        }
+
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Result<OptimizationResult, String>>>> = Mutex::new(vec![None; todo.len()]);
+        let (space, config) = (self.space, self.config);
+        std::thread::scope(|scope| {
+            for _ in 0..config.workers.clamp(1, todo.len().max(1)) {
+                let program = self.program.clone();
+                let bars = self.bars.clone();
+                let (todo, next, results) = (&todo, &next, &results);
+                scope.spawn(move || {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(point) = todo.get(i) else { break };
+                        let inputs = space.point(point);
+                        let result = backtest::run(&program, &bars, config.broker.clone(), inputs.clone())
+                            .map(|run| OptimizationResult {
+                                score: config.metric.score(&run.report),
+                                report: run.report,
+                                inputs,
+                            });
+                        results.lock().unwrap()[i] = Some(result.map_err(String::from));
+                    }
+                });
+            }
+        });
+
+        for (point, result) in todo.into_iter().zip(results.into_inner().unwrap()) {
+            let result = result.expect("every point is evaluated")?;
+            self.order.push(point.clone());
+            self.evaluated.insert(point, result);
+        }
+        Ok(())
+    }
+
+    fn ranked(mut self) -> Vec<OptimizationResult> {
+        let mut ranked: Vec<OptimizationResult> =
+            self.order.iter().map(|p| self.evaluated.remove(p).unwrap()).collect();
+        // Stable, so ties keep evaluation order
+        ranked.sort_by(|a, b| self.config.metric.rank(a, b));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::parse_csv;
+    use crate::parser::parse_program;
+
+    const BARS: &str = "\
+2024-01-01,10,11,9,10.5
+2024-01-02,10.5,12,10,11.5
+2024-01-03,11.5,12,10.5,11
+2024-01-04,11,11.5,10,10.2
+2024-01-05,10,10.5,9.5,10.4
+2024-01-06,10.4,11,10.2,10.9
+2024-01-07,10.9,12,10.8,11.8
+";
+
+    fn setup() -> (Arc<[Expr]>, Arc<[Bar]>) {
+        let program = parse_program("(inputs (size 1) (threshold 0)) (if (> (- close open) threshold) (buy size) (sell size))")
+            .unwrap()
+            .into_iter()
+            .map(|f| f.value)
+            .collect();
+        (program, parse_csv(BARS).unwrap().into())
+    }
+
+    #[test]
+    fn parses_param_specs() {
+        assert_eq!(parse_param("n=1:5:2").unwrap().1, vec![Expr::Integer(1), Expr::Integer(3), Expr::Integer(5)]);
+        assert_eq!(parse_param("x=0:0.5:0.25").unwrap().1.len(), 3);
+        assert_eq!(parse_param("x=1,2.5").unwrap().1, vec![Expr::Integer(1), Expr::Float(2.5)]);
+        assert!(parse_param("x").is_err());
+        assert!(parse_param("x=0:1:1e-12").is_err());
+        assert!(parse_param("n=0:9223372036854775807:1").is_err());
+    }
+
+    #[test]
+    fn grid_search_ranks_every_point() {
+        let (program, bars) = setup();
+        let mut space = ParamSpace::new();
+        space.insert("size", vec![Expr::Integer(1), Expr::Integer(2), Expr::Integer(3)]);
+        space.insert("threshold", vec![Expr::Float(0.0), Expr::Float(0.5)]);
+        let config = OptimizeConfig { workers: 3, ..OptimizeConfig::default() };
+        let results = optimize(program, bars, &space, &config).unwrap();
+        assert_eq!(results.len(), 6);
+        assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+
+    #[test]
+    fn searches_are_reproducible() {
+        let (program, bars) = setup();
+        let mut space = ParamSpace::new();
+        space.insert("size", (1..=20).map(Expr::Integer).collect());
+        space.insert("threshold", vec![Expr::Float(0.0), Expr::Float(0.3), Expr::Float(0.6)]);
+        for search in [Search::Random { samples: 10 }, Search::Genetic { population: 6, generations: 3 }] {
+            let config = OptimizeConfig { search, seed: 7, ..OptimizeConfig::default() };
+            let a = optimize(program.clone(), bars.clone(), &space, &config).unwrap();
+            let b = optimize(program.clone(), bars.clone(), &space, &config).unwrap();
+            let inputs = |rs: &[OptimizationResult]| rs.iter().map(|r| r.inputs.clone()).collect::<Vec<_>>();
+            assert_eq!(inputs(&a), inputs(&b));
+        }
+    }
+
+    #[test]
+    fn bounds_random_search_and_ranks_runs_that_never_lost_first() {
+        let (program, bars) = setup();
+        let mut space = ParamSpace::new();
+        space.insert("size", vec![Expr::Integer(1), Expr::Integer(2)]);
+        let config = |search| OptimizeConfig { search, metric: Metric::ProfitFactor, ..OptimizeConfig::default() };
+        let results = optimize(program.clone(), bars.clone(), &space, &config(Search::Random { samples: MAX_GRID }));
+        assert_eq!(results.unwrap().len(), 2);
+        assert!(optimize(program.clone(), bars.clone(), &space, &config(Search::Random { samples: MAX_GRID + 1 })).is_err());
+
+        let base = optimize(program, bars, &space, &config(Search::Grid)).unwrap().remove(0);
+        let run = |profit_factor: f64, winning_trades| {
+            let report = PerformanceReport { profit_factor, winning_trades, ..base.report.clone() };
+            OptimizationResult { score: Metric::ProfitFactor.score(&report), report, ..base.clone() }
+        };
+        let mut runs = [run(3.0, 30), run(f64::INFINITY, 1), run(f64::INFINITY, 2)];
+        runs.sort_by(|a, b| Metric::ProfitFactor.rank(a, b));
+        let order: Vec<(f64, usize)> = runs.iter().map(|r| (r.score, r.report.winning_trades)).collect();
+        assert_eq!(order, [(f64::INFINITY, 2), (f64::INFINITY, 1), (3.0, 30)]);
+    }
+
+    #[test]
+    fn rejects_undeclared_inputs() {
+        let (program, bars) = setup();
+        let mut space = ParamSpace::new();
+        space.insert("period", vec![Expr::Integer(1)]);
+        assert!(optimize(program, bars, &space, &OptimizeConfig::default()).is_err());
+    }
+}
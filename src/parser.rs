@@ -5,26 +5,100 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use nom::branch::alt;
-use nom::bytes::complete::{tag, take_while1};
-use nom::character::complete::multispace0;
-use nom::combinator::map_res;
+use nom::bytes::complete::{tag, take, take_while, take_while1};
+use nom::character::complete::{digit1, multispace1, one_of};
+use nom::combinator::{all_consuming, map_res, opt, recognize, value};
 use nom::error::{FromExternalError, ParseError as NomErr};
-use nom::multi::separated_list1;
+use nom::multi::many0;
 use nom::number::complete::recognize_float;
-use nom::sequence::{delimited, separated_pair};
-use nom::{IResult, Parser};
+use nom::sequence::{delimited, pair, preceded, terminated};
+use nom::{IResult, Input, Parser};
 use thiserror::Error;
 
-use crate::ast::{Expr, Symbol};
+use crate::ast::{Duration, Expr, Symbol, Unit};
+use crate::decimal::Decimal;
+use crate::bars::parse_timestamp;
 use crate::code::{Code, CodeSpan, ParserSpan, Spanned};
+use crate::diagnostics::Diagnostic;
+use crate::syntax::{Node, NodeKind};
 
 #[derive(Debug, Clone, Error, PartialEq)]
 pub enum ParseError {
-    #[error("nom error: {kind:?} at {span:?}")]
+    #[error("syntax error ({kind:?})")]
     Nom { kind: nom::error::ErrorKind, span: CodeSpan },
 
+    #[error("{msg}")]
+    Syntax { msg: String, span: CodeSpan },
+
+    /// `span` is the opening delimiter and `end` where its closer was due.
+    #[error("expected `{}` to close `{delimiter}` opened at {}", closer(*.delimiter), line_col(.span))]
+    Unclosed { delimiter: char, span: CodeSpan, end: CodeSpan },
+
+    /// A closer at `span` that does not match the list opened at `open`.
+    #[error("expected `{}` to close `{delimiter}` opened at {}, found `{found}`", closer(*.delimiter), line_col(.open))]
+    Mismatched { found: char, span: CodeSpan, delimiter: char, open: CodeSpan },
+
+    /// `close` is the closer of the innermost open list, which was also allowed here.
+    #[error("expected an expression{}, found `{found}`", .close.map(|c| format!(" or `{}`", c)).unwrap_or_default())]
+    Unexpected { found: char, span: CodeSpan, close: Option<char> },
+
+    #[error("cannot read {path}: {msg}")]
+    Io { path: String, msg: String, span: CodeSpan },
+
     #[error("invalid number `{value}` – {msg}")]
     BadInt { value: String, msg: String, span: CodeSpan },
+
+    #[error("invalid literal `{value}` – {msg}")]
+    BadLiteral { value: String, msg: String, span: CodeSpan },
+}
+
+impl ParseError {
+    pub fn span(&self) -> &CodeSpan {
+        match self {
+            ParseError::Nom { span, .. }
+            | ParseError::Syntax { span, .. }
+            | ParseError::Unclosed { span, .. }
+            | ParseError::Mismatched { span, .. }
+            | ParseError::Unexpected { span, .. }
+            | ParseError::Io { span, .. }
+            | ParseError::BadInt { span, .. }
+            | ParseError::BadLiteral { span, .. } => span,
+        }
+    }
+
+    /// The error as a report with labeled source snippets
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.to_string());
+        match self {
+            ParseError::Io { .. } => diagnostic,
+            ParseError::Unclosed { delimiter, span, end } => diagnostic
+                .with_label(span.clone(), "unclosed")
+                .with_secondary(end.clone(), format!("`{}` expected here", closer(*delimiter))),
+            ParseError::Mismatched { span, open, .. } => diagnostic
+                .with_label(span.clone(), "mismatched closing delimiter")
+                .with_secondary(open.clone(), "unclosed"),
+            ParseError::Unexpected { found: found @ (')' | ']'), span, close: None } => diagnostic
+                .with_label(span.clone(), format!("no open `{}` to close", opener(*found))),
+            ParseError::Unexpected { span, .. } => diagnostic.with_label(span.clone(), "not the start of an expression"),
+            ParseError::BadLiteral { span, value, .. } if value.starts_with('#') => diagnostic
+                .with_label(span.clone(), "")
+                .with_help("timestamps are written like #2024-01-02T15:30:00Z"),
+            other => diagnostic.with_label(other.span().clone(), ""),
+        }
+    }
+}
+
+fn closer(open: char) -> char {
+    if open == '[' { ']' } else { ')' }
+}
+
+fn opener(close: char) -> char {
+    if close == ']' { '[' } else { '(' }
+}
+
+fn line_col(span: &CodeSpan) -> String {
+    let (line, column) = span.code.line_col(span.start);
+    format!("{}:{}", line, column)
 }
 
 impl<'a> NomErr<ParserSpan<'a>> for ParseError {
@@ -72,11 +146,28 @@ where
     }
 }
 
+/// Whitespace and `;` comments running to the end of the line
+fn ws<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, (), ParseError> {
+    many0(alt((multispace1, preceded(tag(";"), take_while(|c| c != '\n')))))
+        .map(|_| ())
+        .parse(input)
+}
+
 fn parse_number<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
+    alt((
+        value(Expr::Float(f64::INFINITY), tag("+inf.0")),
+        value(Expr::Float(f64::NEG_INFINITY), tag("-inf.0")),
+        value(Expr::Float(f64::NAN), tag("+nan.0")),
+        parse_finite_number,
+    ))
+        .parse(input)
+}
+
+fn parse_finite_number<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
     map_res(
         recognize_float,
         |span: ParserSpan<'a>| {
-            let fragment = span.fragment().clone();
+            let fragment = *span.fragment();
             let cleaned: String = fragment.chars().filter(|&c| c != '_').collect();
             if cleaned.contains('.') || cleaned.contains('e') || cleaned.contains('E') {
                 cleaned.parse::<f64>()
@@ -92,31 +183,175 @@ fn parse_number<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, Pars
         .parse(input)
 }
 
-fn parse_combination_inner<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
-    separated_pair(
-        parse_expr,
-        multispace0,
-        separated_list1(multispace0, parse_expr),
+/// An integer directly followed by a unit, e.g. `5m`, `-30d` or `250ms`
+fn parse_duration<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
+    let unit = alt((tag("ms"), tag("us"), tag("ns"), recognize(one_of("wdhms"))));
+    let (rest, span) = recognize((opt(tag("-")), digit1, unit)).parse(input.clone())?;
+    if rest.fragment().starts_with(is_symbol_char) {
+        return Err(nom::Err::Error(ParseError::from_error_kind(input, nom::error::ErrorKind::Digit)));
+    }
+    let duration = span.fragment().parse::<Duration>().map_err(|msg| {
+        nom::Err::Failure(ParseError::BadLiteral { value: span.fragment().to_string(), msg, span: CodeSpan::from(span.clone()) })
+    })?;
+    Ok((rest, Expr::Duration(duration)))
+}
+
+/// A number with a decimal point directly followed by `m`, e.g. `101.25m`;
+/// without the point it would be minutes
+fn parse_decimal<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
+    let (rest, span) = recognize((opt(one_of("+-")), digit1, tag("."), digit1, tag("m"))).parse(input.clone())?;
+    if rest.fragment().starts_with(is_symbol_char) {
+        return Err(nom::Err::Error(ParseError::from_error_kind(input, nom::error::ErrorKind::Digit)));
+    }
+    let text = span.fragment();
+    let decimal = text[..text.len() - 1].parse::<Decimal>().map_err(|msg| {
+        nom::Err::Failure(ParseError::BadLiteral { value: text.to_string(), msg, span: CodeSpan::from(span.clone()) })
+    })?;
+    Ok((rest, Expr::Decimal(decimal)))
+}
+
+/// `#t`, `#f`, decimals like `#d101.25`, amounts like `#price:101.25` and timestamps like `#2024-01-02T15:30:00Z`
+fn parse_hash_literal<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
+    let (rest, body) = preceded(tag("#"), take_while1(|c: char| !c.is_whitespace() && !"()[];\"".contains(c)))
+        .parse(input.clone())?;
+    if let Some((name, _)) = body.fragment().split_once(':')
+        && let Some(unit) = Unit::from_name(name)
+    {
+        let (number, _) = take(name.len() + 1).parse(body.clone())?;
+        return match all_consuming(alt((parse_decimal, parse_number))).parse(number) {
+            Ok((_, value)) => Ok((rest, Expr::Amount(unit, Box::new(value)))),
+            Err(_) => Err(nom::Err::Failure(ParseError::BadLiteral {
+                value: format!("#{}", body.fragment()),
+                msg: format!("expected a number after `#{}:`", name),
+                span: CodeSpan::new(input.extra.clone(), input.location_offset(), rest.location_offset()),
+            })),
+        };
+    }
+    let expr = match *body.fragment() {
+        "t" | "true" => Expr::Boolean(true),
+        "f" | "false" => Expr::Boolean(false),
+        text if text.starts_with('d') && text[1..].starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+            match text[1..].parse::<Decimal>() {
+                Ok(decimal) => Expr::Decimal(decimal),
+                Err(msg) => {
+                    return Err(nom::Err::Failure(ParseError::BadLiteral {
+                        value: format!("#{}", text),
+                        msg,
+                        span: CodeSpan::new(input.extra.clone(), input.location_offset(), rest.location_offset()),
+                    }));
+                }
+            }
+        }
+        text => match parse_timestamp(text) {
+            Some(timestamp) => Expr::Timestamp(timestamp),
+            None => {
+                return Err(nom::Err::Failure(ParseError::BadLiteral {
+                    value: format!("#{}", text),
+                    msg: "expected #t, #f, a decimal, an amount or a timestamp".to_string(),
+                    span: CodeSpan::new(input.extra.clone(), input.location_offset(), rest.location_offset()),
+                }));
+            }
+        },
+    };
+    Ok((rest, expr))
+}
+
+/// `[a b c]`
+fn parse_array<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, NodeKind, ParseError> {
+    delimited(
+        terminated(tag("["), ws),
+        many0(terminated(parse_node, ws)),
+        tag("]"),
     )
-        .map(|(op, args)| Expr::Combination(Box::new(op.value), args.into_iter().map(|s| s.value).collect()))
+        .map(NodeKind::Array)
         .parse(input)
 }
 
-fn parse_combination<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
-    delimited(tag("("), parse_combination_inner, tag(")")).parse(input)
+/// A double-quoted string with `\n`, `\t`, `\\` and `\"` escapes.
+fn parse_string<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
+    let (rest, quote) = tag("\"").parse(input)?;
+    let mut value = String::new();
+    let mut chars = rest.fragment().chars().enumerate();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let (rest, _) = take(i + 1).parse(rest)?;
+                return Ok((rest, Expr::String(value)));
+            }
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c @ ('\\' | '"'))) => value.push(c),
+                escape => {
+                    let (at, _) = take(i).parse(rest)?;
+                    let len = 1 + escape.map_or(0, |(_, c)| c.len_utf8());
+                    return Err(nom::Err::Failure(ParseError::Syntax {
+                        msg: "unknown escape in string".to_string(),
+                        span: CodeSpan::new(at.extra.clone(), at.location_offset(), at.location_offset() + len),
+                    }));
+                }
+            },
+            c => value.push(c),
+        }
+    }
+    Err(nom::Err::Failure(ParseError::Syntax { msg: "unterminated string".to_string(), span: CodeSpan::from(quote) }))
+}
+
+fn parse_combination_inner<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, NodeKind, ParseError> {
+    pair(
+        parse_node,
+        many0(preceded(ws, parse_node)),
+    )
+        .map(|(op, args)| NodeKind::Combination(std::iter::once(op).chain(args).collect()))
+        .parse(input)
+}
+
+/// `(op args...)`, or `()` for nil
+fn parse_combination<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, NodeKind, ParseError> {
+    alt((
+        value(NodeKind::Atom(Expr::Nil), (tag("("), ws, tag(")"))),
+        delimited(
+            terminated(tag("("), ws),
+            parse_combination_inner,
+            preceded(ws, tag(")")),
+        ),
+    ))
+        .parse(input)
+}
+
+/// Characters that may appear in a symbol
+pub fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '+' || c == '-' || c == '*' ||
+        c == '=' || c == '>' || c == '<' || c == '!' || c == '?' || c == '/' || c == '$' || c == ':'
 }
 
 pub fn parse_expr<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Spanned<Expr>, ParseError> {
+    parse_node.map(|node| node.to_spanned()).parse(input)
+}
+
+/// Like [`parse_expr`], but keeps the span of every nested node.
+pub fn parse_node<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Node, ParseError> {
     alt((
-        spanned(parse_number),
-        spanned(map_res(
-            take_while1(|c: char| {
-                c.is_ascii_alphabetic() || c == '_' || c == '+' || c == '-' || c == '*' ||
-                    c == '=' || c == '>' || c == '<' || c == '!' || c == '?' || c == '/' || c == '$'
-            }),
-            |span: ParserSpan<'a>| Ok(Expr::Symbol(Symbol(span.fragment().to_string()))),
-        )),
+        spanned(parse_atom).map(|s| Spanned { value: NodeKind::Atom(s.value), span: s.span }),
+        spanned(parse_array),
         spanned(parse_combination),
+    ))
+        .map(|s| Node { kind: s.value, span: s.span })
+        .parse(input)
+}
+
+/// Anything but a list
+fn parse_atom<'a>(input: ParserSpan<'a>) -> IResult<ParserSpan<'a>, Expr, ParseError> {
+    alt((
+        parse_duration,
+        parse_decimal,
+        parse_number,
+        parse_string,
+        parse_hash_literal,
+        map_res(
+            take_while1(is_symbol_char),
+            |span: ParserSpan<'a>| Ok(Expr::Symbol(Symbol(span.fragment().to_string()))),
+        ),
     ))
         .parse(input)
 }
@@ -127,16 +362,256 @@ pub fn parse_snippet(src: &str) -> Result<Spanned<Expr>, ParseError> {
 }
 
 pub fn parse_file(path: &std::path::Path) -> Result<Spanned<Expr>, ParseError> {
-    let code = Code::from_file(path).map_err(|_io| ParseError::Nom {
-        kind: nom::error::ErrorKind::Fail,
+    complete_expr(&read_code(path)?)
+}
+
+/// Parse every top-level form of a snippet.
+pub fn parse_program(src: &str) -> Result<Vec<Spanned<Expr>>, ParseError> {
+    Ok(parse_syntax(src)?.iter().map(Node::to_spanned).collect())
+}
+
+/// Parse every top-level form of a file.
+pub fn parse_program_file(path: &std::path::Path) -> Result<Vec<Spanned<Expr>>, ParseError> {
+    Ok(parse_syntax_file(path)?.iter().map(Node::to_spanned).collect())
+}
+
+/// Parse every top-level form of a snippet into spanned syntax trees.
+pub fn parse_syntax(src: &str) -> Result<Vec<Node>, ParseError> {
+    complete_program(&Code::from_snippet(src))
+}
+
+/// Parse every top-level form of a file into spanned syntax trees.
+pub fn parse_syntax_file(path: &std::path::Path) -> Result<Vec<Node>, ParseError> {
+    complete_program(&read_code(path)?)
+}
+
+/// [`parse_recovering`] on a file.
+pub fn parse_recovering_file(path: &std::path::Path) -> Result<Recovered, ParseError> {
+    Ok(parse_recovering(&read_code(path)?))
+}
+
+fn read_code(path: &std::path::Path) -> Result<Arc<Code>, ParseError> {
+    Code::from_file(path).map_err(|e| ParseError::Io {
+        path: path.display().to_string(),
+        msg: e.to_string(),
         span: CodeSpan::new(Code::from_snippet(""), 0, 0),
-    })?;
-    complete_expr(&code)
+    })
+}
+
+/// The first error the recovering parser finds, without ending unclosed lists
+/// early: a `(` in the first column may be nested in valid code.
+fn complete_program(code: &Arc<Code>) -> Result<Vec<Node>, ParseError> {
+    let Recovered { nodes, mut errors } = Recovery { errors: Vec::new(), comments: false, resync: false }.program(code);
+    if errors.is_empty() { Ok(nodes) } else { Err(errors.swap_remove(0)) }
+}
+
+/// Forms parsed despite errors, for tools that work on code being edited.
+#[derive(Debug, Clone)]
+pub struct Recovered {
+    /// Every form that could be read; unclosed lists are cut short and
+    /// unreadable tokens are left out.
+    pub nodes: Vec<Node>,
+    /// In source order
+    pub errors: Vec<ParseError>,
+}
+
+/// Parse every top-level form, carrying on past errors.
+///
+/// A bad token is skipped. If the delimiters do not balance, a list that is
+/// never closed ends at the next `(` in the first column, which is taken to
+/// start the next top-level form, or at the end of the input; in balanced code
+/// such a `(` is just a nested form. A closer that does not match its list
+/// closes it anyway, unless it matches a list further out.
+pub fn parse_recovering(code: &Arc<Code>) -> Recovered {
+    Recovery { errors: Vec::new(), comments: false, resync: !is_balanced(&code.text) }.program(code)
+}
+
+/// Like [`parse_recovering`], but `;` comments are kept as [`Expr::Comment`]
+/// atoms wherever they appear, lists included. For the formatter; such trees
+/// are not meant to be evaluated.
+pub fn parse_with_comments(code: &Arc<Code>) -> Recovered {
+    Recovery { errors: Vec::new(), comments: true, resync: !is_balanced(&code.text) }.program(code)
+}
+
+/// Whether every `(` and `[` in `text` is closed by its own closer and every
+/// string ends, ignoring strings and comments.
+fn is_balanced(text: &str) -> bool {
+    let mut open = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => loop {
+                match chars.next() {
+                    Some('\\') => {
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            ';' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '(' | '[' => open.push(closer(c)),
+            ')' | ']' if open.pop() != Some(c) => return false,
+            _ => {}
+        }
+    }
+    open.is_empty()
+}
+
+struct Recovery {
+    errors: Vec<ParseError>,
+    /// Keep comments as nodes instead of skipping them with the whitespace
+    comments: bool,
+    /// End an unclosed list at a `(` in the first column
+    resync: bool,
+}
+
+impl Recovery {
+    fn program(mut self, code: &Arc<Code>) -> Recovered {
+        let mut nodes = Vec::new();
+        let mut input = self.trivia(Code::span(code), &mut nodes);
+        while let Some(c) = input.fragment().chars().next() {
+            if c == ')' || c == ']' {
+                let start = input.location_offset();
+                self.errors.push(ParseError::Unexpected { found: c, span: CodeSpan::new(code.clone(), start, start + 1), close: None });
+                input = input.take_from(1);
+            } else {
+                let (rest, node) = self.node(input, &[]);
+                input = rest;
+                nodes.extend(node);
+            }
+            input = self.trivia(input, &mut nodes);
+        }
+        Recovered { nodes, errors: self.errors }
+    }
+
+    /// Skip whitespace and comments, adding the comments to `out` if they are kept.
+    fn trivia<'a>(&self, mut input: ParserSpan<'a>, out: &mut Vec<Node>) -> ParserSpan<'a> {
+        loop {
+            let text = *input.fragment();
+            let trimmed = text.trim_start_matches([' ', '\t', '\r', '\n']);
+            input = input.take_from(text.len() - trimmed.len());
+            let Some(comment) = trimmed.strip_prefix(';') else {
+                return input;
+            };
+            let len = 1 + comment.find('\n').unwrap_or(comment.len());
+            if self.comments {
+                let start = input.location_offset();
+                let text = comment[..len - 1].trim_end_matches('\r').to_string();
+                out.push(Node { kind: NodeKind::Atom(Expr::Comment(text)), span: CodeSpan::new(input.extra.clone(), start, start + len) });
+            }
+            input = input.take_from(len);
+        }
+    }
+
+    /// One expression at `input`, which is not a closer. `open` holds the lists
+    /// it is nested in, innermost last.
+    fn node<'a>(&mut self, input: ParserSpan<'a>, open: &[(char, CodeSpan)]) -> (ParserSpan<'a>, Option<Node>) {
+        let start = input.location_offset();
+        let code = input.extra.clone();
+        let c = input.fragment().chars().next().expect("called on non-empty input");
+        if c == '(' || c == '[' {
+            return self.list(input, c, open);
+        }
+        match spanned(parse_atom).parse(input.clone()) {
+            Ok((rest, atom)) => (rest, Some(Node { kind: NodeKind::Atom(atom.value), span: atom.span })),
+            Err(e) => {
+                let error = match e {
+                    nom::Err::Failure(e) => e,
+                    _ => ParseError::Unexpected {
+                        found: c,
+                        span: CodeSpan::new(code, start, start + c.len_utf8()),
+                        close: open.last().map(|(d, _)| closer(*d)),
+                    },
+                };
+                self.errors.push(error);
+                (skip_token(input), None)
+            }
+        }
+    }
+
+    fn list<'a>(&mut self, input: ParserSpan<'a>, delimiter: char, outer: &[(char, CodeSpan)]) -> (ParserSpan<'a>, Option<Node>) {
+        let code = input.extra.clone();
+        let start = input.location_offset();
+        let open_span = CodeSpan::new(code.clone(), start, start + 1);
+        let mut open = outer.to_vec();
+        open.push((delimiter, open_span.clone()));
+
+        let mut items: Vec<Node> = Vec::new();
+        let mut rest = self.trivia(input.take_from(1), &mut items);
+        let end = loop {
+            let at = rest.location_offset();
+            match rest.fragment().chars().next() {
+                Some(c) if c == closer(delimiter) => {
+                    rest = rest.take_from(1);
+                    break rest.location_offset();
+                }
+                Some(found @ (')' | ']')) => {
+                    self.errors.push(ParseError::Mismatched {
+                        found,
+                        span: CodeSpan::new(code.clone(), at, at + 1),
+                        delimiter,
+                        open: open_span.clone(),
+                    });
+                    if !outer.iter().any(|(d, _)| closer(*d) == found) {
+                        rest = rest.take_from(1);
+                    }
+                    break rest.location_offset();
+                }
+                Some('(') if self.resync && code.text[..at].ends_with('\n') => {
+                    let due = code.text[..at].trim_end().len();
+                    self.errors.push(ParseError::Unclosed { delimiter, span: open_span, end: CodeSpan::new(code.clone(), due, due) });
+                    break items.last().map_or(start + 1, |n| n.span.end);
+                }
+                None => {
+                    let due = code.text.trim_end().len();
+                    self.errors.push(ParseError::Unclosed { delimiter, span: open_span, end: CodeSpan::new(code.clone(), due, due) });
+                    break items.last().map_or(start + 1, |n| n.span.end);
+                }
+                Some(_) => {
+                    let (after, node) = self.node(rest, &open);
+                    items.extend(node);
+                    rest = self.trivia(after, &mut items);
+                }
+            }
+        };
+
+        let kind = match delimiter {
+            '[' => NodeKind::Array(items),
+            _ if items.is_empty() => NodeKind::Atom(Expr::Nil),
+            _ => NodeKind::Combination(items),
+        };
+        (rest, Some(Node { kind, span: CodeSpan::new(code, start, end) }))
+    }
+}
+
+/// Past the token at `input`: a whole string literal if it starts one (to the
+/// end of the line if it is unterminated), else up to the next space or delimiter.
+fn skip_token(input: ParserSpan<'_>) -> ParserSpan<'_> {
+    let text = *input.fragment();
+    let len = if let Some(body) = text.strip_prefix('"') {
+        let mut escaped = false;
+        let close = body.char_indices().find(|&(_, c)| {
+            let found = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            found
+        });
+        match close {
+            Some((i, _)) => i + 2,
+            None => text.find('\n').unwrap_or(text.len()),
+        }
+    } else {
+        text.find(|c: char| c.is_whitespace() || "()[];\"".contains(c)).unwrap_or(text.len())
+    };
+    input.take_from(len.max(text.chars().next().map_or(0, char::len_utf8)))
 }
 
 fn complete_expr(code: &Arc<Code>) -> Result<Spanned<Expr>, ParseError> {
     let span = Code::span(code);
-    let (_, spanned) = delimited(multispace0, parse_expr, multispace0)
+    let (_, spanned) = delimited(ws, parse_expr, ws)
         .parse(span)
         .map_err(|e| match e {
             nom::Err::Error(p) | nom::Err::Failure(p) => p,
@@ -145,6 +620,23 @@ fn complete_expr(code: &Arc<Code>) -> Result<Spanned<Expr>, ParseError> {
     Ok(spanned)
 }
 
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sp = parse_snippet(s)?;
+        Ok(sp.value)
+    }
+}
+
+impl FromStr for Spanned<Expr> {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_snippet(s)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,9 +651,9 @@ mod tests {
 
     #[test]
     fn parses_float() {
-        let sp = parse_snippet("  3.14 ").expect("parse");
-        assert_eq!(sp.value, Expr::Float(3.14));
-        assert_eq!(&sp.span.code.text[sp.span.start..sp.span.end], "3.14");
+        let sp = parse_snippet("  2.75 ").expect("parse");
+        assert_eq!(sp.value, Expr::Float(2.75));
+        assert_eq!(&sp.span.code.text[sp.span.start..sp.span.end], "2.75");
     }
 
     #[test]
@@ -182,21 +674,236 @@ mod tests {
             )
         );
     }
-}
 
-impl FromStr for Expr {
-    type Err = ParseError;
+    #[test]
+    fn parses_strings_and_keywords() {
+        let sp = parse_snippet(r#"( plot x :title "a \"b\"\n" )"#).expect("parse");
+        assert_eq!(
+            sp.value,
+            Expr::Combination(
+                Box::new(Expr::Symbol(Symbol("plot".to_string()))),
+                vec![
+                    Expr::Symbol(Symbol("x".to_string())),
+                    Expr::Symbol(Symbol(":title".to_string())),
+                    Expr::String("a \"b\"\n".to_string()),
+                ]
+            )
+        );
+        assert_eq!(parse_snippet(r#""""#).expect("parse").value, Expr::String(String::new()));
+        assert!(parse_snippet(r#""open"#).is_err());
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let sp = parse_snippet(s)?;
-        Ok(sp.value)
+    #[test]
+    fn parses_zero_argument_call() {
+        let sp = parse_snippet("(main)").expect("parse");
+        assert_eq!(sp.value, Expr::Combination(Box::new(Expr::Symbol(Symbol("main".to_string()))), vec![]));
     }
-}
 
-impl FromStr for Spanned<Expr> {
-    type Err = ParseError;
+    #[test]
+    fn parses_program() {
+        let forms = parse_program(" (set x 1)\n(set y 2) ").expect("parse");
+        assert_eq!(forms.len(), 2);
+        assert_eq!(&forms[1].span.code.text[forms[1].span.start..forms[1].span.end], "(set y 2)");
+        assert!(parse_program("(set x 1) )").is_err());
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        parse_snippet(s)
+    #[test]
+    fn parses_literals_and_comments() {
+        let forms = parse_program("; header\n[1 #t #f 5m -2d 101.25m #d-0.5] ; trailing\n(set t #2024-01-02T15:30:00Z) () +inf.0").expect("parse");
+        assert_eq!(
+            forms.iter().map(|f| f.value.clone()).collect::<Vec<_>>(),
+            vec![
+                Expr::Array(vec![
+                    Expr::Integer(1),
+                    Expr::Boolean(true),
+                    Expr::Boolean(false),
+                    Expr::Duration("5m".parse().unwrap()),
+                    Expr::Duration("-2d".parse().unwrap()),
+                    Expr::Decimal("101.25".parse().unwrap()),
+                    Expr::Decimal("-0.5".parse().unwrap()),
+                ]),
+                Expr::Combination(
+                    Box::new(Expr::Symbol(Symbol("set".to_string()))),
+                    vec![
+                        Expr::Symbol(Symbol("t".to_string())),
+                        Expr::Timestamp(parse_timestamp("2024-01-02T15:30:00Z").unwrap()),
+                    ]
+                ),
+                Expr::Nil,
+                Expr::Float(f64::INFINITY),
+            ]
+        );
+        assert!(parse_snippet("#tomorrow").is_err());
+        assert!(parse_snippet("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn renders_errors_with_a_snippet() {
+        let render = |src: &str| parse_program(src).unwrap_err().diagnostic().to_string();
+        assert_eq!(
+            render("(set x 1)\n(set t\t#tomorrow)"),
+            [
+                "error: invalid literal `#tomorrow` – expected #t, #f, a decimal, an amount or a timestamp",
+                " --> <input>:2:8",
+                "  |",
+                "2 | (set t\t#tomorrow)",
+                "  |       \t^^^^^^^^^",
+                "  = help: timestamps are written like #2024-01-02T15:30:00Z",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            render("(if (> a b)\n  (buy 1)  ; no close\n"),
+            [
+                "error: expected `)` to close `(` opened at 1:1",
+                " --> <input>:1:1",
+                "  |",
+                "1 | (if (> a b)",
+                "  | ^ unclosed",
+                "2 |   (buy 1)  ; no close",
+                "  |                      - `)` expected here",
+            ]
+            .join("\n")
+        );
+        assert!(render("(buy 1))").ends_with("1 | (buy 1))\n  |        ^ no open `(` to close"));
+        assert!(render("(set x [1 2))").contains("^ mismatched closing delimiter"));
+        assert!(render("(set x @)").starts_with("error: expected an expression or `)`, found `@`\n --> <input>:1:8"));
+        assert!(render("\"abc").starts_with("error: unterminated string\n"));
+        assert!(render("(display \"a\\q\")").contains("^^"));
+    }
+
+    #[test]
+    fn recovers_and_reports_every_error() {
+        let src = "(set a (+ 1 2)\n(set b [1 2)\n(set c @x \"d\\q\" 3))\n(set d 4)";
+        let Recovered { nodes, errors } = parse_recovering(&Code::from_snippet(src));
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "expected `)` to close `(` opened at 1:1",
+                "expected `]` to close `[` opened at 2:8, found `)`",
+                "expected an expression or `)`, found `@`",
+                "unknown escape in string",
+                "expected an expression, found `)`",
+            ]
+        );
+        assert_eq!(
+            nodes.iter().map(|n| n.to_expr().to_string()).collect::<Vec<_>>(),
+            ["(set a (+ 1 2))", "(set b [1 2])", "(set c 3)", "(set d 4)"]
+        );
+        assert_eq!(&src[nodes[0].span.start..nodes[0].span.end], "(set a (+ 1 2)");
+        // Strict parsing never guesses where an unclosed list ends
+        assert_eq!(parse_program(src).unwrap_err(), errors[1]);
+    }
+
+    #[test]
+    fn nested_forms_may_start_a_line() {
+        let src = "(begin\n(buy 1))\n; (\n(if #t\n[1\n(+ 1 \")\")])";
+        assert_eq!(parse_program(src).unwrap().len(), 2);
+        let Recovered { nodes, errors } = parse_recovering(&Code::from_snippet(src));
+        assert!(errors.is_empty());
+        assert_eq!(nodes[0].to_expr().to_string(), "(begin (buy 1))");
+    }
+
+    mod round_trip {
+        use super::*;
+        use crate::ast::Timestamp;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        const SYMBOL_START: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ:";
+        const SYMBOL_REST: &[u8] = b"abcxyzXYZ0123456789_+-*=><!?/$:";
+        const STRING_CHARS: &[char] = &['a', 'Z', '0', ' ', '"', '\\', '\n', '\t', '(', ')', '[', '#', ';', 'é', '€', '📈'];
+
+        fn pick<T: Copy>(rng: &mut StdRng, items: &[T]) -> T {
+            items[rng.random_range(0..items.len())]
+        }
+
+        /// Any value the parser can produce, nested up to `depth` levels.
+        fn arbitrary_expr(rng: &mut StdRng, depth: u32) -> Expr {
+            let kind = if depth == 0 || rng.random_bool(0.6) { rng.random_range(0..10) } else { rng.random_range(10..12) };
+            match kind {
+                0 => Expr::Nil,
+                1 => Expr::Boolean(rng.random()),
+                2 => Expr::Integer(if rng.random_bool(0.5) { rng.random() } else { rng.random_range(-100..100) }),
+                3 => Expr::Float(match rng.random_range(0..4) {
+                    0 => f64::from_bits(rng.random()),
+                    1 => pick(rng, &[0.0, -0.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN, f64::MAX, f64::MIN_POSITIVE, 1e16, 1e-7]),
+                    _ => rng.random_range(-1e6..1e6),
+                }),
+                4 => Expr::String((0..rng.random_range(0..8)).map(|_| pick(rng, STRING_CHARS)).collect()),
+                5 => {
+                    let mut name = String::from(pick(rng, SYMBOL_START) as char);
+                    name.extend((0..rng.random_range(0..6)).map(|_| pick(rng, SYMBOL_REST) as char));
+                    Expr::Symbol(Symbol(name))
+                }
+                6 => {
+                    let count = rng.random_range(-1_000_000..1_000_000);
+                    Expr::Duration(Duration(match rng.random_range(0..3) {
+                        0 => chrono::Duration::seconds(count * pick(rng, &[1, 60, 3600, 86_400, 604_800])),
+                        1 => chrono::Duration::nanoseconds(count * pick(rng, &[1, 1_000, 1_000_000])),
+                        _ => chrono::Duration::nanoseconds(rng.random_range(-1_000_000_000_000_000..1_000_000_000_000_000)),
+                    }))
+                }
+                7 => {
+                    let nanos = if rng.random_bool(0.5) { 0 } else { rng.random_range(0..1_000_000_000) };
+                    let time = chrono::DateTime::from_timestamp(rng.random_range(0..4_000_000_000), nanos).unwrap();
+                    Expr::Timestamp(Timestamp(time))
+                }
+                8 => {
+                    let units = format!("{}.{:09}", rng.random_range(-1_000_000_000i64..1_000_000_000), rng.random_range(0..1_000_000_000));
+                    Expr::Decimal(units.parse().unwrap())
+                }
+                9 => {
+                    let unit = pick(rng, &[Unit::Price, Unit::Quantity, Unit::Money]);
+                    let number = loop {
+                        match arbitrary_expr(rng, 0) {
+                            number @ (Expr::Integer(_) | Expr::Float(_) | Expr::Decimal(_)) => break number,
+                            _ => continue,
+                        }
+                    };
+                    Expr::Amount(unit, Box::new(number))
+                }
+                10 => Expr::Combination(
+                    Box::new(arbitrary_expr(rng, depth - 1)),
+                    (0..rng.random_range(0..4)).map(|_| arbitrary_expr(rng, depth - 1)).collect(),
+                ),
+                _ => Expr::Array((0..rng.random_range(0..4)).map(|_| arbitrary_expr(rng, depth - 1)).collect()),
+            }
+        }
+
+        /// Structural equality that takes every NaN as the same value
+        fn same(a: &Expr, b: &Expr) -> bool {
+            match (a, b) {
+                (Expr::Float(x), Expr::Float(y)) => x == y || (x.is_nan() && y.is_nan()),
+                (Expr::Combination(f, xs), Expr::Combination(g, ys)) => {
+                    same(f, g) && xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| same(x, y))
+                }
+                (Expr::Array(xs), Expr::Array(ys)) => xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| same(x, y)),
+                (Expr::Amount(u, x), Expr::Amount(v, y)) => u == v && same(x, y),
+                _ => a == b,
+            }
+        }
+
+        #[test]
+        fn printed_values_parse_back_unchanged() {
+            let mut rng = StdRng::seed_from_u64(37);
+            for _ in 0..5000 {
+                let expr = arbitrary_expr(&mut rng, 4);
+                let printed = expr.to_string();
+                let parsed = parse_snippet(&printed).unwrap_or_else(|e| panic!("`{}` failed to parse: {}", printed, e));
+                assert!(same(&parsed.value, &expr), "`{}` parsed back as {:?}", printed, parsed.value);
+            }
+        }
+
+        #[test]
+        fn prints_scheme_syntax() {
+            let expr: Expr = r#"(plot [1 2.5 "a\"b"] :every 5m :at #2024-01-02T15:30:00Z #t ())"#.parse().unwrap();
+            assert_eq!(expr.to_string(), r#"(plot [1 2.5 "a\"b"] :every 5m :at #2024-01-02T15:30:00Z #t ())"#);
+            assert_eq!(Expr::Float(f64::NAN).to_string(), "+nan.0");
+            assert_eq!(Expr::Float(3.0).to_string(), "3.0");
+            let money = Expr::Amount(Unit::Money, Box::new(Expr::Decimal("-2.5".parse().unwrap())));
+            assert_eq!(money.to_string(), "#money:-2.5m");
+            assert!(parse_snippet("#price:5m").is_err());
+        }
     }
 }
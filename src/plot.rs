@@ -0,0 +1,396 @@
+//! Charts collected while a script runs, rendered offline to SVG.
+//!
+//! `plot`, `plot-overlay` and `plot-candles` append to named charts instead of
+//! drawing anything, so a backtest can plot once per bar and render at the end.
+//! The x axis is the bar index; bar timestamps, when known, label the ticks.
+
+use crate::ast::Timestamp;
+use indexmap::IndexMap;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+const PALETTE: [&str; 6] = ["steelblue", "darkorange", "seagreen", "crimson", "rebeccapurple", "goldenrod"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlotPoint {
+    pub index: usize,
+    pub time: Option<Timestamp>,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candle {
+    pub index: usize,
+    pub time: Option<Timestamp>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+impl Candle {
+    fn is_finite(&self) -> bool {
+        [self.open, self.high, self.low, self.close].iter().all(|v| v.is_finite())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeriesData {
+    Line(Vec<PlotPoint>),
+    Histogram(Vec<PlotPoint>),
+    Candles(Vec<Candle>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub name: String,
+    pub color: String,
+    pub data: SeriesData,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chart {
+    pub title: String,
+    pub x_label: Option<String>,
+    pub y_label: Option<String>,
+    pub series: Vec<Series>,
+}
+
+impl Chart {
+    /// The series called `name`, created with `empty` data if it doesn't exist yet.
+    pub fn series_mut(&mut self, name: &str, color: Option<String>, empty: SeriesData) -> &mut Series {
+        let position = match self.series.iter().position(|s| s.name == name) {
+            Some(i) => i,
+            None => {
+                let color = PALETTE[self.series.len() % PALETTE.len()].to_string();
+                self.series.push(Series { name: name.to_string(), color, data: empty });
+                self.series.len() - 1
+            }
+        };
+        let series = &mut self.series[position];
+        if let Some(color) = color {
+            series.color = color;
+        }
+        series
+    }
+}
+
+/// Every chart a run produced, keyed by name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Plots {
+    pub charts: IndexMap<String, Chart>,
+}
+
+impl Plots {
+    pub fn is_empty(&self) -> bool {
+        self.charts.is_empty()
+    }
+
+    /// The chart called `name`, created with `name` as its title if it doesn't exist yet.
+    pub fn chart_mut(&mut self, name: &str) -> &mut Chart {
+        self.charts
+            .entry(name.to_string())
+            .or_insert_with(|| Chart { title: name.to_string(), ..Chart::default() })
+    }
+
+    /// Render every chart into `dir` as `<chart name>.svg`. Names that come out the same
+    /// once unsafe characters are replaced, or that differ only in case, get `-2`, `-3`, ...
+    pub fn write_svgs(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+        let mut written = Vec::new();
+        let mut taken = HashSet::new();
+        for (name, chart) in &self.charts {
+            let base: String = name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                .collect();
+            let mut file_name = base.clone();
+            for n in 2.. {
+                if taken.insert(file_name.to_ascii_lowercase()) {
+                    break;
+                }
+                file_name = format!("{}-{}", base, n);
+            }
+            let path = dir.join(format!("{}.svg", file_name));
+            std::fs::write(&path, render_svg(chart))?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+const WIDTH: f64 = 900.0;
+const HEIGHT: f64 = 500.0;
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 40.0;
+const BOTTOM: f64 = 60.0;
+
+/// Maps data coordinates to the plot area.
+struct Frame {
+    x_min: f64,
+    x_max: f64,
+    y_min: f64,
+    y_max: f64,
+}
+
+impl Frame {
+    fn x(&self, index: f64) -> f64 {
+        let span = (self.x_max - self.x_min).max(1.0);
+        LEFT + (index - self.x_min + 0.5) / (span + 1.0) * (WIDTH - LEFT - RIGHT)
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        let span = self.y_max - self.y_min;
+        TOP + (self.y_max - value) / span * (HEIGHT - TOP - BOTTOM)
+    }
+
+    /// Width of one bar slot in pixels.
+    fn slot(&self) -> f64 {
+        (WIDTH - LEFT - RIGHT) / ((self.x_max - self.x_min).max(1.0) + 1.0)
+    }
+}
+
+pub fn render_svg(chart: &Chart) -> String {
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let mut times: IndexMap<usize, Timestamp> = IndexMap::new();
+    let mut note_time = |index: usize, time: &Option<Timestamp>| {
+        if let Some(time) = time {
+            times.entry(index).or_insert_with(|| time.clone());
+        }
+    };
+    for series in &chart.series {
+        match &series.data {
+            SeriesData::Line(points) | SeriesData::Histogram(points) => {
+                for p in points.iter().filter(|p| p.value.is_finite()) {
+                    xs.push(p.index as f64);
+                    ys.push(p.value);
+                    note_time(p.index, &p.time);
+                }
+                if matches!(series.data, SeriesData::Histogram(_)) {
+                    ys.push(0.0);
+                }
+            }
+            SeriesData::Candles(candles) => {
+                for c in candles.iter().filter(|c| c.is_finite()) {
+                    xs.push(c.index as f64);
+                    ys.extend([c.high, c.low]);
+                    note_time(c.index, &c.time);
+                }
+            }
+        }
+    }
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+        w = WIDTH,
+        h = HEIGHT
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="24" text-anchor="middle" font-size="16">{}</text>"#,
+        WIDTH / 2.0,
+        escape(&chart.title)
+    );
+
+    if xs.is_empty() {
+        let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle">no data</text>"#, WIDTH / 2.0, HEIGHT / 2.0);
+        svg.push_str("</svg>\n");
+        return svg;
+    }
+
+    let (mut y_min, mut y_max) = (f64::INFINITY, f64::NEG_INFINITY);
+    for y in &ys {
+        y_min = y_min.min(*y);
+        y_max = y_max.max(*y);
+    }
+    if y_min == y_max {
+        y_min -= 1.0;
+        y_max += 1.0;
+    }
+    let pad = (y_max - y_min) * 0.05;
+    let frame = Frame {
+        x_min: xs.iter().copied().fold(f64::INFINITY, f64::min),
+        x_max: xs.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        y_min: y_min - pad,
+        y_max: y_max + pad,
+    };
+
+    render_axes(&mut svg, chart, &frame, &times);
+    for series in &chart.series {
+        render_series(&mut svg, series, &frame);
+    }
+    render_legend(&mut svg, chart);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn render_axes(svg: &mut String, chart: &Chart, frame: &Frame, times: &IndexMap<usize, Timestamp>) {
+    let (x0, x1, y0, y1) = (LEFT, WIDTH - RIGHT, TOP, HEIGHT - BOTTOM);
+    let _ = writeln!(svg, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="dimgray"/>"#, x0, y0, x1 - x0, y1 - y0);
+
+    const TICKS: usize = 5;
+    for i in 0..=TICKS {
+        let value = frame.y_min + (frame.y_max - frame.y_min) * i as f64 / TICKS as f64;
+        let y = frame.y(value);
+        let _ = writeln!(svg, r#"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="gainsboro"/>"#, x0, x1);
+        let _ = writeln!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end">{}</text>"#, x0 - 6.0, y + 4.0, format_value(value));
+    }
+
+    let span_seconds = match (times.values().next(), times.values().last()) {
+        (Some(first), Some(last)) => (last.0 - first.0).num_seconds().abs(),
+        _ => 0,
+    };
+    let time_format = if span_seconds > 2 * 86_400 { "%Y-%m-%d" } else { "%H:%M" };
+    let first = frame.x_min as usize;
+    let count = (frame.x_max - frame.x_min) as usize + 1;
+    let every = count.div_ceil(8).max(1);
+    for index in (first..first + count).step_by(every) {
+        let x = frame.x(index as f64);
+        let label = match times.get(&index) {
+            Some(time) => time.0.format(time_format).to_string(),
+            None => index.to_string(),
+        };
+        let _ = writeln!(svg, r#"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke="dimgray"/>"#, y1, y1 + 5.0);
+        let _ = writeln!(svg, r#"<text x="{x:.1}" y="{}" text-anchor="middle">{}</text>"#, y1 + 18.0, label);
+    }
+
+    if let Some(label) = &chart.x_label {
+        let _ = writeln!(svg, r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#, (x0 + x1) / 2.0, HEIGHT - 12.0, escape(label));
+    }
+    if let Some(label) = &chart.y_label {
+        let _ = writeln!(
+            svg,
+            r#"<text x="16" y="{y}" text-anchor="middle" transform="rotate(-90 16 {y})">{}</text>"#,
+            escape(label),
+            y = (y0 + y1) / 2.0
+        );
+    }
+}
+
+fn render_series(svg: &mut String, series: &Series, frame: &Frame) {
+    let color = escape(&series.color);
+    match &series.data {
+        SeriesData::Line(points) => {
+            let coords: Vec<String> = points
+                .iter()
+                .filter(|p| p.value.is_finite())
+                .map(|p| format!("{:.1},{:.1}", frame.x(p.index as f64), frame.y(p.value)))
+                .collect();
+            let _ = writeln!(svg, r#"<polyline fill="none" stroke="{}" stroke-width="1.5" points="{}"/>"#, color, coords.join(" "));
+        }
+        SeriesData::Histogram(points) => {
+            let width = (frame.slot() * 0.8).max(1.0);
+            let zero = frame.y(0.0);
+            for p in points.iter().filter(|p| p.value.is_finite()) {
+                let y = frame.y(p.value);
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                    frame.x(p.index as f64) - width / 2.0,
+                    y.min(zero),
+                    width,
+                    (y - zero).abs(),
+                    color
+                );
+            }
+        }
+        SeriesData::Candles(candles) => {
+            let width = (frame.slot() * 0.6).max(1.0);
+            for c in candles.iter().filter(|c| c.is_finite()) {
+                let x = frame.x(c.index as f64);
+                let fill = if c.close >= c.open { "seagreen" } else { "crimson" };
+                let (top, bottom) = (frame.y(c.open.max(c.close)), frame.y(c.open.min(c.close)));
+                let _ = writeln!(
+                    svg,
+                    r#"<line x1="{x:.1}" y1="{:.1}" x2="{x:.1}" y2="{:.1}" stroke="{}"/>"#,
+                    frame.y(c.high),
+                    frame.y(c.low),
+                    fill
+                );
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                    x - width / 2.0,
+                    top,
+                    width,
+                    (bottom - top).max(1.0),
+                    fill
+                );
+            }
+        }
+    }
+}
+
+fn render_legend(svg: &mut String, chart: &Chart) {
+    for (i, series) in chart.series.iter().enumerate() {
+        let y = TOP + 14.0 + i as f64 * 16.0;
+        let _ = writeln!(svg, r#"<rect x="{}" y="{}" width="10" height="10" fill="{}"/>"#, LEFT + 10.0, y - 9.0, escape(&series.color));
+        let _ = writeln!(svg, r#"<text x="{}" y="{}">{}</text>"#, LEFT + 26.0, y, escape(&series.name));
+    }
+}
+
+fn format_value(v: f64) -> String {
+    if v.abs() >= 1000.0 { format!("{:.0}", v) } else { format!("{:.2}", v) }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_line_histogram_and_candles() {
+        let mut plots = Plots::default();
+        let chart = plots.chart_mut("Price");
+        chart.series_mut("Bars", None, SeriesData::Candles(vec![])).data = SeriesData::Candles(vec![
+            Candle { index: 0, time: None, open: 10.0, high: 12.0, low: 9.0, close: 11.0 },
+            Candle { index: 1, time: None, open: 11.0, high: 11.5, low: 8.0, close: 9.0 },
+            Candle { index: 2, time: None, open: 9.0, high: f64::INFINITY, low: 8.0, close: 9.0 },
+        ]);
+        chart.series_mut("SMA", Some("black".to_string()), SeriesData::Line(vec![])).data = SeriesData::Line(vec![
+            PlotPoint { index: 0, time: None, value: 10.5 },
+            PlotPoint { index: 1, time: None, value: 10.0 },
+        ]);
+        let svg = render_svg(&plots.charts["Price"]);
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        assert!(svg.contains(r#"stroke="black""#));
+        assert!(svg.contains("crimson") && svg.contains("seagreen"));
+        assert!(!svg.contains("inf") && !svg.contains("NaN"));
+
+        let histogram = Chart {
+            title: "A < B".to_string(),
+            series: vec![Series {
+                name: "h".to_string(),
+                color: "steelblue".to_string(),
+                data: SeriesData::Histogram(vec![PlotPoint { index: 0, time: None, value: -1.0 }]),
+            }],
+            ..Chart::default()
+        };
+        let svg = render_svg(&histogram);
+        assert!(svg.contains("A &lt; B"));
+        assert!(svg.contains(r#"fill="steelblue""#));
+    }
+
+    #[test]
+    fn gives_colliding_chart_names_their_own_files() {
+        let mut plots = Plots::default();
+        for name in ["a/b", "a_b", "A_B"] {
+            plots.chart_mut(name);
+        }
+        let dir = std::env::temp_dir().join(format!("stonkscheme-plots-{}", std::process::id()));
+        let written = plots.write_svgs(&dir).unwrap();
+        let names: Vec<_> = written.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["a_b.svg", "a_b-2.svg", "A_B-3.svg"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+//! Portfolio backtests: one program traded across many symbols from a single account.
+//!
+//! Every symbol gets its own [`BarEngine`], and so its own position, but they draw
+//! on one pool of cash. Bars are aligned by timestamp. At each timestamp the
+//! symbols' scripts are evaluated on a pool of worker threads, each symbol's state
+//! living in a [`DashMap`]. Their orders are then sized, held to the account's
+//! buying power and checked against the exposure limit one symbol at a time, in
+//! the order the symbols were given, so a run is the same however many workers it
+//! had.
+//!
+//! Buying power is what equity covers beyond the margin of the open positions,
+//! with [`BrokerConfig::margin`] of each position's value held as margin. At the
+//! default margin of 1 that is the pool's cash for long positions; short sales
+//! are held to the same amount.
+
+use crate::ast::Expr;
+use crate::bars::Bar;
+use crate::broker::{BrokerConfig, EquityPoint, Order, Trade};
+use crate::engine::BarEngine;
+use crate::interpreter::EvalError;
+use crate::report::PerformanceReport;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How many shares an order that opens or adds to a position is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sizing {
+    /// Whatever the script asked for.
+    Script,
+    /// This fraction of portfolio equity at the signal bar's close.
+    PercentEquity(f64),
+    /// Portfolio equity split evenly between the symbols.
+    EqualWeight,
+}
+
+/// Parses `script`, `percent-equity:F` or `equal-weight`.
+impl FromStr for Sizing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("unknown sizing `{}`", s);
+        match s.split_once(':') {
+            None if s == "script" => Ok(Sizing::Script),
+            None if s == "equal-weight" => Ok(Sizing::EqualWeight),
+            Some(("percent-equity", f)) => {
+                let f: f64 = f.parse().map_err(|_| bad())?;
+                if f.is_finite() && f > 0.0 { Ok(Sizing::PercentEquity(f)) } else { Err(bad()) }
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PortfolioConfig {
+    /// Shared by every symbol; `initial_cash` is the whole account's.
+    pub broker: BrokerConfig,
+    pub sizing: Sizing,
+    /// Most the open positions may be worth together, as a multiple of equity.
+    pub max_exposure: Option<f64>,
+    pub workers: usize,
+}
+
+impl Default for PortfolioConfig {
+    fn default() -> Self {
+        Self {
+            broker: BrokerConfig::default(),
+            sizing: Sizing::Script,
+            max_exposure: None,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+/// A closed round trip and the symbol it was in.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SymbolTrade {
+    pub symbol: String,
+    #[serde(flatten)]
+    pub trade: Trade,
+}
+
+#[derive(Debug, Clone)]
+pub struct PortfolioResult {
+    pub report: PerformanceReport,
+    /// Every symbol's trades, by exit time.
+    pub trades: Vec<SymbolTrade>,
+    /// The account at each timestamp; `position` counts the symbols held.
+    pub equity_curve: Vec<EquityPoint>,
+    /// Each symbol's own report, against the whole account's starting cash.
+    pub symbols: IndexMap<String, PerformanceReport>,
+}
+
+/// A script error and the symbol it happened on.
+#[derive(Debug, Clone)]
+pub struct PortfolioError {
+    pub symbol: String,
+    pub error: EvalError,
+}
+
+impl fmt::Display for PortfolioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.symbol, self.error)
+    }
+}
+
+struct SymbolState {
+    engine: BarEngine,
+    /// Index of the symbol's next bar.
+    next: usize,
+    last_close: Option<f64>,
+}
+
+/// Run `program` over every symbol's bars with one shared account.
+pub fn run(
+    program: &[Expr],
+    symbols: &IndexMap<String, Vec<Bar>>,
+    config: &PortfolioConfig,
+    inputs: IndexMap<String, Expr>,
+) -> Result<PortfolioResult, PortfolioError> {
+    let history: Arc<DashMap<String, Vec<Bar>>> = Arc::new(DashMap::new());
+    let states: DashMap<String, SymbolState> = DashMap::new();
+    for name in symbols.keys() {
+        let broker = BrokerConfig { initial_cash: 0.0, ..config.broker.clone() };
+        let mut engine = BarEngine::new(broker).with_inputs(inputs.clone());
+        engine.interpreter.symbols = Some(history.clone());
+        history.insert(name.clone(), Vec::new());
+        states.insert(name.clone(), SymbolState { engine, next: 0, last_close: None });
+    }
+
+    let timestamps: BTreeSet<DateTime<Utc>> =
+        symbols.values().flat_map(|bars| bars.iter().map(|bar| bar.timestamp.0)).collect();
+    let mut equity_curve = Vec::with_capacity(timestamps.len());
+    for (step, &time) in timestamps.iter().enumerate() {
+        // Every symbol's bar at this time is visible to every script before any of them runs; a symbol
+        // with out-of-order or repeated timestamps catches up one bar per step
+        let due: Vec<(&String, usize)> = symbols
+            .iter()
+            .filter_map(|(name, bars)| {
+                let next = states.get(name).expect("every symbol has a state").next;
+                bars.get(next).filter(|bar| bar.timestamp.0 <= time).map(|_| (name, next))
+            })
+            .collect();
+        for &(name, index) in &due {
+            history.get_mut(name).expect("every symbol has a history").push(symbols[name][index].clone());
+        }
+
+        // Each script's `account-equity` is the whole account's, as of the last bar
+        let equities: Vec<f64> = symbols
+            .keys()
+            .map(|name| {
+                let state = states.get(name).expect("every symbol has a state");
+                state.last_close.map_or(0.0, |close| state.engine.broker.equity(close))
+            })
+            .collect();
+        let total = config.broker.initial_cash + equities.iter().sum::<f64>();
+        for &(name, _) in &due {
+            let own = equities[symbols.get_index_of(name).expect("due symbols are symbols")];
+            states.get_mut(name).expect("every symbol has a state").engine.external_equity = total - own;
+        }
+
+        let orders = evaluate(program, symbols, &states, &due, config.workers)?;
+        for &(name, index) in &due {
+            states.get_mut(name).expect("every symbol has a state").last_close = Some(symbols[name][index].close);
+        }
+
+        // Summed in symbol order, so the float sums don't depend on the map's layout
+        let mut equity = config.broker.initial_cash;
+        let mut exposure = 0.0;
+        for name in symbols.keys() {
+            let state = states.get(name).expect("every symbol has a state");
+            if let Some(close) = state.last_close {
+                equity += state.engine.broker.equity(close);
+                exposure += (state.engine.broker.position() as f64 * close).abs();
+            }
+        }
+        for ((name, index), orders) in due.iter().zip(orders) {
+            let bar = &symbols[*name][*index];
+            let mut state = states.get_mut(*name).expect("every symbol has a state");
+            let mut position = state.engine.broker.position();
+            let mut sized = Vec::with_capacity(orders.len());
+            for mut order in orders {
+                // What closes part of the position goes through as asked; only the rest, which opens
+                // or adds to one, is sized
+                let reduces = position.signum() == -order.side.sign();
+                let closing = if reduces { order.quantity.min(position.abs()) } else { 0 };
+                exposure -= closing as f64 * bar.close;
+                let opening = match order.quantity - closing {
+                    0 => 0,
+                    rest => size(config, rest, equity, exposure, symbols.len(), bar.close),
+                };
+                exposure += opening as f64 * bar.close;
+                order.quantity = closing + opening;
+                if order.quantity > 0 {
+                    position += order.side.sign() * order.quantity;
+                    sized.push(order);
+                }
+            }
+            state.engine.settle(&sized, *index, bar);
+            state.next += 1;
+        }
+
+        let (mut equity, mut held) = (config.broker.initial_cash, 0);
+        for name in symbols.keys() {
+            let state = states.get(name).expect("every symbol has a state");
+            if let Some(close) = state.last_close {
+                equity += state.engine.broker.equity(close);
+                held += i64::from(state.engine.broker.position() != 0);
+            }
+        }
+        let timestamp = due.first().map(|&(name, index)| symbols[name][index].timestamp.clone());
+        equity_curve.push(EquityPoint {
+            bar_index: step,
+            timestamp: timestamp.expect("every timestamp has a bar"),
+            equity,
+            position: held,
+        });
+    }
+
+    let mut trades = Vec::new();
+    let mut reports = IndexMap::new();
+    for name in symbols.keys() {
+        let (_, state) = states.remove(name).expect("every symbol has a state");
+        let broker = state.engine.broker;
+        let curve: Vec<EquityPoint> = broker
+            .equity_curve()
+            .iter()
+            .map(|point| EquityPoint { equity: config.broker.initial_cash + point.equity, ..point.clone() })
+            .collect();
+        reports.insert(name.clone(), PerformanceReport::new(config.broker.initial_cash, broker.trades(), &curve));
+        trades.extend(broker.trades().iter().map(|trade| SymbolTrade { symbol: name.clone(), trade: trade.clone() }));
+    }
+    trades.sort_by_key(|t| t.trade.exit_time.0);
+    let flat: Vec<Trade> = trades.iter().map(|t| t.trade.clone()).collect();
+    Ok(PortfolioResult {
+        report: PerformanceReport::new(config.broker.initial_cash, &flat, &equity_curve),
+        trades,
+        equity_curve,
+        symbols: reports,
+    })
+}
+
+/// Evaluate the script of every symbol with a bar due, returning their orders in `due`'s order.
+fn evaluate(
+    program: &[Expr],
+    symbols: &IndexMap<String, Vec<Bar>>,
+    states: &DashMap<String, SymbolState>,
+    due: &[(&String, usize)],
+    workers: usize,
+) -> Result<Vec<Vec<Order>>, PortfolioError> {
+    let run = |&(name, index): &(&String, usize)| {
+        let mut state = states.get_mut(name).expect("every symbol has a state");
+        let engine = &mut state.engine;
+        engine
+            .evaluate(program, index, &symbols[name][index])
+            .map(|_| std::mem::take(&mut engine.interpreter.orders))
+            .map_err(|error| PortfolioError { symbol: name.clone(), error })
+    };
+    let workers = workers.clamp(1, due.len().max(1));
+    if workers == 1 {
+        return due.iter().map(run).collect();
+    }
+    let chunk = due.len().div_ceil(workers);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> =
+            due.chunks(chunk).map(|due| scope.spawn(move || due.iter().map(run).collect::<Vec<_>>())).collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("script threads don't panic")).collect()
+    })
+}
+
+/// The quantity for `requested` shares that open or add to a position, after sizing, buying power and
+/// the exposure limit.
+fn size(config: &PortfolioConfig, requested: i64, equity: f64, exposure: f64, symbols: usize, price: f64) -> i64 {
+    if price <= 0.0 || price.is_nan() {
+        return requested;
+    }
+    let shares = |value: f64| (value / price).floor().max(0.0) as i64;
+    let quantity = match config.sizing {
+        Sizing::Script => requested,
+        Sizing::PercentEquity(f) => shares(equity * f),
+        Sizing::EqualWeight => shares(equity / symbols as f64),
+    };
+    let margin = config.broker.margin;
+    let quantity = if margin > 0.0 { quantity.min(shares((equity - margin * exposure) / margin)) } else { quantity };
+    match config.max_exposure {
+        Some(max) => quantity.min(shares(max * equity - exposure)),
+        None => quantity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::parse_csv;
+    use crate::parser::parse_snippet;
+
+    fn symbols() -> IndexMap<String, Vec<Bar>> {
+        let spy = "2024-01-01,100,101,99,100\n2024-01-02,100,106,100,105\n2024-01-03,105,111,105,110\n";
+        let qqq = "2024-01-01,50,51,49,50\n2024-01-03,50,51,44,45\n";
+        IndexMap::from([
+            ("SPY".to_string(), parse_csv(spy).unwrap()),
+            ("QQQ".to_string(), parse_csv(qqq).unwrap()),
+        ])
+    }
+
+    #[test]
+    fn shares_one_account_and_reads_other_symbols() {
+        // Every symbol buys once, on its first bar, if SPY closed above 99
+        let program = [parse_snippet("(if (= bar-index 0) (if (> (close 0 :symbol \"SPY\") 99) (buy 10)))").unwrap().value];
+        let config = PortfolioConfig {
+            broker: BrokerConfig { initial_cash: 10_000.0, ..BrokerConfig::default() },
+            ..PortfolioConfig::default()
+        };
+        let result = run(&program, &symbols(), &config, IndexMap::new()).unwrap();
+
+        // SPY fills at 100 and ends at 110; QQQ fills at 50 and ends at 45
+        let last = result.equity_curve.last().unwrap();
+        assert_eq!(result.equity_curve.len(), 3);
+        assert_eq!(last.equity, 10_000.0 + 100.0 - 50.0);
+        assert_eq!(last.position, 2);
+        assert!(result.trades.is_empty());
+    }
+
+    #[test]
+    fn sizes_orders_and_caps_exposure() {
+        let program = [parse_snippet("(if (= bar-index 0) (buy 1))").unwrap().value];
+        let config = PortfolioConfig {
+            broker: BrokerConfig { initial_cash: 10_000.0, ..BrokerConfig::default() },
+            sizing: Sizing::PercentEquity(0.6),
+            max_exposure: Some(1.0),
+            workers: 2,
+        };
+        let result = run(&program, &symbols(), &config, IndexMap::new()).unwrap();
+        // SPY takes 60 shares for 60% of equity, leaving QQQ 80 shares for the remaining 40%
+        assert_eq!(result.equity_curve[1].equity, 10_000.0 + 60.0 * 5.0);
+        assert_eq!(result.equity_curve[2].equity, 10_000.0 + 60.0 * 10.0 - 80.0 * 5.0);
+        assert_eq!("percent-equity:0.6".parse(), Ok(Sizing::PercentEquity(0.6)));
+        assert!("percent-equity:-1".parse::<Sizing>().is_err());
+    }
+
+    #[test]
+    fn holds_orders_to_buying_power_and_sizes_reversals() {
+        let program = [parse_snippet("(if (= bar-index 0) (buy 100) (if (= bar-index 1) (sell 200)))").unwrap().value];
+        let config = PortfolioConfig {
+            broker: BrokerConfig { initial_cash: 1000.0, ..BrokerConfig::default() },
+            workers: 1,
+            ..PortfolioConfig::default()
+        };
+        let result = run(&program, &symbols(), &config, IndexMap::new()).unwrap();
+        // The cash buys SPY 10 shares at 100 and leaves nothing for QQQ; SPY's reversal sells those 10 and
+        // opens a short of only 10 more, as much as the 1050 of equity covers at 105
+        assert_eq!(result.equity_curve[1].equity, 1000.0 + 10.0 * 5.0);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!((result.trades[0].symbol.as_str(), result.trades[0].trade.quantity), ("SPY", 10));
+        assert_eq!(result.equity_curve[2].equity, 1000.0 + 10.0 * 5.0 - 10.0 * 5.0);
+        assert_eq!(result.equity_curve[2].position, 1);
+    }
+}
@@ -0,0 +1,364 @@
+//! Interactive prompt built on rustyline.
+//!
+//! Input continues over several lines until its parentheses and strings are
+//! closed, history persists between sessions, and tab completes builtins and
+//! whatever the session has bound so far. Lines starting with `,` are
+//! meta-commands handled by [`Session::command`] instead of the interpreter.
+
+use crate::ast::Expr;
+use crate::bars::{self, Bar};
+use crate::code::Spanned;
+use crate::diagnostics::{use_color, Diagnostic};
+use crate::interpreter::{Env, Interpreter, BUILTINS};
+use crate::parser::{is_symbol_char, parse_program, parse_program_file};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const PROMPT: &str = "> ";
+
+/// Meta-commands with their argument and what they do, for `,help` and completion.
+const COMMANDS: &[(&str, &str, &str)] = &[
+    (",load", "FILE", "evaluate a file into the session"),
+    (",reload", "", "evaluate the last loaded file again"),
+    (",env", "", "list the bindings in every scope"),
+    (",type", "EXPR", "evaluate EXPR and show the type of its value"),
+    (",time", "EXPR", "evaluate EXPR and show how long it took"),
+    (",ast", "EXPR", "show how EXPR parses, without evaluating it"),
+    (",bars", "[CSV]", "attach a dataset and bind its last bar, or show the attached one"),
+    (",help", "", "list these commands"),
+];
+
+/// Completion and line continuation for the editor.
+pub struct ReplHelper {
+    /// Shares its scopes with the interpreter's, so new bindings show up as they are made.
+    env: Env,
+}
+
+impl ReplHelper {
+    pub fn new(env: Env) -> Self {
+        Self { env }
+    }
+
+    /// Start of the symbol under the cursor and the names it could complete to.
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        if line.starts_with(',') && !line[..pos].contains(' ') {
+            let names = COMMANDS.iter().map(|c| c.0.to_string()).filter(|name| name.starts_with(&line[..pos])).collect();
+            return (0, names);
+        }
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_symbol_char(c))
+            .last()
+            .map_or(pos, |(i, _)| i);
+        let prefix = &line[start..pos];
+        let mut names: Vec<String> = self
+            .env
+            .names()
+            .into_iter()
+            .chain(BUILTINS.iter().map(|b| b.to_string()))
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names.dedup();
+        (start, names)
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, names) = self.candidates(line, pos);
+        let pairs = names.into_iter().map(|name| Pair { display: name.clone(), replacement: name }).collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if is_incomplete(ctx.input()) { ValidationResult::Incomplete } else { ValidationResult::Valid(None) })
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Whether `src` still has an open parenthesis, bracket or string. `;` comments are skipped.
+///
+/// Too many closing parentheses count as complete so the parser can report them.
+pub fn is_incomplete(src: &str) -> bool {
+    let mut depth = 0i64;
+    let mut in_string = false;
+    let mut chars = src.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            ';' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    in_string || depth > 0
+}
+
+/// `$STONKSCHEME_HISTORY`, else `~/.stonkscheme_history`.
+fn history_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("STONKSCHEME_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".stonkscheme_history"))
+}
+
+/// Parse `input` and evaluate each of its forms.
+pub fn eval_input(interpreter: &mut Interpreter, input: &str) -> Result<Vec<Expr>, Diagnostic> {
+    let forms = parse_program(input).map_err(|e| e.diagnostic())?;
+    eval_forms(interpreter, &forms)
+}
+
+fn eval_forms(interpreter: &mut Interpreter, forms: &[Spanned<Expr>]) -> Result<Vec<Expr>, Diagnostic> {
+    let program: Vec<Expr> = forms.iter().map(|form| form.value.clone()).collect();
+    interpreter.eval_program(&program).map_err(|e| e.diagnostic(forms))
+}
+
+/// An interpreter plus what the meta-commands have attached to it.
+#[derive(Default)]
+pub struct Session {
+    pub interpreter: Interpreter,
+    /// The file `,reload` evaluates again.
+    loaded: Option<PathBuf>,
+    bars: Option<(PathBuf, Vec<Bar>)>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluate a file into the session and remember it for `,reload`.
+    pub fn load(&mut self, path: &Path) -> Result<String, Diagnostic> {
+        let forms = parse_program_file(path).map_err(|e| e.diagnostic())?;
+        self.loaded = Some(path.to_path_buf());
+        eval_forms(&mut self.interpreter, &forms)?;
+        Ok(format!("Loaded {} ({} forms)", path.display(), forms.len()))
+    }
+
+    /// Run a `,command` line and return what it prints.
+    pub fn command(&mut self, line: &str) -> Result<String, Diagnostic> {
+        let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        let require_arg = |what: &str| {
+            if arg.is_empty() { Err(format!("{} requires {}", name, what)) } else { Ok(arg) }
+        };
+        match name {
+            ",load" => self.load(Path::new(require_arg("a file")?)),
+            ",reload" => match self.loaded.clone() {
+                Some(path) => self.load(&path),
+                None => Err("nothing has been loaded yet".to_string().into()),
+            },
+            ",env" => Ok(self.dump_env()),
+            ",type" => {
+                let values = eval_input(&mut self.interpreter, require_arg("an expression")?)?;
+                Ok(values.iter().map(Expr::type_name).collect::<Vec<_>>().join("\n"))
+            }
+            ",time" => {
+                let started = Instant::now();
+                let values = eval_input(&mut self.interpreter, require_arg("an expression")?)?;
+                let elapsed = started.elapsed();
+                let mut out: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                out.push(format!("Elapsed: {:?}", elapsed));
+                Ok(out.join("\n"))
+            }
+            ",ast" => {
+                let forms = parse_program(require_arg("an expression")?).map_err(|e| e.diagnostic())?;
+                Ok(forms.iter().map(|form| format!("{:#?}", form.value)).collect::<Vec<_>>().join("\n"))
+            }
+            ",bars" if arg.is_empty() => match &self.bars {
+                Some((path, bars)) => Ok(format!("{}: {} bars", path.display(), bars.len())),
+                None => Ok("No dataset attached".to_string()),
+            },
+            ",bars" => Ok(self.attach_bars(Path::new(arg))?),
+            ",help" => Ok(COMMANDS
+                .iter()
+                .map(|(name, arg, help)| format!("{:<8} {:<6} {}", name, arg, help))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            _ => Err(format!("unknown command `{}`, try ,help", name).into()),
+        }
+    }
+
+    /// Every scope from the outermost in, one binding per line.
+    fn dump_env(&self) -> String {
+        let mut out = Vec::new();
+        for (depth, scope) in self.interpreter.env.scope_stack.iter().enumerate() {
+            out.push(format!("scope {}:", depth));
+            for (name, value) in scope.lock().unwrap().iter() {
+                out.push(format!("  {} = {}", name, value));
+            }
+        }
+        out.join("\n")
+    }
+
+    /// Load a CSV of bars and step through them, leaving the last one bound as if the engine were on it.
+    fn attach_bars(&mut self, path: &Path) -> Result<String, String> {
+        let bars = bars::load_csv(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let Some(last) = bars.last() else {
+            return Err(format!("{}: no bars", path.display()));
+        };
+        self.interpreter.clear_history();
+        for (index, bar) in bars.iter().enumerate() {
+            self.interpreter.bind_bar(index, bar);
+        }
+        let summary = format!(
+            "Attached {}: {} bars from {} to {}, bound the last one",
+            path.display(),
+            bars.len(),
+            bars[0].timestamp.0.to_rfc3339(),
+            last.timestamp.0.to_rfc3339()
+        );
+        self.bars = Some((path.to_path_buf(), bars));
+        Ok(summary)
+    }
+}
+
+/// Read, evaluate and print until end of input or `exit`.
+pub fn run(session: &mut Session) -> rustyline::Result<()> {
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper::new(session.interpreter.env.clone())));
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let input = match editor.readline(PROMPT) {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(trimmed)?;
+        if trimmed == "exit" {
+            break;
+        }
+        if trimmed.starts_with(',') {
+            match session.command(trimmed) {
+                Ok(out) => println!("{}", out),
+                Err(e) => eprintln!("{}", e.render(use_color())),
+            }
+            continue;
+        }
+        match eval_input(&mut session.interpreter, &input) {
+            Ok(values) => {
+                for value in values {
+                    println!("{}", value);
+                }
+            }
+            Err(e) => eprintln!("{}", e.render(use_color())),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Unit;
+
+    #[test]
+    fn waits_for_balanced_input() {
+        assert!(is_incomplete("(+ 1"));
+        assert!(is_incomplete("(plot close :title \"a)"));
+        assert!(!is_incomplete("(plot close :title \"a)\")"));
+        assert!(!is_incomplete("(+ 1 2))"));
+        assert!(!is_incomplete("(set s \"\\\"(\")"));
+        assert!(!is_incomplete("(buy 1) ; close (later"));
+        assert!(!is_incomplete("(write 1) ; a \"quote\n(write 2)"));
+        assert!(is_incomplete("(write [1 2"));
+        assert!(!is_incomplete("(write [1 2])"));
+    }
+
+    #[test]
+    fn list_errors_do_not_end_the_session() {
+        let mut interpreter = Interpreter::new();
+        assert!(eval_input(&mut interpreter, "(car 1)").is_err());
+        assert!(eval_input(&mut interpreter, "(cdr)").is_err());
+        assert!(eval_input(&mut interpreter, "(cons \"a\")").is_err());
+        assert!(eval_input(&mut interpreter, "(+ 1 2)").is_ok());
+    }
+
+    #[test]
+    fn completes_builtins_and_bindings() {
+        let mut interpreter = Interpreter::new();
+        let helper = ReplHelper::new(interpreter.env.clone());
+        eval_input(&mut interpreter, "(set plot-width 3)").unwrap();
+
+        let (start, names) = helper.candidates("(+ 1 (plo", 9);
+        assert_eq!(start, 6);
+        assert_eq!(names, ["plot", "plot-candles", "plot-overlay", "plot-width"]);
+        assert_eq!(helper.candidates("(", 1).1.len(), BUILTINS.len() + 1);
+    }
+
+    #[test]
+    fn reports_parse_errors_instead_of_panicking() {
+        let mut interpreter = Interpreter::new();
+        assert!(eval_input(&mut interpreter, "(+ 1 \"2)").is_err());
+        assert_eq!(eval_input(&mut interpreter, "(+ 1 2) (* 2 3)").unwrap(), [Expr::Integer(3), Expr::Integer(6)]);
+    }
+
+    #[test]
+    fn meta_commands_load_and_inspect_the_session() {
+        let dir = std::env::temp_dir().join(format!("stonkscheme-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("script.scm");
+        std::fs::write(&script, "(set size 2)").unwrap();
+        let csv = dir.join("bars.csv");
+        std::fs::write(&csv, "2024-01-01,10,11,9,10.5\n2024-01-02,10.5,12,10,11.5\n").unwrap();
+
+        let mut session = Session::new();
+        assert!(session.command(",reload").is_err());
+        session.command(&format!(",load {}", script.display())).unwrap();
+        assert_eq!(session.command(",type size").unwrap(), "Integer");
+
+        std::fs::write(&script, "(set size 2.5)").unwrap();
+        session.command(",reload").unwrap();
+        assert_eq!(session.command(",type size").unwrap(), "Float");
+        assert!(session.command(",env").unwrap().contains("size = 2.5"));
+
+        session.command(&format!(",bars {}", csv.display())).unwrap();
+        assert!(session.command(",time (* close size)").unwrap().starts_with("#price:28.75\n"));
+        session.command(&format!(",bars {}", csv.display())).unwrap();
+        assert_eq!(eval_input(&mut session.interpreter, "(close 1)").unwrap(), [Expr::Amount(Unit::Price, Box::new(Expr::Float(10.5)))]);
+        assert_eq!(eval_input(&mut session.interpreter, "(close 2)").unwrap(), [Expr::Nil]);
+        assert_eq!(session.command(",ast (+ 1)").unwrap(), format!("{:#?}", "(+ 1)".parse::<Expr>().unwrap()));
+        assert!(session.command(",nope").is_err());
+    }
+}
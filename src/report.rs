@@ -0,0 +1,346 @@
+//! Strategy performance report, modelled on TradeStation's.
+
+use crate::ast::Duration;
+use crate::broker::{EquityPoint, Trade};
+use chrono::Duration as ChronoDuration;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PerformanceReport {
+    pub initial_capital: f64,
+    pub final_equity: f64,
+    pub net_profit: f64,
+    pub gross_profit: f64,
+    pub gross_loss: f64,
+    /// Gross profit over gross loss; infinite with no losing trades, which JSON spells `"inf"`.
+    #[serde(serialize_with = "serialize_ratio")]
+    pub profit_factor: f64,
+    pub total_trades: usize,
+    pub winning_trades: usize,
+    pub losing_trades: usize,
+    pub win_rate: f64,
+    pub average_trade: f64,
+    pub average_win: f64,
+    pub average_loss: f64,
+    pub largest_win: f64,
+    pub largest_loss: f64,
+    pub max_drawdown: f64,
+    pub max_drawdown_pct: f64,
+    /// Annualized from per-bar returns over 252 trading days, zero risk-free rate.
+    pub sharpe: f64,
+    pub sortino: f64,
+    pub cagr: f64,
+    /// Fraction of bars with an open position.
+    pub exposure: f64,
+    pub average_trade_duration: Duration,
+    pub max_trade_duration: Duration,
+    pub min_trade_duration: Duration,
+    pub average_bars_in_trade: f64,
+}
+
+impl PerformanceReport {
+    pub fn new(initial_capital: f64, trades: &[Trade], equity_curve: &[EquityPoint]) -> Self {
+        let wins: Vec<f64> = trades.iter().map(|t| t.pnl).filter(|p| *p > 0.0).collect();
+        let losses: Vec<f64> = trades.iter().map(|t| t.pnl).filter(|p| *p < 0.0).collect();
+        let gross_profit = sum(&wins);
+        let gross_loss = sum(&losses);
+        let final_equity = equity_curve.last().map_or(initial_capital, |p| p.equity);
+
+        let (max_drawdown, max_drawdown_pct) = max_drawdown(initial_capital, equity_curve);
+        let returns = returns(initial_capital, equity_curve);
+        let periods = periods_per_year(equity_curve);
+
+        let durations: Vec<ChronoDuration> = trades.iter().map(|t| t.exit_time.0 - t.entry_time.0).collect();
+        let total_duration = durations.iter().fold(ChronoDuration::zero(), |acc, d| acc + *d);
+
+        Self {
+            initial_capital,
+            final_equity,
+            net_profit: final_equity - initial_capital,
+            gross_profit,
+            gross_loss,
+            profit_factor: if gross_loss < 0.0 {
+                gross_profit / -gross_loss
+            } else if gross_profit > 0.0 {
+                f64::INFINITY
+            } else {
+                0.0
+            },
+            total_trades: trades.len(),
+            winning_trades: wins.len(),
+            losing_trades: losses.len(),
+            win_rate: ratio(wins.len() as f64, trades.len() as f64),
+            average_trade: ratio(gross_profit + gross_loss, trades.len() as f64),
+            average_win: ratio(gross_profit, wins.len() as f64),
+            average_loss: ratio(gross_loss, losses.len() as f64),
+            largest_win: wins.iter().copied().fold(0.0, f64::max),
+            largest_loss: losses.iter().copied().fold(0.0, f64::min),
+            max_drawdown,
+            max_drawdown_pct,
+            sharpe: ratio(mean(&returns), std_dev(&returns)) * periods.sqrt(),
+            sortino: ratio(mean(&returns), downside_dev(&returns)) * periods.sqrt(),
+            cagr: cagr(initial_capital, final_equity, equity_curve),
+            exposure: ratio(
+                equity_curve.iter().filter(|p| p.position != 0).count() as f64,
+                equity_curve.len() as f64,
+            ),
+            average_trade_duration: Duration(if durations.is_empty() {
+                ChronoDuration::zero()
+            } else {
+                total_duration / durations.len() as i32
+            }),
+            max_trade_duration: Duration(durations.iter().copied().max().unwrap_or_else(ChronoDuration::zero)),
+            min_trade_duration: Duration(durations.iter().copied().min().unwrap_or_else(ChronoDuration::zero)),
+            average_bars_in_trade: ratio(
+                trades.iter().fold(0.0, |acc, t| acc + (t.exit_bar - t.entry_bar) as f64),
+                trades.len() as f64,
+            ),
+        }
+    }
+}
+
+/// `a / b`, or zero when `b` is zero.
+fn ratio(a: f64, b: f64) -> f64 {
+    if b == 0.0 { 0.0 } else { a / b }
+}
+
+/// Unlike `Iterator::sum`, an empty sum is `+0.0`.
+fn sum(xs: &[f64]) -> f64 {
+    xs.iter().fold(0.0, |acc, x| acc + x)
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    ratio(sum(xs), xs.len() as f64)
+}
+
+fn std_dev(xs: &[f64]) -> f64 {
+    if xs.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(xs);
+    (xs.iter().fold(0.0, |acc, x| acc + (x - m).powi(2)) / (xs.len() - 1) as f64).sqrt()
+}
+
+fn downside_dev(xs: &[f64]) -> f64 {
+    ratio(xs.iter().fold(0.0, |acc, x| acc + x.min(0.0).powi(2)), xs.len() as f64).sqrt()
+}
+
+/// Simple per-bar returns, starting from the initial capital.
+fn returns(initial_capital: f64, equity_curve: &[EquityPoint]) -> Vec<f64> {
+    let mut previous = initial_capital;
+    equity_curve
+        .iter()
+        .map(|p| {
+            let r = ratio(p.equity - previous, previous);
+            previous = p.equity;
+            r
+        })
+        .collect()
+}
+
+/// Largest peak-to-trough fall, as `(amount, fraction of peak)`.
+fn max_drawdown(initial_capital: f64, equity_curve: &[EquityPoint]) -> (f64, f64) {
+    let mut peak = initial_capital;
+    let (mut amount, mut pct) = (0.0_f64, 0.0_f64);
+    for point in equity_curve {
+        peak = peak.max(point.equity);
+        amount = amount.max(peak - point.equity);
+        pct = pct.max(ratio(peak - point.equity, peak));
+    }
+    (amount, pct)
+}
+
+pub(crate) const SECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0;
+
+/// Trading days in a year, as TradeStation annualizes daily returns.
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+/// Bars per year. Daily and intraday bars count 252 trading days of however many bars
+/// a day the curve has; weekly and longer bars go by the median spacing of their timestamps.
+fn periods_per_year(equity_curve: &[EquityPoint]) -> f64 {
+    let mut gaps: Vec<i64> = equity_curve
+        .windows(2)
+        .map(|w| (w[1].timestamp.0 - w[0].timestamp.0).num_seconds())
+        .filter(|s| *s > 0)
+        .collect();
+    if gaps.is_empty() {
+        return 1.0;
+    }
+    gaps.sort_unstable();
+    let median = gaps[gaps.len() / 2];
+    if median >= 4 * 86_400 {
+        return SECONDS_PER_YEAR / median as f64;
+    }
+    let mut days: Vec<_> = equity_curve.iter().map(|p| p.timestamp.0.date_naive()).collect();
+    days.dedup();
+    TRADING_DAYS_PER_YEAR * equity_curve.len() as f64 / days.len() as f64
+}
+
+/// Infinity as `"inf"`, which JSON numbers cannot hold.
+pub(crate) fn serialize_ratio<S: serde::Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    match *value {
+        v if v == f64::INFINITY => serializer.serialize_str("inf"),
+        v => serializer.serialize_f64(v),
+    }
+}
+
+fn cagr(initial_capital: f64, final_equity: f64, equity_curve: &[EquityPoint]) -> f64 {
+    let (Some(first), Some(last)) = (equity_curve.first(), equity_curve.last()) else {
+        return 0.0;
+    };
+    let years = (last.timestamp.0 - first.timestamp.0).num_seconds() as f64 / SECONDS_PER_YEAR;
+    if years <= 0.0 || initial_capital <= 0.0 || final_equity <= 0.0 {
+        return 0.0;
+    }
+    (final_equity / initial_capital).powf(1.0 / years) - 1.0
+}
+
+fn format_duration(d: &Duration) -> String {
+    let secs = d.0.num_seconds();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (hours, rem) = (rem / 3600, rem % 3600);
+    let minutes = rem / 60;
+    match (days, hours, minutes) {
+        (0, 0, m) => format!("{}m", m),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
+impl fmt::Display for PerformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let money = |v: f64| format!("{:.2}", v);
+        let pct = |v: f64| format!("{:.2}%", v * 100.0);
+        let rows = [
+            ("Initial Capital", money(self.initial_capital)),
+            ("Final Equity", money(self.final_equity)),
+            ("Net Profit", money(self.net_profit)),
+            ("Gross Profit", money(self.gross_profit)),
+            ("Gross Loss", money(self.gross_loss)),
+            ("Profit Factor", format!("{:.2}", self.profit_factor)),
+            ("Total Trades", self.total_trades.to_string()),
+            ("Winning Trades", self.winning_trades.to_string()),
+            ("Losing Trades", self.losing_trades.to_string()),
+            ("Percent Profitable", pct(self.win_rate)),
+            ("Avg Trade", money(self.average_trade)),
+            ("Avg Winning Trade", money(self.average_win)),
+            ("Avg Losing Trade", money(self.average_loss)),
+            ("Largest Winning Trade", money(self.largest_win)),
+            ("Largest Losing Trade", money(self.largest_loss)),
+            ("Max Drawdown", money(self.max_drawdown)),
+            ("Max Drawdown %", pct(self.max_drawdown_pct)),
+            ("Sharpe Ratio", format!("{:.3}", self.sharpe)),
+            ("Sortino Ratio", format!("{:.3}", self.sortino)),
+            ("CAGR", pct(self.cagr)),
+            ("Exposure", pct(self.exposure)),
+            ("Avg Trade Duration", format_duration(&self.average_trade_duration)),
+            ("Max Trade Duration", format_duration(&self.max_trade_duration)),
+            ("Min Trade Duration", format_duration(&self.min_trade_duration)),
+            ("Avg Bars in Trade", format!("{:.1}", self.average_bars_in_trade)),
+        ];
+        let label_width = rows.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+        let value_width = rows.iter().map(|(_, v)| v.len()).max().unwrap_or(0);
+        let rule = format!("+-{}-+-{}-+", "-".repeat(label_width), "-".repeat(value_width));
+
+        writeln!(f, "Strategy Performance Report")?;
+        writeln!(f, "{}", rule)?;
+        for (label, value) in rows {
+            writeln!(f, "| {:<lw$} | {:>vw$} |", label, value, lw = label_width, vw = value_width)?;
+        }
+        write!(f, "{}", rule)
+    }
+}
+
+/// Render a trade list as a plain text table.
+pub fn format_trades(trades: &[Trade]) -> String {
+    let mut out = format!(
+        "{:>4}  {:<5}  {:>8}  {:<25}  {:>10}  {:<25}  {:>10}  {:>12}\n",
+        "#", "Dir", "Qty", "Entry Time", "Entry", "Exit Time", "Exit", "P&L"
+    );
+    for (i, t) in trades.iter().enumerate() {
+        out.push_str(&format!(
+            "{:>4}  {:<5}  {:>8}  {:<25}  {:>10.2}  {:<25}  {:>10.2}  {:>12.2}\n",
+            i + 1,
+            format!("{:?}", t.direction),
+            t.quantity,
+            t.entry_time.0.to_rfc3339(),
+            t.entry_price,
+            t.exit_time.0.to_rfc3339(),
+            t.exit_price,
+            t.pnl
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Timestamp;
+    use crate::bars::parse_timestamp;
+    use crate::broker::Direction;
+
+    fn ts(day: u32) -> Timestamp {
+        parse_timestamp(&format!("2024-01-{:02}", day)).unwrap()
+    }
+
+    fn point(day: u32, equity: f64, position: i64) -> EquityPoint {
+        EquityPoint { bar_index: day as usize - 1, timestamp: ts(day), equity, position }
+    }
+
+    fn trade(entry: u32, exit: u32, pnl: f64) -> Trade {
+        Trade {
+            direction: Direction::Long,
+            quantity: 1,
+            entry_bar: entry as usize - 1,
+            entry_time: ts(entry),
+            entry_price: 100.0,
+            exit_bar: exit as usize - 1,
+            exit_time: ts(exit),
+            exit_price: 100.0 + pnl,
+            commission: 0.0,
+            pnl,
+        }
+    }
+
+    #[test]
+    fn computes_trade_and_drawdown_stats() {
+        let trades = [trade(1, 3, 30.0), trade(3, 4, -10.0), trade(4, 5, 20.0)];
+        let curve = [
+            point(1, 1000.0, 1),
+            point(2, 1030.0, 1),
+            point(3, 1010.0, 1),
+            point(4, 1020.0, 1),
+            point(5, 1040.0, 0),
+        ];
+        let report = PerformanceReport::new(1000.0, &trades, &curve);
+        assert_eq!(report.net_profit, 40.0);
+        assert_eq!(report.gross_profit, 50.0);
+        assert_eq!(report.gross_loss, -10.0);
+        assert_eq!(report.profit_factor, 5.0);
+        assert!((report.win_rate - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(report.max_drawdown, 20.0);
+        assert!((report.max_drawdown_pct - 20.0 / 1030.0).abs() < 1e-12);
+        assert_eq!(report.exposure, 0.8);
+        assert_eq!(report.max_trade_duration.0, ChronoDuration::days(2));
+        assert_eq!(report.min_trade_duration.0, ChronoDuration::days(1));
+        assert!(report.sharpe > 0.0);
+        assert!(report.to_string().contains("| Profit Factor"));
+
+        // Daily bars annualize over 252 trading days, and a run without losses says so in JSON
+        assert_eq!(periods_per_year(&curve), 252.0);
+        let report = PerformanceReport::new(1000.0, &trades[..1], &curve);
+        assert_eq!(serde_json::to_value(&report).unwrap()["profit_factor"], "inf");
+    }
+
+    #[test]
+    fn empty_run_is_all_zero() {
+        let report = PerformanceReport::new(1000.0, &[], &[]);
+        assert_eq!(report.net_profit, 0.0);
+        assert_eq!(report.profit_factor, 0.0);
+        assert_eq!(report.sharpe, 0.0);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["total_trades"], 0);
+    }
+}
@@ -0,0 +1,762 @@
+//! Local web dashboard and JSON API.
+//!
+//! Serves `static/index.html`, lists the CSV datasets in a data directory and
+//! backtests scripts posted from the page. The same machinery backs the JSON
+//! API for other tools: `POST /eval`, `POST /backtest`, `GET /runs/{id}` and `DELETE /runs/{id}`,
+//! plus `GET /stream`, a WebSocket that feeds pushed bars to a live session.
+//! Scripts run on tokio's blocking pool, at most `workers` at a time, since the
+//! interpreter is synchronous.
+
+use crate::ast::Expr;
+use crate::backtest;
+use crate::bars::{self, parse_timestamp, Bar, DataStream};
+use crate::broker::{BrokerConfig, Commission, FillModel, Slippage};
+use crate::calendar::{Calendar, Session, TimeZone};
+use crate::code::{SpanInfo, Spanned};
+use crate::engine::BarEngine;
+use crate::interpreter::{EvalError, Interpreter};
+use crate::parser::{parse_program, ParseError};
+use crate::plot::{render_svg, Plots, SeriesData};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+const INDEX_HTML: &str = include_str!("../static/index.html");
+/// Finished runs kept for `GET /runs/{id}`; past this the oldest are forgotten.
+const MAX_FINISHED_RUNS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// Directory whose `*.csv` files are offered as datasets.
+    pub data_dir: PathBuf,
+    /// How many scripts may run at once.
+    pub workers: usize,
+}
+
+struct AppState {
+    data_dir: PathBuf,
+    workers: Arc<Semaphore>,
+    runs: Mutex<Runs>,
+}
+
+/// Runs started by `POST /backtest`, with the finished ones in the order they finished.
+#[derive(Default)]
+struct Runs {
+    statuses: HashMap<Uuid, RunStatus>,
+    finished: VecDeque<Uuid>,
+}
+
+impl Runs {
+    /// Record a finished run, unless it was deleted while running, and forget the oldest past the cap.
+    fn finish(&mut self, id: Uuid, status: RunStatus) {
+        let Some(slot) = self.statuses.get_mut(&id) else { return };
+        *slot = status;
+        self.finished.push_back(id);
+        while self.finished.len() > MAX_FINISHED_RUNS {
+            if let Some(oldest) = self.finished.pop_front() {
+                self.statuses.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Option<RunStatus> {
+        self.finished.retain(|finished| finished != id);
+        self.statuses.remove(id)
+    }
+}
+
+/// What kind of failure an [`ErrorBody`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    /// The request itself was malformed.
+    Request,
+    Parse,
+    Eval,
+    /// The dataset could not be loaded.
+    Data,
+    NotFound,
+    Internal,
+}
+
+/// The `error` object of a failed request or run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorBody {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Index of the top-level form that failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bar: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<SpanInfo>,
+}
+
+impl ErrorBody {
+    fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into(), form: None, bar: None, span: None }
+    }
+
+    fn parse(e: &ParseError) -> Self {
+        Self { span: Some(e.span().into()), ..Self::new(ErrorKind::Parse, e.to_string()) }
+    }
+
+    /// Point an evaluation error at the form it came from.
+    fn eval(e: EvalError, program: &[Spanned<Expr>]) -> Self {
+        Self {
+            form: Some(e.form),
+            bar: e.bar,
+            span: program.get(e.form).map(|form| (&form.span).into()),
+            ..Self::new(ErrorKind::Eval, e.message)
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self.kind {
+            ErrorKind::Request => StatusCode::BAD_REQUEST,
+            ErrorKind::Parse | ErrorKind::Eval | ErrorKind::Data => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// An error that becomes a JSON `{"error": {...}}` response.
+pub struct ApiError(ErrorBody);
+
+impl From<ErrorBody> for ApiError {
+    fn from(body: ErrorBody) -> Self {
+        Self(body)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0.status(), Json(json!({ "error": self.0 }))).into_response()
+    }
+}
+
+fn bad_request(msg: impl Into<String>) -> ApiError {
+    ErrorBody::new(ErrorKind::Request, msg).into()
+}
+
+/// State of a run started by `POST /backtest`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum RunStatus {
+    Running,
+    Succeeded { result: Value },
+    Failed { error: ErrorBody },
+}
+
+pub fn router(config: &ServerConfig) -> Router {
+    let state = Arc::new(AppState {
+        data_dir: config.data_dir.clone(),
+        workers: Arc::new(Semaphore::new(config.workers.max(1))),
+        runs: Mutex::new(Runs::default()),
+    });
+    Router::new()
+        .route("/", get(index))
+        .route("/api/datasets", get(datasets))
+        .route("/api/run", post(run))
+        .route("/eval", post(eval))
+        .route("/backtest", post(start_backtest))
+        .route("/runs/{id}", get(get_run).delete(delete_run))
+        .route("/stream", get(stream))
+        .with_state(state)
+}
+
+pub async fn serve(config: ServerConfig) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(config.addr).await?;
+    tracing::info!("serving on http://{}", listener.local_addr()?);
+    axum::serve(listener, router(&config)).await
+}
+
+/// Run `f` on the blocking pool once a worker slot is free.
+async fn on_worker<T: Send + 'static>(
+    state: &AppState,
+    f: impl FnOnce() -> Result<T, ErrorBody> + Send + 'static,
+) -> Result<T, ErrorBody> {
+    let _permit = state.workers.clone().acquire_owned().await.expect("worker semaphore is never closed");
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ErrorBody::new(ErrorKind::Internal, e.to_string()))?
+}
+
+async fn index() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+async fn datasets(State(state): State<Arc<AppState>>) -> Result<Json<Vec<String>>, ApiError> {
+    let entries = std::fs::read_dir(&state.data_dir).map_err(|e| {
+        ErrorBody::new(ErrorKind::Internal, format!("{}: {}", state.data_dir.display(), e))
+    })?;
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".csv"))
+        .collect();
+    names.sort();
+    Ok(Json(names))
+}
+
+/// Resolve a dataset name to a file inside the data directory, refusing anything that could escape it.
+fn dataset_path(data_dir: &Path, name: &str) -> Result<PathBuf, ApiError> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') || !name.ends_with(".csv") {
+        return Err(bad_request(format!("invalid dataset `{}`", name)));
+    }
+    Ok(data_dir.join(name))
+}
+
+fn parse(source: &str) -> Result<Vec<Spanned<Expr>>, ApiError> {
+    parse_program(source).map_err(|e| ErrorBody::parse(&e).into())
+}
+
+/// Input overrides arrive as JSON scalars.
+fn input_expr(name: &str, value: &Value) -> Result<Expr, ApiError> {
+    match value {
+        Value::Bool(b) => Ok(Expr::Boolean(*b)),
+        Value::Number(n) => Ok(n.as_i64().map_or_else(|| Expr::Float(n.as_f64().unwrap_or(f64::NAN)), Expr::Integer)),
+        Value::String(s) => Ok(Expr::String(s.clone())),
+        _ => Err(bad_request(format!("input `{}` must be a number, boolean or string", name))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EvalRequest {
+    pub source: String,
+}
+
+/// `POST /eval`: evaluate a script once, outside any backtest, and return each form's value.
+async fn eval(State(state): State<Arc<AppState>>, Json(request): Json<EvalRequest>) -> Result<Json<Value>, ApiError> {
+    let program = parse(&request.source)?;
+    let values = on_worker(&state, move || {
+        let exprs: Vec<Expr> = program.iter().map(|form| form.value.clone()).collect();
+        Interpreter::new().eval_program(&exprs).map_err(|e| ErrorBody::eval(e, &program))
+    })
+    .await?;
+    Ok(Json(json!({ "values": values })))
+}
+
+/// Account settings accepted wherever a script trades.
+#[derive(Debug, Default, Deserialize)]
+pub struct BrokerSettings {
+    #[serde(default)]
+    pub cash: Option<f64>,
+    #[serde(default)]
+    pub commission: Option<String>,
+    #[serde(default)]
+    pub slippage: Option<String>,
+    #[serde(default)]
+    pub fill: Option<String>,
+    /// A built-in exchange calendar like `nyse`
+    #[serde(default)]
+    pub exchange: Option<String>,
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    /// Trading hours like `09:30-16:00`
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Periods like `1h` to resample the bars into, read as `:data 2` onwards
+    #[serde(default)]
+    pub streams: Vec<String>,
+}
+
+impl BrokerSettings {
+    fn config(&self) -> Result<BrokerConfig, ApiError> {
+        let mut config = BrokerConfig::default();
+        if let Some(cash) = self.cash {
+            config.initial_cash = cash;
+        }
+        if let Some(commission) = &self.commission {
+            config.commission = commission.parse::<Commission>().map_err(bad_request)?;
+        }
+        if let Some(slippage) = &self.slippage {
+            config.slippage = slippage.parse::<Slippage>().map_err(bad_request)?;
+        }
+        if let Some(fill) = &self.fill {
+            config.fill_model = fill.parse::<FillModel>().map_err(bad_request)?;
+        }
+        if let Some(exchange) = &self.exchange {
+            config.calendar = Calendar::exchange(exchange).map_err(bad_request)?;
+        }
+        if let Some(time_zone) = &self.time_zone {
+            config.calendar.time_zone = time_zone.parse::<TimeZone>().map_err(bad_request)?;
+        }
+        if let Some(session) = &self.session {
+            config.calendar.session = session.parse::<Session>().map_err(bad_request)?;
+        }
+        for stream in &self.streams {
+            config.data_streams.push(stream.parse::<DataStream>().map_err(bad_request)?);
+        }
+        config.validate().map_err(bad_request)?;
+        Ok(config)
+    }
+}
+
+fn input_exprs(inputs: &IndexMap<String, Value>) -> Result<IndexMap<String, Expr>, ApiError> {
+    inputs.iter().map(|(name, value)| Ok((name.clone(), input_expr(name, value)?))).collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunRequest {
+    pub source: String,
+    /// Name of a CSV file in the data directory.
+    #[serde(default)]
+    pub dataset: Option<String>,
+    /// Inline CSV bars, instead of a dataset.
+    #[serde(default)]
+    pub csv: Option<String>,
+    #[serde(default)]
+    pub inputs: IndexMap<String, Value>,
+    #[serde(flatten)]
+    pub broker: BrokerSettings,
+}
+
+enum BarSource {
+    Dataset(String, PathBuf),
+    Csv(String),
+}
+
+/// A validated, parsed backtest waiting for a worker.
+struct Job {
+    program: Vec<Spanned<Expr>>,
+    bars: BarSource,
+    config: BrokerConfig,
+    inputs: IndexMap<String, Expr>,
+}
+
+impl RunRequest {
+    /// Everything that can be checked without touching the data.
+    fn into_job(self, data_dir: &Path) -> Result<Job, ApiError> {
+        let bars = match (&self.dataset, &self.csv) {
+            (Some(name), None) => BarSource::Dataset(name.clone(), dataset_path(data_dir, name)?),
+            (None, Some(csv)) => BarSource::Csv(csv.clone()),
+            _ => return Err(bad_request("exactly one of `dataset` and `csv` is required")),
+        };
+        Ok(Job {
+            program: parse(&self.source)?,
+            bars,
+            config: self.broker.config()?,
+            inputs: input_exprs(&self.inputs)?,
+        })
+    }
+}
+
+impl Job {
+    fn load_bars(&self) -> Result<Vec<Bar>, ErrorBody> {
+        match &self.bars {
+            BarSource::Dataset(name, path) => bars::load_csv(path).map_err(|e| format!("{}: {}", name, e)),
+            BarSource::Csv(text) => bars::parse_csv(text).map_err(|e| e.to_string()),
+        }
+        .map_err(|msg| ErrorBody::new(ErrorKind::Data, msg))
+    }
+
+    /// Backtest the script and bundle the report, trades and rendered plots.
+    fn run(self) -> Result<Value, ErrorBody> {
+        let bars = self.load_bars()?;
+        let exprs: Vec<Expr> = self.program.iter().map(|form| form.value.clone()).collect();
+        let result = backtest::run(&exprs, &bars, self.config, self.inputs)
+            .map_err(|e| ErrorBody::eval(e, &self.program))?;
+        let plots: IndexMap<&String, String> =
+            result.plots.charts.iter().map(|(name, chart)| (name, render_svg(chart))).collect();
+        Ok(json!({
+            "report": result.report,
+            "trades": result.trades,
+            "plots": plots,
+        }))
+    }
+}
+
+/// `POST /api/run`: the dashboard waits for its backtest.
+async fn run(State(state): State<Arc<AppState>>, Json(request): Json<RunRequest>) -> Result<Json<Value>, ApiError> {
+    let job = request.into_job(&state.data_dir)?;
+    Ok(Json(on_worker(&state, move || job.run()).await?))
+}
+
+/// `POST /backtest`: queue a backtest and return its run id straight away.
+async fn start_backtest(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RunRequest>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let job = request.into_job(&state.data_dir)?;
+    let id = Uuid::now_v7();
+    state.runs.lock().unwrap().statuses.insert(id, RunStatus::Running);
+
+    let worker_state = state.clone();
+    tokio::spawn(async move {
+        let status = match on_worker(&worker_state, move || job.run()).await {
+            Ok(result) => RunStatus::Succeeded { result },
+            Err(error) => RunStatus::Failed { error },
+        };
+        worker_state.runs.lock().unwrap().finish(id, status);
+    });
+    Ok((StatusCode::ACCEPTED, Json(json!({ "id": id.to_string(), "status": "running" }))))
+}
+
+fn run_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|e| bad_request(format!("invalid run id `{}`: {}", id, e)))
+}
+
+/// `GET /runs/{id}`: the run's status, with its result or error once finished.
+async fn get_run(State(state): State<Arc<AppState>>, UrlPath(id): UrlPath<String>) -> Result<Json<Value>, ApiError> {
+    let uuid = run_id(&id)?;
+    let status = state.runs.lock().unwrap().statuses.get(&uuid).cloned();
+    let Some(status) = status else {
+        return Err(ErrorBody::new(ErrorKind::NotFound, format!("no run `{}`", id)).into());
+    };
+    let mut body = serde_json::to_value(status).map_err(|e| ErrorBody::new(ErrorKind::Internal, e.to_string()))?;
+    body["id"] = json!(id);
+    Ok(Json(body))
+}
+
+/// `DELETE /runs/{id}`: forget a run. A run deleted while running finishes unrecorded.
+async fn delete_run(State(state): State<Arc<AppState>>, UrlPath(id): UrlPath<String>) -> Result<StatusCode, ApiError> {
+    match state.runs.lock().unwrap().remove(&run_id(&id)?) {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err(ErrorBody::new(ErrorKind::NotFound, format!("no run `{}`", id)).into()),
+    }
+}
+
+/// First message of a `/stream` session.
+#[derive(Debug, Deserialize)]
+pub struct SessionRequest {
+    pub source: String,
+    #[serde(default)]
+    pub inputs: IndexMap<String, Value>,
+    #[serde(flatten)]
+    pub broker: BrokerSettings,
+}
+
+/// A bar pushed by a `/stream` client.
+#[derive(Debug, Deserialize)]
+pub struct StreamBar {
+    pub timestamp: String,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    #[serde(default)]
+    pub volume: f64,
+}
+
+/// A script kept running across the bars of one `/stream` connection.
+struct LiveSession {
+    program: Vec<Spanned<Expr>>,
+    exprs: Vec<Expr>,
+    engine: BarEngine,
+    next_bar: usize,
+    fills_seen: usize,
+}
+
+impl LiveSession {
+    fn start(text: &str) -> Result<Self, ApiError> {
+        let request: SessionRequest = serde_json::from_str(text).map_err(|e| bad_request(e.to_string()))?;
+        let program = parse(&request.source)?;
+        let engine = BarEngine::new(request.broker.config()?).with_inputs(input_exprs(&request.inputs)?);
+        Ok(Self {
+            exprs: program.iter().map(|form| form.value.clone()).collect(),
+            program,
+            engine,
+            next_bar: 0,
+            fills_seen: 0,
+        })
+    }
+
+    /// Evaluate one pushed bar and describe what it produced.
+    fn on_bar(&mut self, text: &str) -> Value {
+        let bar = match serde_json::from_str::<StreamBar>(text) {
+            Ok(bar) => match parse_timestamp(&bar.timestamp) {
+                Some(timestamp) => Bar::new(timestamp, bar.open, bar.high, bar.low, bar.close, bar.volume),
+                None => return json!({ "error": ErrorBody::new(ErrorKind::Request, format!("invalid timestamp `{}`", bar.timestamp)) }),
+            },
+            Err(e) => return json!({ "error": ErrorBody::new(ErrorKind::Request, e.to_string()) }),
+        };
+        let index = self.next_bar;
+        self.next_bar += 1;
+
+        let step = self.engine.step(&self.exprs, index, &bar);
+        let fills = &self.engine.broker.fills()[self.fills_seen..];
+        let mut reply = json!({
+            "bar": index,
+            "timestamp": bar.timestamp,
+            "fills": fills,
+            "position": self.engine.broker.position(),
+            "equity": self.engine.broker.equity(bar.close),
+        });
+        self.fills_seen += fills.len();
+        match step {
+            Ok(output) => {
+                reply["values"] = json!(output.values);
+                reply["orders"] = json!(output.orders);
+                reply["plots"] = json!(plot_points(&self.engine.interpreter.plots, index));
+            }
+            Err(e) => {
+                // Orders from the forms before the failing one are dropped with the bar.
+                self.engine.interpreter.orders.clear();
+                reply["error"] = json!(ErrorBody::eval(e, &self.program));
+            }
+        }
+        reply
+    }
+}
+
+/// What the program plotted at bar `index`.
+fn plot_points(plots: &Plots, index: usize) -> Vec<Value> {
+    let mut points = Vec::new();
+    for (name, chart) in &plots.charts {
+        for series in &chart.series {
+            match &series.data {
+                SeriesData::Line(data) | SeriesData::Histogram(data) => points.extend(
+                    data.iter()
+                        .filter(|p| p.index == index)
+                        .map(|p| json!({ "chart": name, "series": series.name, "value": p.value })),
+                ),
+                SeriesData::Candles(data) => points.extend(data.iter().filter(|c| c.index == index).map(|c| {
+                    json!({
+                        "chart": name,
+                        "series": series.name,
+                        "open": c.open,
+                        "high": c.high,
+                        "low": c.low,
+                        "close": c.close,
+                    })
+                })),
+            }
+        }
+    }
+    points
+}
+
+/// `GET /stream`: upgrade to a WebSocket running a live session.
+///
+/// The first text message is a [`SessionRequest`], answered with `{"ready": true}`;
+/// every later one is a [`StreamBar`], answered with that bar's values, orders,
+/// fills and plot points. Messages are read one at a time, so a client pushing
+/// bars faster than they evaluate is slowed down by TCP rather than buffered.
+async fn stream(State(state): State<Arc<AppState>>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| live_session(state, socket))
+}
+
+async fn live_session(state: Arc<AppState>, mut socket: WebSocket) {
+    let mut session: Option<LiveSession> = None;
+    while let Some(Ok(message)) = socket.recv().await {
+        let text = match message {
+            Message::Text(text) => text.to_string(),
+            // Pings are answered, and a close echoed, by the socket itself on the next read
+            Message::Binary(_) | Message::Ping(_) | Message::Pong(_) | Message::Close(_) => continue,
+        };
+        let (reply, failed) = match session.take() {
+            None => match LiveSession::start(&text) {
+                Ok(live) => {
+                    session = Some(live);
+                    (json!({ "ready": true }), false)
+                }
+                Err(e) => (json!({ "error": e.0 }), false),
+            },
+            Some(mut live) => match on_worker(&state, move || Ok((live.on_bar(&text), live))).await {
+                Ok((reply, live)) => {
+                    session = Some(live);
+                    (reply, false)
+                }
+                Err(error) => (json!({ "error": error }), true),
+            },
+        };
+        if socket.send(Message::Text(reply.to_string().into())).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::Message as Frame;
+
+    /// Bind the router to an ephemeral port and return its address.
+    pub(crate) async fn spawn(router: Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        addr
+    }
+
+    /// Minimal HTTP/1.1 client: returns the status code and body.
+    pub(crate) async fn request(addr: SocketAddr, method: &str, path: &str, body: Option<&Value>) -> (u16, String) {
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method,
+            path,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, body.to_string())
+    }
+
+    pub(crate) fn data_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stonkscheme-server-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("bars.csv"),
+            "date,open,high,low,close\n2024-01-01,10,11,9,10.5\n2024-01-02,10.5,12,10,11.5\n2024-01-03,11.5,12,10.5,11\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    pub(crate) fn config() -> ServerConfig {
+        ServerConfig { addr: "127.0.0.1:0".parse().unwrap(), data_dir: data_dir(), workers: 2 }
+    }
+
+    #[tokio::test]
+    async fn serves_dashboard_and_runs_scripts() {
+        let addr = spawn(router(&config())).await;
+
+        let (status, body) = request(addr, "GET", "/", None).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("<title>stonkscheme</title>"));
+
+        let (status, body) = request(addr, "GET", "/api/datasets", None).await;
+        assert_eq!(status, 200);
+        assert!(body.contains("bars.csv"));
+
+        let run = json!({ "source": "(if (> close open) (buy 1)) (plot close :title \"Close\")", "dataset": "bars.csv" });
+        let (status, body) = request(addr, "POST", "/api/run", Some(&run)).await;
+        assert_eq!(status, 200, "{}", body);
+        let value: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value["trades"], json!([]));
+        assert!(value["plots"]["Close"].as_str().unwrap().starts_with("<svg"));
+
+        let escape = json!({ "source": "1", "dataset": "../secret.csv" });
+        let (status, _) = request(addr, "POST", "/api/run", Some(&escape)).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn eval_reports_values_and_error_spans() {
+        let addr = spawn(router(&config())).await;
+
+        let (status, body) = request(addr, "POST", "/eval", Some(&json!({ "source": "(set x 2)\n(+ x 3)" }))).await;
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "values": [null, 5] }));
+
+        let (status, body) = request(addr, "POST", "/eval", Some(&json!({ "source": "1\n  (/ 1 0)" }))).await;
+        assert_eq!(status, 422);
+        let error = &serde_json::from_str::<Value>(&body).unwrap()["error"];
+        assert_eq!(error["kind"], "eval");
+        assert_eq!(error["form"], 1);
+        assert_eq!(error["span"], json!({ "start": 4, "end": 11, "line": 2, "column": 3 }));
+
+        let (status, body) = request(addr, "POST", "/eval", Some(&json!({ "source": "(+ 1 \"2)" }))).await;
+        assert_eq!(status, 422);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["error"]["kind"], "parse");
+    }
+
+    #[tokio::test]
+    async fn backtest_runs_are_queued_and_polled() {
+        let addr = spawn(router(&config())).await;
+        let run = json!({
+            "source": "(inputs (size 1)) (if (> close open) (buy size) (sell size))",
+            "csv": "2024-01-01,10,11,9,10.5\n2024-01-02,10.5,12,10,11.5\n2024-01-03,11.5,12,10.5,11\n2024-01-04,11,11.5,10,10.2\n",
+            "inputs": { "size": 5 },
+        });
+        let (status, body) = request(addr, "POST", "/backtest", Some(&run)).await;
+        assert_eq!(status, 202, "{}", body);
+        let id = serde_json::from_str::<Value>(&body).unwrap()["id"].as_str().unwrap().to_string();
+        assert_eq!(Uuid::parse_str(&id).unwrap().get_version_num(), 7);
+
+        let run = loop {
+            let (status, body) = request(addr, "GET", &format!("/runs/{}", id), None).await;
+            assert_eq!(status, 200);
+            let run: Value = serde_json::from_str(&body).unwrap();
+            if run["status"] != "running" {
+                break run;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(run["status"], "succeeded", "{}", run);
+        assert_eq!(run["result"]["trades"][0]["quantity"], 5);
+
+        let failing = json!({ "source": "(buy close)", "dataset": "bars.csv" });
+        let (_, body) = request(addr, "POST", "/backtest", Some(&failing)).await;
+        let id = serde_json::from_str::<Value>(&body).unwrap()["id"].as_str().unwrap().to_string();
+        let run = loop {
+            let (_, body) = request(addr, "GET", &format!("/runs/{}", id), None).await;
+            let run: Value = serde_json::from_str(&body).unwrap();
+            if run["status"] != "running" {
+                break run;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(run["status"], "failed");
+        assert_eq!(run["error"]["bar"], 0);
+
+        let (status, _) = request(addr, "DELETE", &format!("/runs/{}", id), None).await;
+        assert_eq!(status, 204);
+        let (status, _) = request(addr, "GET", &format!("/runs/{}", id), None).await;
+        assert_eq!(status, 404);
+        let (status, _) = request(addr, "GET", &format!("/runs/{}", Uuid::now_v7()), None).await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn streams_bars_through_a_live_session() {
+        let addr = spawn(router(&config())).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/stream", addr)).await.unwrap();
+        let mut exchange = async |message: Value| {
+            socket.send(Frame::text(message.to_string())).await.unwrap();
+            match socket.next().await.unwrap().unwrap() {
+                Frame::Text(text) => serde_json::from_str::<Value>(&text).unwrap(),
+                other => panic!("unexpected {:?}", other),
+            }
+        };
+
+        let source = "(inputs (size 1)) (if (> close open) (buy size)) (plot (- close open) :title \"Change\")";
+        let ready = exchange(json!({ "source": source, "inputs": { "size": 3 } })).await;
+        assert_eq!(ready, json!({ "ready": true }));
+
+        let bar = |day: u32, open: f64, close: f64| {
+            json!({ "timestamp": format!("2024-01-0{}", day), "open": open, "high": 12, "low": 9, "close": close })
+        };
+        let first = exchange(bar(1, 10.0, 11.0)).await;
+        assert_eq!(first["bar"], 0);
+        assert_eq!(first["orders"], json!([{ "side": "Buy", "quantity": 3, "kind": "Market" }]));
+        assert_eq!(first["plots"], json!([{ "chart": "Change", "series": "Change", "value": 1.0 }]));
+
+        let second = exchange(bar(2, 11.5, 11.0)).await;
+        assert_eq!(second["fills"][0]["price"], 11.5);
+        assert_eq!(second["position"], 3);
+        assert_eq!(second["orders"], json!([]));
+
+        let bad = exchange(json!({ "timestamp": "yesterday", "open": 1, "high": 1, "low": 1, "close": 1 })).await;
+        assert_eq!(bad["error"]["kind"], "request");
+        let third = exchange(bar(3, 11.0, 10.5)).await;
+        assert_eq!(third["bar"], 2);
+
+        socket.close(None).await.unwrap();
+        assert!(matches!(socket.next().await, Some(Ok(Frame::Close(_)))));
+    }
+}
@@ -0,0 +1,130 @@
+//! Syntax tree with a source span on every node.
+//!
+//! [`Expr`] only remembers where each top-level form came from. Tools that
+//! need to point at something nested, like `stonkscheme parse` or error
+//! reports, work on [`Node`]s instead and lower them with [`Node::to_expr`].
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::fmt::Write;
+
+use crate::ast::Expr;
+use crate::code::{CodeSpan, SpanInfo, Spanned};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    /// A literal, a symbol or `()`
+    Atom(Expr),
+    /// `(head args...)`, never empty
+    Combination(Vec<Node>),
+    /// `[items...]`
+    Array(Vec<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: CodeSpan,
+}
+
+impl Node {
+    pub fn type_name(&self) -> &'static str {
+        match &self.kind {
+            NodeKind::Atom(expr) => expr.type_name(),
+            NodeKind::Combination(_) => "Combination",
+            NodeKind::Array(_) => "Array",
+        }
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match &self.kind {
+            NodeKind::Atom(_) => &[],
+            NodeKind::Combination(items) | NodeKind::Array(items) => items,
+        }
+    }
+
+    pub fn to_expr(&self) -> Expr {
+        match &self.kind {
+            NodeKind::Atom(expr) => expr.clone(),
+            NodeKind::Combination(items) => Expr::Combination(
+                Box::new(items[0].to_expr()),
+                items[1..].iter().map(Node::to_expr).collect(),
+            ),
+            NodeKind::Array(items) => Expr::Array(items.iter().map(Node::to_expr).collect()),
+        }
+    }
+
+    pub fn to_spanned(&self) -> Spanned<Expr> {
+        Spanned { value: self.to_expr(), span: self.span.clone() }
+    }
+}
+
+/// `{"type", "value"?, "span", "children"?}`; atoms carry a value and lists carry children.
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("type", self.type_name())?;
+        match &self.kind {
+            NodeKind::Atom(expr) => map.serialize_entry("value", expr)?,
+            NodeKind::Combination(items) | NodeKind::Array(items) => map.serialize_entry("children", items)?,
+        }
+        map.serialize_entry("span", &SpanInfo::from(&self.span))?;
+        map.end()
+    }
+}
+
+/// An indented outline of `nodes`, one line per node with its position:
+///
+/// ```text
+/// Combination 1:1 (0..9)
+/// ├── Symbol set 1:2 (1..4)
+/// ...
+/// ```
+pub fn format_tree(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        write_node(&mut out, node, "", "");
+    }
+    out
+}
+
+fn write_node(out: &mut String, node: &Node, lead: &str, indent: &str) {
+    let (line, column) = node.span.code.line_col(node.span.start);
+    let _ = write!(out, "{}{}", lead, node.type_name());
+    if let NodeKind::Atom(expr) = &node.kind {
+        let _ = write!(out, " {}", expr);
+    }
+    let _ = writeln!(out, " {}:{} ({}..{})", line, column, node.span.start, node.span.end);
+
+    let children = node.children();
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let lead = format!("{}{}", indent, if last { "└── " } else { "├── " });
+        let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+        write_node(out, child, &lead, &indent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_syntax;
+
+    #[test]
+    fn spans_every_node() {
+        let nodes = parse_syntax("(set x\n  [1 \"a\"])").expect("parse");
+        let expected = [
+            "Combination 1:1 (0..17)",
+            "├── Symbol set 1:2 (1..4)",
+            "├── Symbol x 1:6 (5..6)",
+            "└── Array 2:3 (9..16)",
+            "    ├── Integer 1 2:4 (10..11)",
+            "    └── String \"a\" 2:6 (12..15)",
+        ];
+        assert_eq!(super::format_tree(&nodes), expected.join("\n") + "\n");
+
+        let json = serde_json::to_value(&nodes[0]).unwrap();
+        assert_eq!(json["children"][2]["children"][1]["value"], "a");
+        assert_eq!(json["children"][2]["span"], serde_json::json!({"start": 9, "end": 16, "line": 2, "column": 3}));
+        assert_eq!(nodes[0].to_expr().to_string(), "(set x [1 \"a\"])");
+    }
+}
@@ -0,0 +1,631 @@
+//! Gradual types for scripts, checked without running them.
+//!
+//! Annotations are written where `inputs` already allows them, as in
+//! `(period Duration 10d)` or `(prices (Array Price))`. Everything else is
+//! inferred from literals, bar fields, builtins and the first `set` of each
+//! variable. A value whose type cannot be known, like an unbound symbol or the
+//! result of `car`, is [`Type::Any`] and fits anywhere, so unannotated scripts
+//! only fail on mistakes that are certain.
+
+use indexmap::IndexMap;
+use std::fmt;
+
+use crate::ast::{Expr, Unit};
+use crate::code::CodeSpan;
+use crate::syntax::{Node, NodeKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Unknown; compatible with every type
+    Any,
+    Nil,
+    Bool,
+    Integer,
+    Float,
+    /// An exact number, like `101.25m`
+    Decimal,
+    /// What one unit of the instrument costs
+    Price,
+    /// A number of units, as bought or sold
+    Quantity,
+    /// An amount of cash, like a price times a quantity
+    Money,
+    String,
+    Duration,
+    Timestamp,
+    Array(Box<Type>),
+    /// One value per bar
+    Series(Box<Type>),
+    /// `(Fn params... result)`
+    Function(Vec<Type>, Box<Type>),
+    Tuple(Vec<Type>),
+}
+
+impl From<Unit> for Type {
+    fn from(unit: Unit) -> Type {
+        match unit {
+            Unit::Price => Type::Price,
+            Unit::Quantity => Type::Quantity,
+            Unit::Money => Type::Money,
+        }
+    }
+}
+
+/// Names usable in annotations, for error help
+const TYPE_NAMES: &str = "Any, Nil, Bool, Integer, Float, Decimal, Price, Quantity, Money, String, Duration, Timestamp, \
+                          (Array T), (Series T), (Tuple T...) and (Fn T... R)";
+
+impl Type {
+    /// Read an annotation like `Price` or `(Array (Tuple Timestamp Price))`,
+    /// or return the part of it that is not a type.
+    pub fn from_node(node: &Node) -> Result<Type, &Node> {
+        match &node.kind {
+            NodeKind::Atom(Expr::Symbol(name)) => Ok(match name.0.as_str() {
+                "Any" => Type::Any,
+                "Nil" => Type::Nil,
+                "Bool" | "Boolean" => Type::Bool,
+                "Integer" => Type::Integer,
+                "Float" => Type::Float,
+                "Decimal" => Type::Decimal,
+                "Price" => Type::Price,
+                "Quantity" => Type::Quantity,
+                "Money" => Type::Money,
+                "String" => Type::String,
+                "Duration" => Type::Duration,
+                "Timestamp" => Type::Timestamp,
+                _ => return Err(node),
+            }),
+            NodeKind::Combination(items) => {
+                let NodeKind::Atom(Expr::Symbol(head)) = &items[0].kind else {
+                    return Err(node);
+                };
+                let args = items[1..].iter().map(Type::from_node).collect::<Result<Vec<_>, _>>()?;
+                match (head.0.as_str(), args.as_slice()) {
+                    ("Array", [item]) => Ok(Type::Array(Box::new(item.clone()))),
+                    ("Series", [item]) => Ok(Type::Series(Box::new(item.clone()))),
+                    ("Tuple", _) => Ok(Type::Tuple(args)),
+                    ("Fn", [params @ .., result]) => Ok(Type::Function(params.to_vec(), Box::new(result.clone()))),
+                    _ => Err(node),
+                }
+            }
+            _ => Err(node),
+        }
+    }
+
+    /// The type of a literal or `()`, or `None` for symbols and lists.
+    pub fn of_literal(expr: &Expr) -> Option<Type> {
+        Some(match expr {
+            Expr::Nil => Type::Nil,
+            Expr::Boolean(_) => Type::Bool,
+            Expr::Integer(_) => Type::Integer,
+            Expr::Float(_) => Type::Float,
+            Expr::Decimal(_) => Type::Decimal,
+            Expr::String(_) => Type::String,
+            Expr::Duration(_) => Type::Duration,
+            Expr::Timestamp(_) => Type::Timestamp,
+            Expr::Amount(unit, _) => Type::from(*unit),
+            _ => return None,
+        })
+    }
+
+    /// The unit of `Price`, `Quantity` and `Money`, which numbers carry at run time
+    pub fn unit(&self) -> Option<Unit> {
+        match self {
+            Type::Price => Some(Unit::Price),
+            Type::Quantity => Some(Unit::Quantity),
+            Type::Money => Some(Unit::Money),
+            _ => None,
+        }
+    }
+
+    /// Whether a value of type `actual` can be used where `self` is expected.
+    /// Integers widen to floats and decimals, any number can be a price or
+    /// money and any integer a quantity.
+    pub fn accepts(&self, actual: &Type) -> bool {
+        match (self, actual) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Float | Type::Decimal, Type::Integer) => true,
+            (Type::Price | Type::Money, Type::Integer | Type::Float | Type::Decimal) => true,
+            (Type::Quantity, Type::Integer) => true,
+            (Type::Array(expected), Type::Array(actual)) | (Type::Series(expected), Type::Series(actual)) => {
+                expected.accepts(actual)
+            }
+            (Type::Tuple(expected), Type::Tuple(actual)) => {
+                expected.len() == actual.len() && expected.iter().zip(actual).all(|(e, a)| e.accepts(a))
+            }
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Any | Type::Integer | Type::Float | Type::Decimal | Type::Price | Type::Quantity | Type::Money)
+    }
+
+    /// The narrowest type both `self` and `other` fit, or `Any`.
+    pub fn join(&self, other: &Type) -> Type {
+        if self.accepts(other) && !matches!(self, Type::Any) {
+            self.clone()
+        } else if other.accepts(self) && !matches!(other, Type::Any) {
+            other.clone()
+        } else {
+            Type::Any
+        }
+    }
+}
+
+/// The annotation syntax, so a type prints the way it is written.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, head: &str, items: &[&Type]) -> fmt::Result {
+            write!(f, "({}", head)?;
+            for item in items {
+                write!(f, " {}", item)?;
+            }
+            f.write_str(")")
+        }
+        match self {
+            Type::Any => f.write_str("Any"),
+            Type::Nil => f.write_str("Nil"),
+            Type::Bool => f.write_str("Bool"),
+            Type::Integer => f.write_str("Integer"),
+            Type::Float => f.write_str("Float"),
+            Type::Decimal => f.write_str("Decimal"),
+            Type::Price => f.write_str("Price"),
+            Type::Quantity => f.write_str("Quantity"),
+            Type::Money => f.write_str("Money"),
+            Type::String => f.write_str("String"),
+            Type::Duration => f.write_str("Duration"),
+            Type::Timestamp => f.write_str("Timestamp"),
+            Type::Array(item) => list(f, "Array", &[item]),
+            Type::Series(item) => list(f, "Series", &[item]),
+            Type::Tuple(items) => list(f, "Tuple", &items.iter().collect::<Vec<_>>()),
+            Type::Function(params, result) => {
+                list(f, "Fn", &params.iter().chain([result.as_ref()]).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+/// A mismatch found by [`check_types`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    pub span: CodeSpan,
+    pub label: String,
+    /// Where the expected type came from
+    pub related: Option<(CodeSpan, String)>,
+    pub help: Option<String>,
+}
+
+/// The type of each bar field bound by the engine
+pub fn bar_field_type(name: &str) -> Option<Type> {
+    match name {
+        "open" | "high" | "low" | "close" => Some(Type::Price),
+        "volume" => Some(Type::Float),
+        "vwap" => Some(Type::Price),
+        "time" => Some(Type::Timestamp),
+        "bar-index" | "Date" | "Time" => Some(Type::Integer),
+        _ => None,
+    }
+}
+
+/// The type of a builtin procedure with fixed arguments
+pub fn builtin_type(name: &str) -> Option<Type> {
+    let function = |params: &[Type], result: Type| Type::Function(params.to_vec(), Box::new(result));
+    Some(match name {
+        "not" => function(&[Type::Bool], Type::Bool),
+        "write" | "display" => function(&[Type::Any], Type::Nil),
+        "newline" => function(&[], Type::Nil),
+        "car" | "cdr" | "cons" => function(&[Type::Any], Type::Any),
+        "plot" | "plot-overlay" => function(&[Type::Any], Type::Nil),
+        "plot-candles" => function(&[Type::Array(Box::new(Type::Price))], Type::Nil),
+        "buy" | "sell" => function(&[Type::Quantity], Type::Nil),
+        "buy-limit" | "sell-limit" | "buy-stop" | "sell-stop" => function(&[Type::Quantity, Type::Price], Type::Nil),
+        "decimal" => function(&[Type::Any], Type::Decimal),
+        "float" => function(&[Type::Any], Type::Float),
+        "to-exchange-time" => function(&[Type::Timestamp], Type::Timestamp),
+        "DayOfWeek" => function(&[Type::Any], Type::Integer),
+        "position" => function(&[], Type::Quantity),
+        "entry-price" => function(&[], Type::Price),
+        "open-position-profit" | "account-equity" | "margin-used" => function(&[], Type::Money),
+        "set-stop-loss" | "set-profit-target" | "set-breakeven" | "set-trailing-stop" => {
+            function(&[Type::Money], Type::Nil)
+        }
+        "set-percent-trailing" => function(&[Type::Money, Type::Float], Type::Nil),
+        _ => return None,
+    })
+}
+
+/// Every type mismatch in `nodes`, in the order they are found.
+pub fn check_types(nodes: &[Node]) -> Vec<TypeError> {
+    let mut checker = Checker::default();
+    for node in nodes {
+        checker.declare_inputs(node);
+    }
+    for node in nodes {
+        checker.infer(node);
+    }
+    checker.errors
+}
+
+/// A name's type and the span that fixed it
+struct Binding {
+    ty: Type,
+    span: CodeSpan,
+}
+
+#[derive(Default)]
+struct Checker {
+    inputs: IndexMap<String, Binding>,
+    variables: IndexMap<String, Binding>,
+    errors: Vec<TypeError>,
+}
+
+impl Checker {
+    /// Record input types up front, since any form may read an input.
+    fn declare_inputs(&mut self, node: &Node) {
+        if let Some(("inputs", decls)) = head(node) {
+            for decl in decls {
+                self.declare_input(decl);
+            }
+        }
+        for child in node.children() {
+            self.declare_inputs(child);
+        }
+    }
+
+    fn declare_input(&mut self, decl: &Node) {
+        let NodeKind::Combination(parts) = &decl.kind else {
+            return;
+        };
+        let NodeKind::Atom(Expr::Symbol(name)) = &parts[0].kind else {
+            return;
+        };
+        let (annotation, default) = match &parts[1..] {
+            [only] if is_annotation(only) => (Some(only), None),
+            [default] => (None, Some(default)),
+            [annotation, default] => (Some(annotation), Some(default)),
+            _ => return,
+        };
+        let declared = match annotation.map(Type::from_node) {
+            Some(Ok(ty)) => Some((ty, annotation.expect("annotated").span.clone())),
+            Some(Err(unknown)) => {
+                self.errors.push(TypeError {
+                    message: format!("unknown type `{}`", unknown.to_expr()),
+                    span: unknown.span.clone(),
+                    label: "not a type".to_string(),
+                    related: None,
+                    help: Some(format!("types are {}", TYPE_NAMES)),
+                });
+                None
+            }
+            None => None,
+        };
+        let default_type = default.map(|default| (self.infer(default), default));
+        let ty = match (declared, default_type) {
+            (Some((ty, span)), Some((actual, default))) => {
+                if !ty.accepts(&actual) {
+                    self.errors.push(TypeError {
+                        message: format!("input `{}` is declared {} but defaults to {}", name.0, ty, actual),
+                        span: default.span.clone(),
+                        label: format!("this is {}", article(&actual)),
+                        related: Some((span, "declared here".to_string())),
+                        help: None,
+                    });
+                }
+                ty
+            }
+            (Some((ty, _)), None) => ty,
+            (None, Some((actual, _))) => actual,
+            (None, None) => Type::Any,
+        };
+        if !self.inputs.contains_key(&name.0) {
+            self.inputs.insert(name.0.clone(), Binding { ty, span: parts[0].span.clone() });
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        if let Some(binding) = self.inputs.get(name).or(self.variables.get(name)) {
+            return binding.ty.clone();
+        }
+        bar_field_type(name).unwrap_or(Type::Any)
+    }
+
+    fn infer(&mut self, node: &Node) -> Type {
+        match &node.kind {
+            NodeKind::Atom(Expr::Symbol(name)) => self.lookup(name),
+            NodeKind::Atom(expr) => Type::of_literal(expr).unwrap_or(Type::Any),
+            NodeKind::Array(items) => {
+                let types: Vec<Type> = items.iter().map(|item| self.infer(item)).collect();
+                let item = types.iter().skip(1).fold(types.first().cloned().unwrap_or(Type::Any), |a, b| a.join(b));
+                Type::Array(Box::new(item))
+            }
+            NodeKind::Combination(items) => match head(node) {
+                Some((name, args)) => self.call(name, args),
+                None => {
+                    items.iter().for_each(|item| {
+                        self.infer(item);
+                    });
+                    Type::Any
+                }
+            },
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Node]) -> Type {
+        match (name, args) {
+            ("inputs", _) => Type::Nil,
+            ("set", [target, value, ..]) => {
+                let actual = self.infer(value);
+                if let NodeKind::Atom(Expr::Symbol(target)) = &target.kind {
+                    self.assign(&target.0, actual, value);
+                }
+                Type::Nil
+            }
+            ("get", [target]) => match &target.kind {
+                NodeKind::Atom(Expr::Symbol(target)) => self.lookup(target),
+                _ => Type::Any,
+            },
+            ("if", [condition, then, rest @ ..]) => {
+                let actual = self.infer(condition);
+                // Literal conditions are the linter's `constant-condition`
+                if !Type::Bool.accepts(&actual) && matches!(condition.kind, NodeKind::Combination(_)) {
+                    self.errors.push(TypeError {
+                        message: format!("`if` condition is {}, not Bool", actual),
+                        span: condition.span.clone(),
+                        label: format!("this is {}", article(&actual)),
+                        related: None,
+                        help: None,
+                    });
+                }
+                let then = self.infer(then);
+                let otherwise = rest.first().map_or(Type::Nil, |otherwise| self.infer(otherwise));
+                then.join(&otherwise)
+            }
+            ("begin", _) => args.iter().fold(Type::Nil, |_, arg| self.infer(arg)),
+            ("+" | "-" | "*" | "/", _) => self.arithmetic(name, args),
+            (field, _) if crate::interpreter::SERIES_FIELDS.contains(&field) => {
+                args.iter().for_each(|arg| {
+                    self.infer(arg);
+                });
+                bar_field_type(field).unwrap_or(Type::Any)
+            }
+            // Rounding keeps a price a price
+            ("round-to-tick", [value, tick]) => {
+                let ty = self.infer(value);
+                self.infer(tick);
+                if matches!(ty, Type::Price | Type::Money) { ty } else { Type::Decimal }
+            }
+            ("<" | ">" | "<=" | ">=" | "=", _) => {
+                self.compare(name, args);
+                Type::Bool
+            }
+            _ => {
+                let positional = args.iter().take_while(|arg| !is_keyword(arg)).count();
+                let types: Vec<Type> = args.iter().map(|arg| self.infer(arg)).collect();
+                match builtin_type(name) {
+                    Some(Type::Function(params, result)) if params.len() == positional => {
+                        for ((param, actual), arg) in params.iter().zip(&types).zip(args) {
+                            if !param.accepts(actual) {
+                                self.errors.push(TypeError {
+                                    message: format!("`{}` expects {} here, found {}", name, param, actual),
+                                    span: arg.span.clone(),
+                                    label: format!("this is {}", article(actual)),
+                                    related: None,
+                                    help: None,
+                                });
+                            }
+                        }
+                        *result
+                    }
+                    _ => Type::Any,
+                }
+            }
+        }
+    }
+
+    /// Fix a variable's type at its first `set`; later ones must agree.
+    fn assign(&mut self, name: &str, actual: Type, value: &Node) {
+        let (kind, binding) = match (self.inputs.get(name), self.variables.get(name)) {
+            (Some(binding), _) => ("input", binding),
+            (None, Some(binding)) => ("variable", binding),
+            (None, None) => {
+                self.variables.insert(name.to_string(), Binding { ty: actual, span: value.span.clone() });
+                return;
+            }
+        };
+        if !binding.ty.accepts(&actual) {
+            self.errors.push(TypeError {
+                message: format!("{} `{}` is {} but is set to {}", kind, name, binding.ty, actual),
+                span: value.span.clone(),
+                label: format!("this is {}", article(&actual)),
+                related: Some((binding.span.clone(), format!("{} here", article(&binding.ty)))),
+                help: None,
+            });
+        }
+    }
+
+    /// Comparisons need operands in the same unit, or plain numbers.
+    fn compare(&mut self, op: &str, args: &[Node]) {
+        let types: Vec<Type> = args.iter().map(|arg| self.infer(arg)).collect();
+        for (pair, arg) in types.windows(2).zip(&args[1..]) {
+            if !comparable(&pair[0], &pair[1]) {
+                self.mismatch(format!("`{}` cannot compare {} with {}", op, pair[0], pair[1]), arg, &pair[1]);
+            }
+        }
+    }
+
+    /// Fold the operands left to right through [`arithmetic_type`].
+    fn arithmetic(&mut self, op: &str, args: &[Node]) -> Type {
+        let types: Vec<Type> = args.iter().map(|arg| self.infer(arg)).collect();
+        let Some((first, rest)) = types.split_first() else {
+            return Type::Integer;
+        };
+        if rest.is_empty() {
+            // `(- x)` is `(- 0 x)` and `(/ x)` is `(/ 1 x)`
+            return match op {
+                "-" | "/" => self.operate(op, &Type::Integer, first, &args[0]),
+                _ => self.operate(op, first, &Type::Integer, &args[0]),
+            };
+        }
+        rest.iter().zip(&args[1..]).fold(first.clone(), |acc, (ty, arg)| self.operate(op, &acc, ty, arg))
+    }
+
+    fn operate(&mut self, op: &str, left: &Type, right: &Type, arg: &Node) -> Type {
+        arithmetic_type(op, left, right).unwrap_or_else(|| {
+            let message = match op {
+                "+" => format!("cannot add {} to {}", right, left),
+                "-" => format!("cannot subtract {} from {}", right, left),
+                "*" => format!("cannot multiply {} by {}", left, right),
+                _ => format!("cannot divide {} by {}", left, right),
+            };
+            self.mismatch(message, arg, right);
+            Type::Any
+        })
+    }
+
+    fn mismatch(&mut self, message: String, node: &Node, actual: &Type) {
+        self.errors.push(TypeError {
+            message,
+            span: node.span.clone(),
+            label: format!("this is {}", article(actual)),
+            related: None,
+            help: None,
+        });
+    }
+}
+
+/// The type of `left op right` for `+`, `-`, `*` and `/`, or `None` if the
+/// units do not combine. Plain numbers scale or offset any unit, and are
+/// decimal if either one is; a timestamp
+/// moves by a duration and two timestamps differ by one; a price times a
+/// quantity is money, and money divides back into either.
+pub fn arithmetic_type(op: &str, left: &Type, right: &Type) -> Option<Type> {
+    use Type::*;
+    let scalar = |ty: &Type| matches!(ty, Integer | Float | Decimal);
+    let amount = |ty: &Type| matches!(ty, Price | Quantity | Money);
+    Some(match (op, left, right) {
+        (_, Any, _) | (_, _, Any) => Any,
+        (_, Integer, Integer) if op != "/" => Integer,
+        (_, Decimal, n) | (_, n, Decimal) if scalar(n) => Decimal,
+        (_, a, b) if scalar(a) && scalar(b) => Float,
+        ("+", Timestamp, Duration) | ("+", Duration, Timestamp) | ("-", Timestamp, Duration) => Timestamp,
+        ("-", Timestamp, Timestamp) => Duration,
+        ("+" | "-", a, b) if a == b && (amount(a) || *a == Duration) => a.clone(),
+        ("+" | "-" | "*", unit, n) | ("+" | "-" | "*", n, unit) if amount(unit) && scalar(n) => unit.clone(),
+        ("*", Price, Quantity) | ("*", Quantity, Price) => Money,
+        ("*", Duration, Integer) | ("*", Integer, Duration) | ("/", Duration, Integer) => Duration,
+        ("/", unit, n) if amount(unit) && scalar(n) => unit.clone(),
+        ("/", a, b) if a == b && (amount(a) || *a == Duration) => Float,
+        ("/", Money, Price) => Quantity,
+        ("/", Money, Quantity) => Price,
+        _ => return None,
+    })
+}
+
+/// Numbers compare with numbers in the same unit or none; times with times.
+fn comparable(left: &Type, right: &Type) -> bool {
+    let scalar = |ty: &Type| matches!(ty, Type::Any | Type::Integer | Type::Float | Type::Decimal);
+    left == right || scalar(left) && right.is_numeric() || scalar(right) && left.is_numeric()
+}
+
+fn head(node: &Node) -> Option<(&str, &[Node])> {
+    match &node.kind {
+        NodeKind::Combination(items) => match &items[0].kind {
+            NodeKind::Atom(Expr::Symbol(name)) => Some((name.0.as_str(), &items[1..])),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_keyword(node: &Node) -> bool {
+    matches!(&node.kind, NodeKind::Atom(Expr::Symbol(name)) if name.starts_with(':'))
+}
+
+/// Capitalized like `Price` or `(Array Price)`, as the interpreter reads it
+fn is_annotation(node: &Node) -> bool {
+    match &node.kind {
+        NodeKind::Atom(Expr::Symbol(name)) => name.starts_with(|c: char| c.is_ascii_uppercase()),
+        NodeKind::Combination(items) => is_annotation(&items[0]),
+        _ => false,
+    }
+}
+
+/// "an Integer", "a Price"
+fn article(ty: &Type) -> String {
+    let text = ty.to_string();
+    let vowel = text.trim_start_matches('(').starts_with(['A', 'E', 'I', 'O', 'U']);
+    format!("{} {}", if vowel { "an" } else { "a" }, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_syntax;
+
+    fn errors(src: &str) -> Vec<String> {
+        check_types(&parse_syntax(src).expect("parses")).into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn reads_annotations() {
+        let node = &parse_syntax("(Fn (Array Price) (Tuple Timestamp (Series Float)) Bool)").unwrap()[0];
+        let ty = Type::from_node(node).unwrap();
+        assert_eq!(ty.to_string(), "(Fn (Array Price) (Tuple Timestamp (Series Float)) Bool)");
+        assert!(Type::Array(Box::new(Type::Price)).accepts(&Type::Array(Box::new(Type::Integer))));
+        assert!(!Type::Price.accepts(&Type::Duration));
+
+        assert_eq!(errors("(inputs (p (Array Prce)))"), ["unknown type `Prce`"]);
+    }
+
+    #[test]
+    fn combines_units() {
+        use Type::*;
+        let cases = [
+            ("+", Timestamp, Duration, Some(Timestamp)),
+            ("-", Timestamp, Timestamp, Some(Duration)),
+            ("*", Duration, Integer, Some(Duration)),
+            ("*", Price, Quantity, Some(Money)),
+            ("/", Money, Price, Some(Quantity)),
+            ("+", Price, Float, Some(Price)),
+            ("*", Decimal, Float, Some(Decimal)),
+            ("/", Integer, Decimal, Some(Decimal)),
+            ("*", Decimal, Quantity, Some(Quantity)),
+            ("/", Price, Price, Some(Float)),
+            ("+", Timestamp, Timestamp, None),
+            ("*", Price, Price, None),
+            ("*", Duration, Float, None),
+            ("+", Price, Quantity, None),
+        ];
+        for (op, left, right, expected) in cases {
+            assert_eq!(arithmetic_type(op, &left, &right), expected, "{} {} {}", left, op, right);
+        }
+        let src = "(inputs (size Quantity 10) (window Duration 1h))
+(set cost (* close size))
+(if (> cost time) (buy (+ time window)))";
+        let expected = ["`>` cannot compare Money with Timestamp", "`buy` expects Quantity here, found Timestamp"];
+        assert_eq!(errors(src), expected);
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let src = "\
+(inputs (period Duration 10) (size Integer 5) (offset 2d))
+(set stop (- close offset))
+(set entry (+ close 0.5))
+(set entry \"now\")
+(if (+ close 1) (buy size))
+(buy-limit size period)
+(set ok (* (/ high low) size))
+(if (> ok 1) (sell (get size)))";
+        let expected = [
+            "input `period` is declared Duration but defaults to Integer",
+            "cannot subtract Duration from Price",
+            "variable `entry` is Price but is set to String",
+            "`if` condition is Price, not Bool",
+            "`buy-limit` expects Price here, found Duration",
+        ];
+        assert_eq!(errors(src), expected);
+    }
+}
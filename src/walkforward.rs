@@ -0,0 +1,275 @@
+//! Walk-forward analysis.
+//!
+//! History is cut into rolling windows: inputs are optimized on an in-sample
+//! window and then traded, untouched, on the out-of-sample window that follows
+//! it. The out-of-sample runs are chained, each starting with the equity the
+//! previous one ended with, into a single stitched equity curve.
+//!
+//! Each out-of-sample run starts flat and cold on its own bars, and any position
+//! still open at its last bar is closed at that bar's close, so the stitched trade
+//! list accounts for every change in equity.
+
+use crate::ast::{Duration, Expr, Timestamp};
+use crate::bars::Bar;
+use crate::broker::{BrokerConfig, EquityPoint, Trade};
+use crate::engine::BarEngine;
+use crate::optimize::{optimize, OptimizationResult, OptimizeConfig, ParamSpace};
+use crate::report::{PerformanceReport, SECONDS_PER_YEAR};
+use serde::Serialize;
+use std::ops::Range;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct WalkForwardConfig {
+    pub in_sample: Duration,
+    pub out_of_sample: Duration,
+    /// How far each window advances; defaults to the out-of-sample length, and may not be
+    /// shorter, or the out-of-sample windows would overlap.
+    pub step: Option<Duration>,
+    pub optimize: OptimizeConfig,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkForwardWindow {
+    pub in_sample_start: Timestamp,
+    pub out_of_sample_start: Timestamp,
+    pub out_of_sample_end: Timestamp,
+    /// The winning in-sample candidate.
+    pub optimized: OptimizationResult,
+    pub out_of_sample: PerformanceReport,
+    /// Annualized out-of-sample net profit over annualized in-sample net profit.
+    pub efficiency: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkForwardReport {
+    pub windows: Vec<WalkForwardWindow>,
+    /// Over the stitched out-of-sample trades and equity curve.
+    pub report: PerformanceReport,
+    /// Same ratio as the per-window efficiency, over all windows combined.
+    pub efficiency: f64,
+    pub trades: Vec<Trade>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+pub fn walk_forward(
+    program: Arc<[Expr]>,
+    bars: &[Bar],
+    space: &ParamSpace,
+    config: &WalkForwardConfig,
+) -> Result<WalkForwardReport, String> {
+    let in_sample = config.in_sample.0;
+    let out_of_sample = config.out_of_sample.0;
+    let step = config.step.as_ref().map_or(out_of_sample, |s| s.0);
+    if in_sample <= chrono::Duration::zero() || out_of_sample <= chrono::Duration::zero() || step <= chrono::Duration::zero() {
+        return Err("walk-forward windows must have positive length".to_string());
+    }
+    if step < out_of_sample {
+        return Err("the walk-forward step may not be shorter than the out-of-sample window".to_string());
+    }
+    let (Some(first), Some(last)) = (bars.first(), bars.last()) else {
+        return Err("no bars".to_string());
+    };
+
+    let initial_cash = config.optimize.broker.initial_cash;
+    let mut equity = initial_cash;
+    let mut windows = Vec::new();
+    let mut trades = Vec::new();
+    let mut equity_curve = Vec::new();
+    let (mut is_profit, mut is_years, mut oos_profit, mut oos_years) = (0.0, 0.0, 0.0, 0.0);
+
+    // Windows stop where a start or split would be past the last representable time
+    let mut next = Some(first.timestamp.0);
+    while let Some(start) = next
+        && let Some(split) = start.checked_add_signed(in_sample)
+        && split <= last.timestamp.0
+    {
+        let end = split.checked_add_signed(out_of_sample).unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+        let (in_range, out_range) = (range(bars, start, split), range(bars, split, end));
+        next = start.checked_add_signed(step);
+        if in_range.is_empty() || out_range.is_empty() {
+            continue;
+        }
+        let in_bars: Arc<[Bar]> = bars[in_range.clone()].into();
+        let out_bars = &bars[out_range.clone()];
+
+        let optimized = optimize(program.clone(), in_bars, space, &config.optimize)?
+            .into_iter()
+            .next()
+            .ok_or("optimization produced no candidates")?;
+        let broker = BrokerConfig { initial_cash: equity, ..config.optimize.broker.clone() };
+        let mut engine = BarEngine::new(broker).with_inputs(optimized.inputs.clone());
+        engine.run(&program, out_bars)?;
+        engine.broker.flatten(out_bars.len() - 1, &out_bars[out_bars.len() - 1]);
+        let run_trades = engine.broker.trades().to_vec();
+        let run_curve = engine.broker.equity_curve().to_vec();
+        let run_report = PerformanceReport::new(equity, &run_trades, &run_curve);
+
+        let window_is_years = years(span(bars, in_range, split));
+        let window_oos_years = years(span(bars, out_range, end));
+        is_profit += optimized.report.net_profit;
+        is_years += window_is_years;
+        oos_profit += run_report.net_profit;
+        oos_years += window_oos_years;
+
+        equity = run_report.final_equity;
+        windows.push(WalkForwardWindow {
+            in_sample_start: Timestamp(start),
+            out_of_sample_start: Timestamp(split),
+            out_of_sample_end: Timestamp(end),
+            efficiency: efficiency(
+                optimized.report.net_profit,
+                window_is_years,
+                run_report.net_profit,
+                window_oos_years,
+            ),
+            optimized,
+            out_of_sample: run_report,
+        });
+        trades.extend(run_trades);
+        equity_curve.extend(run_curve);
+    }
+
+    if windows.is_empty() {
+        return Err("history is too short for a single walk-forward window".to_string());
+    }
+    Ok(WalkForwardReport {
+        report: PerformanceReport::new(initial_cash, &trades, &equity_curve),
+        efficiency: efficiency(is_profit, is_years, oos_profit, oos_years),
+        windows,
+        trades,
+        equity_curve,
+    })
+}
+
+/// Indices of the bars with `from <= timestamp < to`.
+fn range(bars: &[Bar], from: chrono::DateTime<chrono::Utc>, to: chrono::DateTime<chrono::Utc>) -> Range<usize> {
+    bars.partition_point(|b| b.timestamp.0 < from)..bars.partition_point(|b| b.timestamp.0 < to)
+}
+
+/// The time a non-empty window's bars actually cover: from its first bar until the next
+/// bar opens, no later than the window's `end`. At the end of history the last bar is
+/// taken to last as long as the one before it.
+fn span(bars: &[Bar], window: Range<usize>, end: chrono::DateTime<chrono::Utc>) -> chrono::Duration {
+    let first = bars[window.start].timestamp.0;
+    let last = window.end - 1;
+    let until = match bars.get(window.end) {
+        Some(next) => next.timestamp.0,
+        None if last > 0 => {
+            let gap = bars[last].timestamp.0 - bars[last - 1].timestamp.0;
+            bars[last].timestamp.0.checked_add_signed(gap).unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC)
+        }
+        None => bars[last].timestamp.0,
+    };
+    until.min(end) - first
+}
+
+fn years(d: chrono::Duration) -> f64 {
+    d.num_seconds() as f64 / SECONDS_PER_YEAR
+}
+
+/// Zero when the in-sample result was not profitable, since the ratio means nothing then.
+fn efficiency(is_profit: f64, is_years: f64, oos_profit: f64, oos_years: f64) -> f64 {
+    if is_profit <= 0.0 || is_years <= 0.0 || oos_years <= 0.0 {
+        return 0.0;
+    }
+    (oos_profit / oos_years) / (is_profit / is_years)
+}
+
+impl WalkForwardReport {
+    /// One line per window, for the terminal.
+    pub fn format_windows(&self) -> String {
+        let mut out = format!(
+            "{:>3}  {:<10}  {:<10}  {:<10}  {:>12}  {:>12}  {:>8}  inputs\n",
+            "#", "IS start", "OOS start", "OOS end", "IS net", "OOS net", "WFE"
+        );
+        for (i, w) in self.windows.iter().enumerate() {
+            let inputs: Vec<String> = w.optimized.inputs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            out.push_str(&format!(
+                "{:>3}  {:<10}  {:<10}  {:<10}  {:>12.2}  {:>12.2}  {:>8.2}  {}\n",
+                i + 1,
+                w.in_sample_start.0.format("%Y-%m-%d"),
+                w.out_of_sample_start.0.format("%Y-%m-%d"),
+                w.out_of_sample_end.0.format("%Y-%m-%d"),
+                w.optimized.report.net_profit,
+                w.out_of_sample.net_profit,
+                w.efficiency,
+                inputs.join(" ")
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bars::parse_timestamp;
+    use crate::parser::parse_program;
+    use std::str::FromStr;
+
+    fn bars(days: usize) -> Vec<Bar> {
+        let start = parse_timestamp("2024-01-01").unwrap().0;
+        (0..days)
+            .map(|i| {
+                let open = 100.0 + (i as f64 * 0.7).sin() * 5.0;
+                let close = open + (i as f64 * 1.3).cos();
+                let ts = Timestamp(start + chrono::Duration::days(i as i64));
+                Bar::new(ts, open, open.max(close) + 0.5, open.min(close) - 0.5, close, 0.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stitches_out_of_sample_windows() {
+        let program: Arc<[Expr]> = parse_program("(inputs (size 1)) (if (> close open) (buy size) (sell size))")
+            .unwrap()
+            .into_iter()
+            .map(|f| f.value)
+            .collect();
+        let mut space = ParamSpace::new();
+        space.insert("size", vec![Expr::Integer(1), Expr::Integer(5)]);
+        let config = WalkForwardConfig {
+            in_sample: Duration::from_str("20d").unwrap(),
+            out_of_sample: Duration::from_str("10d").unwrap(),
+            step: None,
+            optimize: OptimizeConfig { workers: 2, ..OptimizeConfig::default() },
+        };
+        let bars = bars(60);
+        let report = walk_forward(program, &bars, &space, &config).unwrap();
+
+        // Windows start on days 0, 10, 20 and 30; a fifth would have no out-of-sample bars.
+        assert_eq!(report.windows.len(), 4);
+        assert_eq!(report.equity_curve.len(), 40);
+        assert_eq!(report.equity_curve[0].timestamp, bars[20].timestamp);
+        let chained = report.windows.iter().fold(100_000.0, |equity, w| {
+            assert_eq!(w.out_of_sample.initial_capital, equity);
+            w.out_of_sample.final_equity
+        });
+        assert_eq!(chained, report.report.final_equity);
+
+        // Every window ends flat, so the trades add up to the change in equity
+        let pnl: f64 = report.trades.iter().map(|t| t.pnl).sum();
+        assert!((100_000.0 + pnl - report.report.final_equity).abs() < 1e-6);
+        assert!(report.equity_curve.iter().all(|p| p.position == 0 || p.timestamp != bars[29].timestamp));
+
+        // A step shorter than the out-of-sample window would trade some bars twice
+        let overlapping = WalkForwardConfig { step: Some(Duration::from_str("5d").unwrap()), ..config };
+        assert!(walk_forward(Arc::from([]), &bars, &space, &overlapping).is_err());
+    }
+
+    #[test]
+    fn rejects_short_history() {
+        let program: Arc<[Expr]> = Arc::new([]);
+        let config = WalkForwardConfig {
+            in_sample: Duration::from_str("30d").unwrap(),
+            out_of_sample: Duration::from_str("10d").unwrap(),
+            step: None,
+            optimize: OptimizeConfig::default(),
+        };
+        assert!(walk_forward(program.clone(), &bars(20), &ParamSpace::new(), &config).is_err());
+        // Windows past the end of time are too long, not a crash
+        let endless = WalkForwardConfig { in_sample: Duration::from_str("14000000w").unwrap(), ..config };
+        assert!(walk_forward(program, &bars(20), &ParamSpace::new(), &endless).is_err());
+    }
+}